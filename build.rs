@@ -0,0 +1,57 @@
+use std::env;
+use std::fs;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+// with the "embedded" feature, every file under shaders/ and assets/ is packed into
+// the executable. the generated table is included by src/assets/mod.rs
+
+/// generated data, we do not want it in the binary
+const SKIP: &'static [&'static str] = &["assets/cache"];
+
+fn collect(dir: &Path, res: &mut Vec<PathBuf>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.filter_map(|e| e.ok()) {
+        let path = entry.path();
+        if SKIP.iter().any(|skip| path == Path::new(skip)) {
+            continue;
+        }
+        if path.is_dir() {
+            collect(&path, res);
+        } else {
+            res.push(path);
+        }
+    }
+}
+
+fn main() {
+    let out = Path::new(&env::var("OUT_DIR").unwrap()).join("embedded_assets.rs");
+    let root = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
+
+    let mut files = Vec::new();
+    if env::var("CARGO_FEATURE_EMBEDDED").is_ok() {
+        collect(Path::new("shaders"), &mut files);
+        collect(Path::new("assets"), &mut files);
+        files.sort();
+    }
+
+    let mut code = String::new();
+    code.push_str("pub static ASSETS: &'static [(&'static str, &'static [u8])] = &[\n");
+    for file in &files {
+        let name: Vec<String> = file.components()
+            .map(|c| c.as_os_str().to_string_lossy().into_owned())
+            .collect();
+        code.push_str(&format!("    ({:?}, include_bytes!({:?})),\n",
+                               name.join("/"),
+                               root.join(file)));
+    }
+    code.push_str("];\n");
+
+    fs::File::create(&out).and_then(|mut f| f.write_all(code.as_bytes())).unwrap();
+
+    println!("cargo:rerun-if-changed=shaders");
+    println!("cargo:rerun-if-changed=assets");
+}
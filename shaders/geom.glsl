@@ -15,7 +15,6 @@ uniform sampler2D shadow_texture;
 uniform uint atlas_side;
 uniform vec3 sun_pos;
 uniform vec3 cam_pos;
-uniform bool shadows_enabled;
 
 // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
 
@@ -70,7 +69,6 @@ uniform sampler2D shadow_texture;
 uniform uint atlas_side;
 uniform vec3 sun_pos;
 uniform vec3 cam_pos;
-uniform bool shadows_enabled;
 
 // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
 
@@ -143,17 +141,14 @@ void main()
 
     vec3 lighting;
 
-    // Calculate shadow
-    if (shadows_enabled)
-    {
-        float bias = max(0.05 * (1.0 - dot(normal, lightDir)), 0.005);  
-        float shadow = ShadowCalculation(frag_lightSpace_coords, bias);       
-        lighting = (ambient + (1-shadow) * (diffuse + specular)) * color;    
-    }
-    else
-    {
-        lighting = color;    
-    }
+    // Calculate shadow, the SHADOWS define is set by the shadows toggle
+#ifdef SHADOWS
+    float bias = max(0.05 * (1.0 - dot(normal, lightDir)), 0.005);  
+    float shadow = ShadowCalculation(frag_lightSpace_coords, bias);       
+    lighting = (ambient + (1-shadow) * (diffuse + specular)) * color;    
+#else
+    lighting = color;    
+#endif
     
     frag_color = vec4(lighting, 1.0f);
 }
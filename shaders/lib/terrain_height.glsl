@@ -0,0 +1,19 @@
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+// height decoding and projection helpers shared by the terrain programs
+//  expects to be included after the declaration of:
+//    uniform mat4 pvm;
+//    uniform sampler2D height_map;
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+// the red channel stores the height in 256 steps
+float terrain_height(vec2 xz){
+    return float(int(texelFetch(height_map, ivec2(xz), 0).r *256));
+}
+
+// place the vertex at the terrain height and project it into normalized device coordinates
+vec4 project(vec4 vertex){
+    vertex.y = terrain_height(vertex.xz);
+    vec4 result = pvm * vertex;
+    result /= result.w;
+    return result;
+}
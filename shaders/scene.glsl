@@ -0,0 +1,67 @@
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+// <- COMMON ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+#version 330 core
+
+uniform mat4 pvm;
+uniform mat4 model;
+uniform sampler2D height_map;
+
+#include "lib/terrain_height.glsl"
+
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+// <- VERTEX ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~ 
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+// the origin of the scene, in terrain coordinates
+uniform vec2 place;
+uniform float scale;
+
+            // ------- the primitive
+in vec3 position;
+in vec3 normal;
+in vec2 tex_coord;
+            // ------- from here on are instanciated
+in mat4 instance_model;
+
+out vec3 v_normal;
+out vec2 v_tex_coord;
+
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+void main() {
+    // each instance stands on the ground under its origin
+    vec3 local = (instance_model * vec4(position, 1.0)).xyz * scale;
+    vec2 origin = place + instance_model[3].xz * scale;
+    vec3 ground = vec3(place.x, terrain_height(origin), place.y);
+    gl_Position = pvm * vec4(ground + local, 1.0);
+
+    v_normal = mat3(model) * mat3(instance_model) * normal;
+    v_tex_coord = tex_coord;
+}
+
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+// <- FRAGMENT ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+uniform vec3 sun_pos;
+uniform vec4 base_color;
+uniform sampler2D base_color_map;
+// 0 unless the material is masked
+uniform float alpha_cutoff;
+
+in vec3 v_normal;
+in vec2 v_tex_coord;
+
+out vec4 frag_color;
+
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+void main() {
+    vec4 color = texture(base_color_map, v_tex_coord) * base_color;
+    if (color.a < alpha_cutoff) {
+        discard;
+    }
+    float diff = max(dot(normalize(sun_pos), normalize(v_normal)), 0.0);
+    frag_color = vec4((0.15 + diff) * color.rgb, 1.0);
+}
@@ -1,9 +1,18 @@
 // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
-// <- VERTEX ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~ 
+// <- COMMON ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
 // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
-
 #version 410 core
 
+uniform mat4 pvm;
+uniform sampler2D height_map;
+uniform uvec2 height_size;
+
+#include "lib/terrain_height.glsl"
+
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+// <- VERTEX ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~ 
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
 // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
 
             // ------- the static geometry. just a quad of side 64
@@ -24,7 +33,6 @@ void main() {
 // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
 // <- TESSELLATION_CONTROL ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
 // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
-#version 410 core
 
 layout (vertices = 4) out;
 
@@ -32,10 +40,7 @@ layout (vertices = 4) out;
 
 uniform mat4 model;
 uniform mat4 view;
-uniform mat4 pvm;
 
-uniform sampler2D height_map;
-uniform uvec2 height_size;
 uniform vec3 cam_pos;
 uniform uvec2 screen_size;
 
@@ -44,13 +49,6 @@ in uint vs_mintess[];
 
 // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
 
-vec4 project(vec4 vertex){
-    vertex.y = int(texelFetch(height_map, ivec2(vertex.xz), 0).r *256);
-    vec4 result = pvm * vertex;
-    result /= result.w;
-    return result;
-}
-
 bvec2 or(bvec2 a, bvec2 b){
     return bvec2(a.x || b.x, a.y || b.y);
 }
@@ -66,7 +64,7 @@ bool offscreen(vec4 vertex){
 }
 
 float distance_to_camera(vec4 vertex, vec3 camera){
-    vertex.y = int(texelFetch(height_map, ivec2(vertex.xz), 0).r *256);
+    vertex.y = terrain_height(vertex.xz);
     vec4 tmp = model * vertex;
 	return clamp(distance(vertex.xyz, camera.xyz) / 1500.0, 0.0, 1.0);
 }
@@ -118,16 +116,12 @@ void main()
 // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
 // <- TESSELLATION_EVALUATION ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
 // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
-#version 410 core
 
 // am I rendereing everithing ccw?  it seems that I do
 layout(quads, fractional_even_spacing, ccw) in;
 
 // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
 
-uniform sampler2D height_map;
-uniform uvec2 height_size;
-
 // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
 
 out float te_height; 
@@ -143,20 +137,17 @@ void main() {
     vec4 b = mix(gl_in[3].gl_Position, gl_in[2].gl_Position, u);
     vec4 position = mix(a, b, v);
 
-    position.y = int(texelFetch(height_map, ivec2(position.xz), 0).r *256);
+    position.y = terrain_height(position.xz);
     gl_Position = vec4(position.xyz,1.0);
 }
 
 // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
 // <- GEOMETRY ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
 // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
-#version 410 core
 
 layout(triangles) in;
 layout(triangle_strip, max_vertices=7) out;
 
-uniform mat4 pvm;
-
 // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
 
 flat out vec3 gs_Normal;
@@ -294,10 +285,7 @@ void main() {
 // <- FRAGMENT ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
 // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
 
-#version 410 core
-
 uniform sampler2D color_map;
-uniform uvec2 height_size;
 
 // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
 in vec2 gs_TextureCoordinates; 
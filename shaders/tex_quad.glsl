@@ -19,29 +19,33 @@ void main() {
 
 #version 140
 uniform sampler2D quad_texture;
-uniform bool is_depth;
+// 0: as it is, 1: depth buffer, linearised, 2: red channel in false colour
+uniform int mode;
+uniform float near;
+uniform float far;
 
 smooth in vec2 coords;
 out vec4 frag_color;
 
+// blue, cyan, green, yellow, red
+vec3 false_color(float v) {
+    v = clamp(v, 0.0, 1.0);
+    return clamp(vec3(v * 4.0 - 2.0, 2.0 - abs(v * 4.0 - 2.0), 2.0 - v * 4.0), 0.0, 1.0);
+}
+
 void main() {
-    if (is_depth){
-
-        // 1500 range?
-        const float far = 1500;
-        const float near = 50;
-
-        float z = texture(quad_texture, coords).r;
-        float v = (2.0 * near) / (far + near - z * (far - near));  // convert to linear values 
-         
-        frag_color = vec4(
-                clamp(v, 0.0, 0.33)*3,
-                (clamp(v, 0.33, 0.66)-0.33)*3,
-                (clamp(v, 0.66, 1.0)-0.66)*3.0,
-                1.0);
+    vec4 texel = texture(quad_texture, coords);
+    if (mode == 1) {
+        // distance from the eye, near is white
+        float z = texel.r * 2.0 - 1.0;
+        float eye = (2.0 * near * far) / (far + near - z * (far - near));
+        float v = 1.0 - (eye - near) / (far - near);
+        frag_color = vec4(vec3(v), 1.0);
+    }
+    else if (mode == 2) {
+        frag_color = vec4(false_color(texel.r), 1.0);
     }
-    else
-    {
-        frag_color = texture(quad_texture, coords);
+    else {
+        frag_color = texel;
     }
 }
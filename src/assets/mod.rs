@@ -0,0 +1,130 @@
+use std::borrow::Cow;
+use std::env;
+use std::path::{Path, PathBuf};
+
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+//  Asset source,
+//  shaders and assets are named by their path in the repo, as in
+//  "shaders/geom.glsl" or "assets/D18.png".
+//  - dev mode: files are read from disk, wherever the repo is, and shaders
+//    hot reload.
+//  - embedded mode (cargo feature "embedded"): build.rs packs the shaders and
+//    assets folders into the executable, so it can be shipped alone.
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+#[derive(Debug)]
+pub enum AssetError {
+    NotFound(String),
+    InvalidData(String),
+}
+
+#[cfg(feature = "embedded")]
+mod embedded {
+    include!(concat!(env!("OUT_DIR"), "/embedded_assets.rs"));
+}
+
+/// files can be edited while the program runs, only in dev mode
+pub fn hot_reload() -> bool {
+    !cfg!(feature = "embedded")
+}
+
+/// folder that contains shaders and assets, the first of: $RQUARFS_ROOT, the working
+/// directory, the executable folder or its parents, and the sources the binary was built from.
+#[cfg(not(feature = "embedded"))]
+fn root() -> PathBuf {
+    use std::fs;
+    lazy_static! {
+        static ref ROOT: PathBuf = {
+            let mut candidates = Vec::new();
+            if let Some(dir) = env::var_os("RQUARFS_ROOT") {
+                candidates.push(PathBuf::from(dir));
+            }
+            if let Ok(dir) = env::current_dir() {
+                candidates.push(dir);
+            }
+            if let Ok(exe) = env::current_exe() {
+                candidates.extend(exe.ancestors().skip(1).map(|d| d.to_path_buf()));
+            }
+            candidates.push(PathBuf::from(env!("CARGO_MANIFEST_DIR")));
+
+            candidates.into_iter()
+                .filter_map(|dir| fs::canonicalize(dir).ok())
+                .find(|dir| dir.join("shaders").is_dir())
+                .unwrap_or(PathBuf::from("."))
+        };
+    }
+    ROOT.clone()
+}
+
+/// where an asset lives, a real path in dev mode.
+#[cfg(not(feature = "embedded"))]
+pub fn locate(name: &str) -> PathBuf {
+    root().join(name)
+}
+
+/// in embedded mode it is the asset name itself, there is nothing on disk.
+#[cfg(feature = "embedded")]
+pub fn locate(name: &str) -> PathBuf {
+    PathBuf::from(name)
+}
+
+/// writable folder for generated data, like the program binaries
+pub fn cache_dir() -> PathBuf {
+    if cfg!(feature = "embedded") {
+        env::temp_dir().join("rquarfs").join("cache")
+    } else {
+        locate("assets/cache")
+    }
+}
+
+#[cfg(not(feature = "embedded"))]
+pub fn read(path: &Path) -> Result<Cow<'static, [u8]>, AssetError> {
+    use std::fs;
+    use std::io::prelude::*;
+    let mut data = Vec::new();
+    fs::File::open(path)
+        .and_then(|mut f| f.read_to_end(&mut data))
+        .map_err(|_| AssetError::NotFound(format!("{}", path.display())))?;
+    Ok(Cow::Owned(data))
+}
+
+#[cfg(feature = "embedded")]
+pub fn read(path: &Path) -> Result<Cow<'static, [u8]>, AssetError> {
+    // names always use / no matter the platform
+    let name: Vec<String> = path.components()
+        .map(|c| c.as_os_str().to_string_lossy().into_owned())
+        .collect();
+    let name = name.join("/");
+    embedded::ASSETS
+        .iter()
+        .find(|&&(n, _)| n == name)
+        .map(|&(_, data)| Cow::Borrowed(data))
+        .ok_or(AssetError::NotFound(name))
+}
+
+pub fn read_string(path: &Path) -> Result<String, AssetError> {
+    let data = read(path)?;
+    String::from_utf8(data.into_owned())
+        .map_err(|_| AssetError::InvalidData(format!("{} is not utf8", path.display())))
+}
+
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+//  Tests
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+#[cfg(test)]
+mod tests {
+
+    use super::{locate, read, read_string};
+
+    #[test]
+    fn read_assets() {
+        let shader = read_string(&locate("shaders/test.glsl")).expect("shader");
+        assert!(shader.contains("// <- VERTEX"));
+
+        let image = read(&locate("assets/small_flat.png")).expect("image");
+        assert!(image.starts_with(b"\x89PNG"));
+
+        assert!(read(&locate("assets/nonsense.png")).is_err());
+    }
+}
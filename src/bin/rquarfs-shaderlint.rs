@@ -0,0 +1,465 @@
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+//  Shader lint,
+//  runs the shader packs through the same parser the renderer uses, but without
+//  a GL context, so shader breakages are caught in machines with no GPU.
+//
+//  usage: rquarfs-shaderlint [pack.glsl ...]
+//  without arguments every shaders/*.glsl file is checked.
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+extern crate glob;
+extern crate glsl;
+extern crate regex;
+#[macro_use]
+extern crate lazy_static;
+
+#[allow(dead_code)]
+#[path = "../renderer/shader_pack.rs"]
+mod shader_pack;
+
+use glsl::parser::Parse;
+use glsl::syntax::{Declaration, ExternalDeclaration, StorageQualifier, TranslationUnit,
+                   TypeQualifier, TypeQualifierSpec};
+use regex::{Captures, Regex};
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path;
+use std::process;
+
+use shader_pack::{Defines, ParseState, ShaderPack, ShaderParseError, display_path, stage_name};
+
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+//  preprocessor,
+//  the glsl parser does not expand macros and only accepts directives between
+//  declarations. we do the bare minimum: object like macros and #if blocks.
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+lazy_static! {
+    static ref DIRECTIVE_RE: Regex = Regex::new(r"^\s*#\s*(\w+)\s*(.*)$").unwrap();
+    static ref DEFINE_RE: Regex = Regex::new(r"^(\w+)(\()?\s*(.*)$").unwrap();
+    static ref IDENT_RE: Regex = Regex::new(r"\b[A-Za-z_]\w*\b").unwrap();
+}
+
+/// #if conditions, anything we do not understand is taken as true
+fn eval_condition(arg: &str, macros: &BTreeMap<String, String>) -> bool {
+    let arg = arg.trim();
+    if arg.starts_with('!') {
+        return !eval_condition(&arg[1..], macros);
+    }
+    if arg.starts_with("defined") {
+        let name = arg["defined".len()..].trim().trim_matches(|c| c == '(' || c == ')').trim();
+        return macros.contains_key(name);
+    }
+    let value = macros.get(arg).map_or(arg, |v| v.as_str());
+    value.parse::<i64>().map(|v| v != 0).unwrap_or(true)
+}
+
+fn expand_macros(line: &str, macros: &BTreeMap<String, String>) -> String {
+    let mut res = line.to_string();
+    // macros may expand to other macros, but not forever
+    for _ in 0..8 {
+        let expanded = IDENT_RE.replace_all(&res, |cap: &Captures| {
+                match macros.get(&cap[0]) {
+                    Some(value) => value.clone(),
+                    None => cap[0].to_string(),
+                }
+            })
+            .into_owned();
+        if expanded == res {
+            break;
+        }
+        res = expanded;
+    }
+    res
+}
+
+/// directives are replaced by empty lines, so the line count does not change.
+/// the defines of the permutation are already in the code, right after the #version
+fn preprocess(code: &str) -> String {
+    let mut macros: BTreeMap<String, String> = BTreeMap::new();
+
+    // for each #if level: active, some branch taken, parent active
+    let mut stack: Vec<(bool, bool, bool)> = Vec::new();
+    let mut res = String::new();
+
+    for line in code.lines() {
+        let active = stack.last().map_or(true, |&(active, _, _)| active);
+
+        if let Some(cap) = DIRECTIVE_RE.captures(line) {
+            let arg = cap[2].trim();
+            match &cap[1] {
+                "ifdef" | "ifndef" | "if" => {
+                    let cond = match &cap[1] {
+                        "ifdef" => macros.contains_key(arg),
+                        "ifndef" => !macros.contains_key(arg),
+                        _ => eval_condition(arg, &macros),
+                    };
+                    stack.push((active && cond, cond, active));
+                }
+                "elif" => {
+                    if let Some((_, taken, parent)) = stack.pop() {
+                        let cond = !taken && eval_condition(arg, &macros);
+                        stack.push((parent && cond, taken || cond, parent));
+                    }
+                }
+                "else" => {
+                    if let Some((_, taken, parent)) = stack.pop() {
+                        stack.push((parent && !taken, true, parent));
+                    }
+                }
+                "endif" => {
+                    stack.pop();
+                }
+                "define" if active => {
+                    if let Some(def) = DEFINE_RE.captures(arg) {
+                        // function like macros are not supported
+                        if def.get(2).is_none() {
+                            macros.insert(def[1].to_string(), def[3].trim().to_string());
+                        }
+                    }
+                }
+                "undef" if active => {
+                    macros.remove(arg);
+                }
+                _ => {}
+            }
+            res.push_str("\n");
+            continue;
+        }
+
+        if active {
+            res.push_str(&expand_macros(line, &macros));
+        }
+        res.push_str("\n");
+    }
+    res
+}
+
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+//  stage interfaces
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Direction {
+    In,
+    Out,
+}
+
+/// an input or output variable of a stage
+#[derive(Clone, Debug)]
+struct Varying {
+    name: String,
+    ty: String,
+    array: bool,
+    patch: bool,
+}
+
+#[derive(Debug, Default)]
+struct Interface {
+    inputs: Vec<Varying>,
+    outputs: Vec<Varying>,
+}
+
+impl Interface {
+    fn push(&mut self, dir: Direction, var: Varying) {
+        match dir {
+            Direction::In => self.inputs.push(var),
+            Direction::Out => self.outputs.push(var),
+        }
+    }
+}
+
+/// direction and whether it is a per patch variable
+fn storage_of(qualifier: &TypeQualifier) -> Option<(Direction, bool)> {
+    let mut dir = None;
+    let mut patch = false;
+    for spec in &qualifier.qualifiers.0 {
+        match *spec {
+            TypeQualifierSpec::Storage(StorageQualifier::In) => dir = Some(Direction::In),
+            TypeQualifierSpec::Storage(StorageQualifier::Out) => dir = Some(Direction::Out),
+            TypeQualifierSpec::Storage(StorageQualifier::Patch) => patch = true,
+            _ => {}
+        }
+    }
+    dir.map(|dir| (dir, patch))
+}
+
+fn interface(unit: &TranslationUnit) -> Interface {
+    let mut res = Interface::default();
+    for decl in &(unit.0).0 {
+        match *decl {
+            ExternalDeclaration::Declaration(Declaration::InitDeclaratorList(ref list)) => {
+                let head = &list.head;
+                let (dir, patch) = match head.ty.qualifier.as_ref().and_then(storage_of) {
+                    Some(storage) => storage,
+                    None => continue,
+                };
+                let ty = format!("{:?}", head.ty.ty.ty).to_lowercase();
+                let type_array = head.ty.ty.array_specifier.is_some();
+
+                if let Some(ref name) = head.name {
+                    res.push(dir,
+                             Varying {
+                                 name: name.0.clone(),
+                                 ty: ty.clone(),
+                                 array: type_array || head.array_specifier.is_some(),
+                                 patch: patch,
+                             });
+                }
+                for other in &list.tail {
+                    res.push(dir,
+                             Varying {
+                                 name: other.ident.ident.0.clone(),
+                                 ty: ty.clone(),
+                                 array: type_array || other.ident.array_spec.is_some(),
+                                 patch: patch,
+                             });
+                }
+            }
+            ExternalDeclaration::Declaration(Declaration::Block(ref block)) => {
+                if let Some((dir, patch)) = storage_of(&block.qualifier) {
+                    res.push(dir,
+                             Varying {
+                                 name: block.name.0.clone(),
+                                 ty: "block".to_string(),
+                                 array: block.identifier
+                                     .as_ref()
+                                     .map_or(false, |id| id.array_spec.is_some()),
+                                 patch: patch,
+                             });
+                }
+            }
+            _ => {}
+        }
+    }
+    res
+}
+
+/// tessellation and geometry stages see their inputs as arrays, one per vertex
+fn arrayed_input(stage: ParseState) -> bool {
+    match stage {
+        ParseState::TessC | ParseState::TessE | ParseState::Geom => true,
+        _ => false,
+    }
+}
+
+/// outputs of one stage against the inputs of the next one
+fn check_interfaces(stages: &[(ParseState, Interface)]) -> Vec<String> {
+    let mut problems = Vec::new();
+    for pair in stages.windows(2) {
+        let (prev, outputs) = (pair[0].0, &pair[0].1.outputs);
+        let (next, inputs) = (pair[1].0, &pair[1].1.inputs);
+        let prev_name = stage_name(prev as usize);
+        let next_name = stage_name(next as usize);
+
+        for input in inputs.iter().filter(|i| !i.name.starts_with("gl_")) {
+            let output = match outputs.iter().find(|o| o.name == input.name) {
+                Some(output) => output,
+                None => {
+                    problems.push(format!("{} input {} is not written by the {} stage",
+                                          next_name,
+                                          input.name,
+                                          prev_name));
+                    continue;
+                }
+            };
+
+            if output.ty != input.ty {
+                problems.push(format!("{} is {} in the {} stage but {} in the {} stage",
+                                      input.name,
+                                      output.ty,
+                                      prev_name,
+                                      input.ty,
+                                      next_name));
+            }
+            if arrayed_input(next) && !input.patch && !input.array {
+                problems.push(format!("{} input {} must be an array, as in {}[]",
+                                      next_name,
+                                      input.name,
+                                      input.name));
+            }
+            if prev == ParseState::TessC && !output.patch && !output.array {
+                problems.push(format!("{} output {} must be an array, as in {}[]",
+                                      prev_name,
+                                      output.name,
+                                      output.name));
+            }
+        }
+    }
+    problems
+}
+
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+//  lint
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+/// the version line is always the first one of a stage, if any.
+/// before 150 there are no profiles, after it core is the default one
+fn version_of(code: &str) -> String {
+    let line = match code.lines().next() {
+        Some(line) if line.trim().starts_with("#version") => line,
+        _ => return "none".to_string(),
+    };
+    let mut words: Vec<&str> = line.split_whitespace().skip(1).collect();
+    if words.len() == 1 && words[0].parse::<u32>().map(|n| n >= 150).unwrap_or(false) {
+        words.push("core");
+    }
+    words.join(" ")
+}
+
+fn check_versions(stages: &[(ParseState, &str)]) -> Vec<String> {
+    let versions: Vec<(ParseState, String)> =
+        stages.iter().map(|&(kind, code)| (kind, version_of(code))).collect();
+    if versions.iter().all(|&(_, ref v)| *v == versions[0].1) {
+        return Vec::new();
+    }
+    let list: Vec<String> = versions.iter()
+        .map(|&(kind, ref v)| format!("{} {}", stage_name(kind as usize), v))
+        .collect();
+    vec![format!("mismatched #version across stages: {}", list.join(", "))]
+}
+
+fn lint_pack(pack: &ShaderPack) -> Vec<String> {
+    let stages = pack.stages();
+    let mut problems = check_versions(&stages);
+
+    let mut interfaces = Vec::new();
+    for &(kind, code) in &stages {
+        match TranslationUnit::parse(preprocess(code)) {
+            Ok(unit) => interfaces.push((kind, interface(&unit))),
+            Err(err) => {
+                problems.push(format!("{} stage does not parse: {}",
+                                      stage_name(kind as usize),
+                                      err))
+            }
+        }
+    }
+    // with a stage missing we would report nonsense
+    if interfaces.len() == stages.len() {
+        problems.extend(check_interfaces(&interfaces));
+    }
+    problems
+}
+
+fn lint_file(path: &path::PathBuf) -> Vec<String> {
+    match ShaderPack::new(path, &Defines::new()) {
+        Ok(pack) => lint_pack(&pack),
+        Err(ShaderParseError::MissingShader) => {
+            vec!["missing vertex or fragment stage".to_string()]
+        }
+        Err(ShaderParseError::SyntaxError(msg)) => vec![msg],
+        Err(ShaderParseError::IncludeNotFound(name)) => vec![format!("include not found: {}", name)],
+        Err(ShaderParseError::IncludeCycle(chain)) => vec![format!("include cycle: {}", chain)],
+        Err(err) => vec![format!("{:?}", err)],
+    }
+}
+
+fn main() {
+    let mut files: Vec<path::PathBuf> = env::args().skip(1).map(path::PathBuf::from).collect();
+    if files.is_empty() {
+        files = glob::glob("shaders/*.glsl")
+            .expect("glob pattern")
+            .filter_map(|f| f.ok())
+            .collect();
+    }
+
+    let mut count = 0;
+    for file in &files {
+        let path = match fs::canonicalize(file) {
+            Ok(path) => path,
+            Err(_) => {
+                println!("{}: file not found", file.display());
+                count += 1;
+                continue;
+            }
+        };
+        let problems = lint_file(&path);
+        for problem in &problems {
+            println!("{}: {}", display_path(&path), problem);
+        }
+        count += problems.len();
+    }
+
+    println!("{} shader packs checked, {} problems", files.len(), count);
+    if count > 0 {
+        process::exit(1);
+    }
+}
+
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+//  Tests
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+#[cfg(test)]
+mod tests {
+
+    use super::{lint_pack, preprocess};
+    use shader_pack::{Defines, ShaderPack};
+
+    use std::env;
+
+    fn lint(code: &str) -> Vec<String> {
+        let pack = ShaderPack::from_source(code, &env::temp_dir(), &Defines::new()).expect("parse");
+        lint_pack(&pack)
+    }
+
+    #[test]
+    fn macros_and_conditionals() {
+        let code = "#define SHADOWS 1\n\
+                    #ifdef SHADOWS\nfloat a;\n#else\nfloat b;\n#endif\n\
+                    #if !defined(DEBUG_LOD)\nfloat c;\n#endif\n\
+                    #define ID x\nID = 1;\n";
+        let lines: Vec<String> = preprocess(code).lines().map(|l| l.to_string()).collect();
+        assert_eq!(lines,
+                   vec!["", "", "float a;", "", "", "", "", "float c;", "", "", "x = 1;"]);
+    }
+
+    #[test]
+    fn clean_pack() {
+        let code = "// <- COMMON\n\
+                    #version 410 core\n\
+                    // <- VERTEX\n\
+                    out uint vs_mintess;\n\
+                    void main(){ vs_mintess = 0u; }\n\
+                    // <- TESSELLATION_CONTROL\n\
+                    layout (vertices = 4) out;\n\
+                    in uint vs_mintess[];\n\
+                    void main(){}\n\
+                    // <- TESSELLATION_EVALUATION\n\
+                    layout(quads, fractional_even_spacing, ccw) in;\n\
+                    out float te_height;\n\
+                    void main(){ te_height = 0.0; }\n\
+                    // <- FRAGMENT\n\
+                    in float te_height;\n\
+                    out vec4 color;\n\
+                    void main(){ color = vec4(te_height); }\n";
+        assert!(lint(code).is_empty());
+    }
+
+    #[test]
+    fn interface_mismatch() {
+        let code = "// <- VERTEX\n\
+                    #version 410 core\n\
+                    out uint vs_mintess;\n\
+                    out vec2 uv;\n\
+                    void main(){}\n\
+                    // <- TESSELLATION_CONTROL\n\
+                    #version 410 core\n\
+                    layout (vertices = 4) out;\n\
+                    in uint vs_mintess;\n\
+                    in vec3 uv[];\n\
+                    in float missing[];\n\
+                    void main(){}\n\
+                    // <- FRAGMENT\n\
+                    #version 330\n\
+                    void main(){}\n";
+        let problems = lint(code);
+        assert_eq!(problems.len(), 4, "{:?}", problems);
+        assert!(problems[0].contains("mismatched #version"));
+        assert!(problems[1].contains("vs_mintess[]"));
+        assert!(problems[2].contains("uv is vec2"));
+        assert!(problems[3].contains("missing"));
+    }
+}
@@ -0,0 +1,420 @@
+use glutin::{ElementState, MouseButton, MouseScrollDelta, VirtualKeyCode, WindowEvent};
+
+use assets;
+
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+//  Input mapping,
+//  window events are turned into named actions (buttons) and axes (values),
+//  the code asks for "toggle_run" and never for the space bar.
+//  bindings are data, one per line:
+//
+//     action <name> = key <VirtualKeyCode> | mouse <Left|Right|Middle|number>
+//     axis <name> = <source> [* scale]
+//
+//  axis sources are mouse_x, mouse_y (cursor motion in pixels), scroll_x,
+//  scroll_y (wheel lines) and "keys <positive> <negative>" (+1, -1 or 0 while held)
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+#[derive(Debug)]
+pub struct InputError {
+    pub line: usize,
+    pub message: String,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Binding {
+    Key(VirtualKeyCode),
+    Mouse(MouseButton),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum AxisSource {
+    MouseX,
+    MouseY,
+    ScrollX,
+    ScrollY,
+    Keys(Binding, Binding),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ActionState {
+    Idle,
+    /// went down this frame
+    Pressed,
+    /// down since an earlier frame
+    Held,
+    /// went up this frame
+    Released,
+}
+
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+macro_rules! key_names {
+    ($name:expr, $($key:ident),*) => {
+        match $name {
+            $(stringify!($key) => Some(VirtualKeyCode::$key),)*
+            _ => None,
+        }
+    }
+}
+
+fn parse_key(name: &str) -> Option<VirtualKeyCode> {
+    key_names!(name,
+               A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z,
+               Key0, Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9,
+               Numpad0, Numpad1, Numpad2, Numpad3, Numpad4,
+               Numpad5, Numpad6, Numpad7, Numpad8, Numpad9,
+               F1, F2, F3, F4, F5, F6, F7, F8, F9, F10, F11, F12,
+               Escape, Space, Return, Tab, Back, Insert, Delete, Home, End, PageUp, PageDown,
+               Left, Up, Right, Down,
+               Add, Subtract, Multiply, Divide, Minus, Equals, Comma, Period,
+               LShift, RShift, LControl, RControl, LAlt, RAlt)
+}
+
+fn parse_button(name: &str) -> Option<MouseButton> {
+    match name {
+        "Left" => Some(MouseButton::Left),
+        "Right" => Some(MouseButton::Right),
+        "Middle" => Some(MouseButton::Middle),
+        other => other.parse().ok().map(MouseButton::Other),
+    }
+}
+
+fn parse_binding(words: &[&str]) -> Result<Binding, String> {
+    match words {
+        &["key", name] => parse_key(name).map(Binding::Key).ok_or(format!("unknown key {}", name)),
+        &["mouse", name] => {
+            parse_button(name).map(Binding::Mouse).ok_or(format!("unknown mouse button {}", name))
+        }
+        _ => Err(format!("expected \"key <name>\" or \"mouse <button>\", got \"{}\"",
+                         words.join(" "))),
+    }
+}
+
+fn parse_key_binding(name: &str) -> Result<Binding, String> {
+    parse_key(name).map(Binding::Key).ok_or(format!("unknown key {}", name))
+}
+
+fn parse_axis(words: &[&str]) -> Result<AxisSource, String> {
+    match words {
+        &["mouse_x"] => Ok(AxisSource::MouseX),
+        &["mouse_y"] => Ok(AxisSource::MouseY),
+        &["scroll_x"] => Ok(AxisSource::ScrollX),
+        &["scroll_y"] => Ok(AxisSource::ScrollY),
+        &["keys", positive, negative] => {
+            Ok(AxisSource::Keys(parse_key_binding(positive)?, parse_key_binding(negative)?))
+        }
+        _ => Err(format!("unknown axis source \"{}\"", words.join(" "))),
+    }
+}
+
+/// named actions and axes, in file order
+#[derive(Clone, Debug, Default)]
+pub struct Bindings {
+    actions: Vec<(String, Binding)>,
+    axes: Vec<(String, AxisSource, f32)>,
+}
+
+impl Bindings {
+    pub fn parse(text: &str) -> Result<Bindings, InputError> {
+        let mut bindings = Bindings::default();
+
+        for (n, line) in text.lines().enumerate() {
+            let error = |message: String| {
+                InputError {
+                    line: n + 1,
+                    message: message,
+                }
+            };
+
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let mut sides = line.splitn(2, '=');
+            let head: Vec<&str> = sides.next().unwrap().split_whitespace().collect();
+            let body = sides.next().ok_or(error("expected \"=\"".to_string()))?;
+
+            match head.as_slice() {
+                &["action", name] => {
+                    let words: Vec<&str> = body.split_whitespace().collect();
+                    let binding = parse_binding(&words).map_err(&error)?;
+                    bindings.actions.push((name.to_string(), binding));
+                }
+                &["axis", name] => {
+                    let mut parts = body.splitn(2, '*');
+                    let words: Vec<&str> = parts.next().unwrap().split_whitespace().collect();
+                    let source = parse_axis(&words).map_err(&error)?;
+                    let scale = match parts.next() {
+                        Some(scale) => {
+                            scale.trim()
+                                .parse()
+                                .map_err(|_| error(format!("bad scale \"{}\"", scale.trim())))?
+                        }
+                        None => 1.0,
+                    };
+                    bindings.axes.push((name.to_string(), source, scale));
+                }
+                _ => {
+                    return Err(error(format!("expected \"action <name>\" or \"axis <name>\", \
+                                              got \"{}\"",
+                                             head.join(" "))))
+                }
+            }
+        }
+        Ok(bindings)
+    }
+
+    /// reads the bindings from an asset, like "assets/bindings.cfg"
+    pub fn load(name: &str) -> Result<Bindings, InputError> {
+        let text = assets::read_string(&assets::locate(name)).map_err(|err| {
+                InputError {
+                    line: 0,
+                    message: format!("{:?}", err),
+                }
+            })?;
+        Bindings::parse(&text)
+    }
+}
+
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+/// state of the bound inputs.
+/// events are fed with handle(), begin_frame() must be called before the events of
+/// each frame so pressed and released last for one frame only.
+pub struct Input {
+    bindings: Bindings,
+    down: Vec<Binding>,
+    pressed: Vec<Binding>,
+    released: Vec<Binding>,
+    cursor: Option<(f64, f64)>,
+    motion: (f32, f32),
+    scroll: (f32, f32),
+}
+
+impl Input {
+    pub fn new(bindings: Bindings) -> Input {
+        Input {
+            bindings: bindings,
+            down: Vec::new(),
+            pressed: Vec::new(),
+            released: Vec::new(),
+            cursor: None,
+            motion: (0.0, 0.0),
+            scroll: (0.0, 0.0),
+        }
+    }
+
+    pub fn begin_frame(&mut self) {
+        self.pressed.clear();
+        self.released.clear();
+        self.motion = (0.0, 0.0);
+        self.scroll = (0.0, 0.0);
+    }
+
+    pub fn handle(&mut self, event: &WindowEvent) {
+        match *event {
+            WindowEvent::KeyboardInput { ref input, .. } => {
+                if let Some(key) = input.virtual_keycode {
+                    self.button(Binding::Key(key), input.state);
+                }
+            }
+            WindowEvent::MouseInput { state, button, .. } => {
+                self.button(Binding::Mouse(button), state)
+            }
+            WindowEvent::MouseMoved { position, .. } => self.move_cursor(position),
+            WindowEvent::MouseWheel { delta, .. } => {
+                match delta {
+                    MouseScrollDelta::LineDelta(x, y) => self.scroll_by(x, y),
+                    // about 20 pixels per line
+                    MouseScrollDelta::PixelDelta(x, y) => self.scroll_by(x / 20.0, y / 20.0),
+                }
+            }
+            // keys released while out of the window never come back
+            WindowEvent::Focused(false) => {
+                let down: Vec<Binding> = self.down.drain(..).collect();
+                self.released.extend(down);
+            }
+            _ => {}
+        }
+    }
+
+    fn button(&mut self, binding: Binding, state: ElementState) {
+        let is_down = self.down.contains(&binding);
+        match state {
+            // key repeat sends more presses
+            ElementState::Pressed if !is_down => {
+                self.down.push(binding);
+                self.pressed.push(binding);
+            }
+            ElementState::Released if is_down => {
+                self.down.retain(|b| *b != binding);
+                self.released.push(binding);
+            }
+            _ => {}
+        }
+    }
+
+    fn move_cursor(&mut self, position: (f64, f64)) {
+        if let Some(last) = self.cursor {
+            self.motion.0 += (position.0 - last.0) as f32;
+            self.motion.1 += (position.1 - last.1) as f32;
+        }
+        self.cursor = Some(position);
+    }
+
+    fn scroll_by(&mut self, x: f32, y: f32) {
+        self.scroll.0 += x;
+        self.scroll.1 += y;
+    }
+
+    fn binding_state(&self, binding: &Binding) -> ActionState {
+        if self.pressed.contains(binding) {
+            ActionState::Pressed
+        } else if self.released.contains(binding) {
+            ActionState::Released
+        } else if self.down.contains(binding) {
+            ActionState::Held
+        } else {
+            ActionState::Idle
+        }
+    }
+
+    /// several bindings for the same action are merged, the most recent change wins
+    pub fn state(&self, action: &str) -> ActionState {
+        let states: Vec<ActionState> = self.bindings
+            .actions
+            .iter()
+            .filter(|&&(ref name, _)| name == action)
+            .map(|&(_, ref binding)| self.binding_state(binding))
+            .collect();
+        for state in &[ActionState::Pressed, ActionState::Released, ActionState::Held] {
+            if states.contains(state) {
+                return *state;
+            }
+        }
+        ActionState::Idle
+    }
+
+    pub fn pressed(&self, action: &str) -> bool {
+        self.state(action) == ActionState::Pressed
+    }
+
+    #[allow(dead_code)]
+    pub fn released(&self, action: &str) -> bool {
+        self.state(action) == ActionState::Released
+    }
+
+    /// down, no matter since when
+    pub fn held(&self, action: &str) -> bool {
+        match self.state(action) {
+            ActionState::Pressed | ActionState::Held => true,
+            _ => false,
+        }
+    }
+
+    /// sum of the sources bound to the axis, 0 if there are none
+    pub fn axis(&self, name: &str) -> f32 {
+        let key = |binding: &Binding| if self.down.contains(binding) { 1.0 } else { 0.0 };
+        self.bindings
+            .axes
+            .iter()
+            .filter(|&&(ref axis, _, _)| axis == name)
+            .map(|&(_, ref source, scale)| {
+                let value = match *source {
+                    AxisSource::MouseX => self.motion.0,
+                    AxisSource::MouseY => self.motion.1,
+                    AxisSource::ScrollX => self.scroll.0,
+                    AxisSource::ScrollY => self.scroll.1,
+                    AxisSource::Keys(ref positive, ref negative) => key(positive) - key(negative),
+                };
+                value * scale
+            })
+            .sum()
+    }
+}
+
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+//  Tests
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+#[cfg(test)]
+mod tests {
+
+    use glutin::{ElementState, MouseButton, VirtualKeyCode};
+    use super::*;
+
+    const CONFIG: &'static str = "
+        # comment
+        action jump = key Space
+        action jump = mouse Left   # two bindings
+        action fire = mouse 4
+        axis zoom = scroll_y * 10
+        axis walk = keys W S
+        axis turn = mouse_x * 0.5
+    ";
+
+    #[test]
+    fn parse() {
+        let bindings = Bindings::parse(CONFIG).expect("parse");
+        assert_eq!(bindings.actions.len(), 3);
+        assert_eq!(bindings.actions[1].1, Binding::Mouse(MouseButton::Left));
+        assert_eq!(bindings.actions[2].1, Binding::Mouse(MouseButton::Other(4)));
+        assert_eq!(bindings.axes[0], ("zoom".to_string(), AxisSource::ScrollY, 10.0));
+
+        let err = Bindings::parse("action a = key Space\naction b = key Nope").unwrap_err();
+        assert_eq!(err.line, 2);
+        assert!(Bindings::parse("axis a = mouse_x * much").is_err());
+        assert!(Bindings::parse("button a = key A").is_err());
+    }
+
+    #[test]
+    fn shipped_bindings() {
+        Bindings::load("assets/bindings.cfg").expect("assets/bindings.cfg");
+    }
+
+    #[test]
+    fn action_states() {
+        let mut input = Input::new(Bindings::parse(CONFIG).unwrap());
+        let space = Binding::Key(VirtualKeyCode::Space);
+        assert_eq!(input.state("jump"), ActionState::Idle);
+
+        input.begin_frame();
+        input.button(space, ElementState::Pressed);
+        assert!(input.pressed("jump") && input.held("jump"));
+
+        // repeat does not press again
+        input.begin_frame();
+        input.button(space, ElementState::Pressed);
+        assert_eq!(input.state("jump"), ActionState::Held);
+
+        input.begin_frame();
+        input.button(space, ElementState::Released);
+        assert!(input.released("jump") && !input.held("jump"));
+
+        input.begin_frame();
+        assert_eq!(input.state("jump"), ActionState::Idle);
+        assert_eq!(input.state("unbound"), ActionState::Idle);
+    }
+
+    #[test]
+    fn axes() {
+        let mut input = Input::new(Bindings::parse(CONFIG).unwrap());
+
+        input.begin_frame();
+        input.move_cursor((10.0, 10.0));
+        input.move_cursor((14.0, 12.0));
+        input.scroll_by(0.0, 1.0);
+        input.button(Binding::Key(VirtualKeyCode::S), ElementState::Pressed);
+        assert_eq!(input.axis("turn"), 2.0);
+        assert_eq!(input.axis("zoom"), 10.0);
+        assert_eq!(input.axis("walk"), -1.0);
+
+        // motion and scroll are per frame, keys stay down
+        input.begin_frame();
+        assert_eq!(input.axis("turn"), 0.0);
+        assert_eq!(input.axis("zoom"), 0.0);
+        assert_eq!(input.axis("walk"), -1.0);
+    }
+}
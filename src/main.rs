@@ -8,27 +8,36 @@ extern crate cgmath;
 extern crate image;
 extern crate time;
 extern crate regex;
+extern crate notify;
+extern crate serde_json;
+extern crate base64;
 #[macro_use]
 extern crate lazy_static;
 
+mod assets;
+mod input;
 mod world;
 mod utils;
 mod renderer;
 
 #[warn(unused_imports)]
-use cgmath::{Point3, Vector3, Matrix4, Euler, Deg, perspective, Transform};
+use cgmath::{Point3, Vector3, Matrix4, Euler, Deg, Transform};
+use renderer::capture;
 use renderer::context;
+use renderer::recorder;
 use renderer::camera;
 use renderer::shader;
-use renderer::texquad;
+use renderer::overlay;
+use renderer::gpu_timers::{self, GpuTimers};
+use renderer::graphs;
+use world::export;
 use world::image_atlas as img_atlas;
+use rand::{Rng, SeedableRng, XorShiftRng};
 // use renderer::pipeline::*;
 // use world::cube;
 // use renderer::shadowmapper;
 
-use renderer::context::DrawIndexed;
 use renderer::context::Program;
-use glium::Surface;
 
 
 // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
@@ -36,24 +45,96 @@ use glium::Surface;
 
 const WINDOW_WIDTH: u32 = 1920;
 const WINDOW_HEIGHT: u32 = 1080;
+/// terrain detail and noise of the recordings
+const RECORD_SEED: [u32; 4] = [0x5eed_0001, 0x5eed_0002, 0x5eed_0003, 0x5eed_0004];
+/// the poster is this many times the window, in each direction
+const POSTER_TILES: u32 = 4;
+/// models are in meters, the terrain in pixels of the height map
+const MODEL_SCALE: f32 = 8.0;
+/// pixels of the height map between exported points
+const EXPORT_STEP: u32 = 4;
+
+/// where the frames go when recording
+enum Record {
+    Png(std::path::PathBuf),
+    Raw(std::path::PathBuf),
+}
 
-enum Preview {
-    Prepass,
-    Height,
-    Depth,
-    Color,
-    SSAO,
-    Blur,
-    Noise,
+struct Options {
+    record: Option<Record>,
+    fps: u32,
+    frames: Option<u64>,
+    scene: Option<String>,
 }
 
-fn main() {
+/// --record <dir> | --record-raw <file>, --fps <n> and --frames <n> to stop after n frames,
+/// --scene <file> for a gltf scene on the terrain
+fn parse_args() -> Options {
+    let mut options = Options {
+        record: None,
+        fps: 30,
+        frames: None,
+        scene: None,
+    };
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let value = args.next().unwrap_or_else(|| {
+            println!("{} needs a value", arg);
+            std::process::exit(-1);
+        });
+        let number = || -> u64 {
+            value.parse().unwrap_or_else(|_| {
+                println!("{} needs a number, got {}", arg, value);
+                std::process::exit(-1);
+            })
+        };
+        match arg.as_str() {
+            "--record" => options.record = Some(Record::Png(value.clone().into())),
+            "--record-raw" => options.record = Some(Record::Raw(value.clone().into())),
+            "--fps" => options.fps = number() as u32,
+            "--frames" => options.frames = Some(number()),
+            "--scene" => options.scene = Some(value.clone()),
+            _ => {
+                println!("unknown option {}", arg);
+                std::process::exit(-1);
+            }
+        }
+    }
+    options
+}
 
-    let window_ratio: f32 = WINDOW_WIDTH as f32 / WINDOW_HEIGHT as f32;
+fn save_capture(img: Result<image::RgbaImage, capture::CaptureError>, path: &std::path::Path) {
+    match img.and_then(|img| capture::save(&img, path)) {
+        Ok(_) => println!("saved {}", path.display()),
+        Err(err) => println!("capture failed: {:?}", err),
+    }
+}
 
-    // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+fn save_mesh(mesh: &world::model::Mesh, path: &std::path::Path) {
+    match export::save(mesh, path) {
+        Ok(_) => println!("saved {}", path.display()),
+        Err(err) => println!("export failed: {:?}", err),
+    }
+}
+
+fn main() {
 
-    let mut ctx = context::Context::new(WINDOW_WIDTH, WINDOW_HEIGHT).unwrap();
+    let options = parse_args();
+
+    // the terrain is drawn with tessellation shaders
+    let config = context::ContextConfig::new(WINDOW_WIDTH, WINDOW_HEIGHT)
+        .with_title("Quarfs!")
+        .with_gl_version(4, 1)
+        .with_gl_profile(glutin::GlProfile::Core);
+    let mut ctx = match config.build() {
+        Ok(ctx) => ctx,
+        Err(err) => {
+            println!("{}", err);
+            std::process::exit(-1);
+        }
+    };
+    println!("{}", ctx.capabilities());
 
     // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
 
@@ -68,11 +149,29 @@ fn main() {
 
     //  map overlay ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
 
-    let mut quad = texquad::TexQuad::new(&ctx);
+    // the bottom of the window, one row. the gpu times panel has the graph
+    let mut overlay = overlay::Overlay::new(&ctx, overlay::Rect::new(0.0, 0.0, 1.0, 0.3), 8);
+    for &(name, false_color) in &[("prepass", false),
+                                  ("depth", false),
+                                  ("ssao", true),
+                                  ("blur", true),
+                                  ("noise", false),
+                                  ("height", false),
+                                  ("color", false),
+                                  ("gpu times", false)] {
+        overlay.add_panel(&ctx, name, false_color);
+    }
+    let mut graph_prg = shader::ProgramReloader::new(&ctx, "performance").unwrap();
+
+    // the pipeline ones, and what goes on screen
+    const GPU_PASSES: [&'static str; 5] = ["prepass", "ssao", "blur", "color", "overlay"];
+    let mut gpu_timers = GpuTimers::new(&GPU_PASSES);
 
     // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
 
-    let height_raw = glium::texture::RawImage2d::from_raw_rgb(height.into_raw(), height_dimensions);
+    // the texture takes the pixels, the export needs them later
+    let height_raw = glium::texture::RawImage2d::from_raw_rgb(height.clone().into_raw(),
+                                                              height_dimensions);
     let height_map = glium::texture::Texture2d::new(ctx.display(), height_raw).unwrap();
 
     let color = img_atlas::load_rgb("assets/C18W.png");
@@ -87,24 +186,16 @@ fn main() {
     let looking = Point3::new(0.0, 0.0, 0.0); // Point3::new(0.0, 0.0, -10.0);
     let mut cam = camera::Camera::new(eye, looking);
 
-    const NEAR: f32 = 5.0;
-    const FAR: f32 = 1500.0;
+    // the context keeps the aspect ratio of the window
+    ctx.set_projection(Deg(45.0), 5.0, 1500.0);
 
-    let mut perspective_matrix: Matrix4<f32> = perspective(Deg(45.0), window_ratio, NEAR, FAR);
     let mut model_matrix: Matrix4<f32> =
         Matrix4::from_translation(Vector3::new(-(size_x as f32 / 2.0),
                                                0.0,
                                                -(size_z as f32 / 2.0)));
 
-    // per increment rotation
-    let rotation = Quaternion::from(Euler {
-        x: Deg(0.0),
-        y: Deg(0.05),
-        z: Deg(0.0),
-    });
-
-    let rot_mat = Matrix4::from(rotation);
-    let _ = rot_mat;
+    // rotation of the terrain, degrees per second
+    const TERRAIN_SPEED: f32 = 3.0;
 
     // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
 
@@ -118,105 +209,187 @@ fn main() {
 
     // sun pos
     let mut sun_pos = Point3::new(0.0, 75.0, size_x as f32); // / 2.0 + 20.0);
-    let sun_rot = Quaternion::from(Euler {
-        x: Deg(0.02),
-        y: Deg(0.02),
-        z: Deg(0.0),
-    });
+    // degrees per second
+    const SUN_SPEED: f32 = 1.2;
+
+    // a recording must be the same every time, terrain detail and noise are seeded
+    let mut rng: XorShiftRng = match options.record {
+        Some(_) => SeedableRng::from_seed(RECORD_SEED),
+        None => rand::thread_rng().gen(),
+    };
 
     // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
 
-    let new_terrain = world::terrain::Terrain::new(&ctx, size_x as u32, size_z as u32);
+    let new_terrain =
+        world::terrain::Terrain::with_rng(&ctx, size_x as u32, size_z as u32, &mut rng);
 
-    let terrain_prg = shader::ProgramReloader::new(&ctx, "terrain_texture");
-    if terrain_prg.is_err() {
-        std::process::exit(-1);
-    }
-    let mut terrain_prg = terrain_prg.unwrap();
-
-    let terrain_normals_prg = shader::ProgramReloader::new(&ctx, "terrain_normals");
-    if terrain_normals_prg.is_err() {
+    // terrain programs are permutations, toggles are compiled in as defines
+    let mut programs = shader::ProgramCache::new();
+    let terrain_defines = |compute_shadows: bool| {
+        let mut defines = shader::Defines::new();
+        if compute_shadows {
+            defines.set("SHADOWS", "1");
+        }
+        defines
+    };
+    if programs.load(&ctx, "terrain_texture", &terrain_defines(compute_shadows)).is_err() {
         std::process::exit(-1);
     }
-    let mut terrain_normals_prg = terrain_normals_prg.unwrap();
+    let terrain_normals_id = match programs.load(&ctx, "terrain_normals", &shader::Defines::new()) {
+        Ok(id) => id,
+        Err(_) => std::process::exit(-1),
+    };
 
     // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
 
     let axis_plot = utils::Axis::new(&ctx);
 
+    // the quarf stands in the middle of the terrain
+    let quarf = match world::model::Model::load(&ctx, "assets/quarf.obj") {
+        Ok(model) => model,
+        Err(err) => {
+            println!("can not load the quarf: {:?}", err);
+            std::process::exit(-1);
+        }
+    };
+    let mut model_prg = shader::ProgramReloader::new(&ctx, "model").unwrap();
+
+    // props, each instance stands on the ground under its origin
+    let scene = options.scene.as_ref().map(|file| {
+        world::gltf::Scene::load(&ctx, file).unwrap_or_else(|err| {
+            println!("can not load {}: {:?}", file, err);
+            std::process::exit(-1);
+        })
+    });
+    let mut scene_prg = shader::ProgramReloader::new(&ctx, "scene").unwrap();
+    // for the materials without texture
+    let white = {
+        let img = image::RgbaImage::from_pixel(1, 1, image::Rgba([255, 255, 255, 255]));
+        ctx.textures_mut().create_texture_from_image(&ctx, "white", img).unwrap()
+    };
+
     // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
 
-    use glium::texture;
-    let (h, w) = ctx.get_size();
-    let prepass_texture =
-        texture::Texture2d::empty_with_format(ctx.display(),
-                                              texture::UncompressedFloatFormat::F32F32F32F32,
-                                              texture::MipmapsOption::NoMipmap,
-                                              h,
-                                              w)
-            .unwrap();
-
-    let depth_tex = texture::DepthTexture2d::empty_with_format(ctx.display(),
-                                                               texture::DepthFormat::F32,
-                                                               texture::MipmapsOption::NoMipmap,
-                                                               h,
-                                                               w)
-        .unwrap();
-
-
-
-    let mut prepas_frame =
-        Box::new(glium::framebuffer::SimpleFrameBuffer::with_depth_buffer(ctx.display(),
-                                                                          &prepass_texture,
-                                                                          &depth_tex)
-            .unwrap());
-
-    //  ssao pass  ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
-    let noise_img = img_atlas::generate_noise((w, h));
-    let noise_dim = noise_img.dimensions();
-    let noise_tex = glium::texture::RawImage2d::from_raw_rgb(noise_img.into_raw(), noise_dim);
-    let noise_tex = glium::texture::Texture2d::new(ctx.display(), noise_tex).unwrap();
-
-    let drop_depth = texture::DepthTexture2d::empty_with_format(ctx.display(),
-                                                                texture::DepthFormat::F32,
-                                                                texture::MipmapsOption::NoMipmap,
-                                                                h,
-                                                                w)
-        .unwrap();
-
-    let ssao_texture =
-        texture::Texture2d::empty_with_format(ctx.display(),
-                                              texture::UncompressedFloatFormat::F32F32F32F32,
-                                              texture::MipmapsOption::NoMipmap,
-                                              h,
-                                              w)
-            .unwrap();
-    let mut ssao = renderer::ScreenSpacePass::new(&ctx, "ssao", &ssao_texture, &drop_depth);
-
-    //  blur  ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
-    let blur_texture =
-        texture::Texture2d::empty_with_format(ctx.display(),
-                                              texture::UncompressedFloatFormat::F32F32F32F32,
-                                              texture::MipmapsOption::NoMipmap,
-                                              h,
-                                              w)
-            .unwrap();
-    let mut blur = renderer::ScreenSpacePass::new(&ctx, "blur", &blur_texture, &drop_depth);
+    //  prepass, ssao and blur targets  ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+    let noise_img = img_atlas::generate_noise_with(ctx.get_size(), &mut rng);
+    let mut pipeline = renderer::pipeline::Pipeline::new(&ctx, &noise_img);
+    let mut screen_capture = capture::Capture::new(&ctx);
+
+    // recordings advance the same time every frame, no matter how long it took
+    let recording = match options.record {
+        Some(Record::Png(ref dir)) => Some(recorder::Recorder::png_sequence(dir, options.fps)),
+        Some(Record::Raw(ref file)) => Some(recorder::Recorder::raw_video(file, options.fps)),
+        None => None,
+    };
+    let mut recorder = match recording {
+        Some(Ok(recorder)) => Some(recorder),
+        Some(Err(err)) => {
+            println!("can not record: {:?}", err);
+            std::process::exit(-1);
+        }
+        None => None,
+    };
+    let timestep = match recorder {
+        Some(ref recorder) => utils::Timestep::Fixed(recorder.timestep()),
+        None => utils::Timestep::Measured,
+    };
+    // a recording only depends on the seed, the input does not move the scene
+    // and the preview overlay stays out of the frames
+    let recording = recorder.is_some();
 
     // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
     // ~~~~~~~~~~~~~~~~~~~~~~~~~~~ RENDER LOOP ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
     // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
 
-    let mut preview = Preview::Blur;
+    let bindings = match input::Bindings::load("assets/bindings.cfg") {
+        Ok(bindings) => bindings,
+        Err(err) => {
+            println!("assets/bindings.cfg:{}: {}", err.line, err.message);
+            std::process::exit(-1);
+        }
+    };
+    let mut input = input::Input::new(bindings);
+
+    let mut show_overlay = true;
     let mut chunk_size: u32 = 20;
-    utils::loop_with_report(&mut |delta: f64, _: &mut utils::PerformaceCounters| {
+    utils::loop_with_timestep(&mut |delta: f64, counters: &mut utils::PerformaceCounters| {
+
+        gpu_timers.begin_frame(&ctx);
+        gpu_timers.report(counters);
+
+        // ~~~~~~~~~ actions, out of the events of the last frame ~~~~~~~~~
+
+        if input.pressed("quit") {
+            std::process::exit(0);
+        }
+        if input.pressed("toggle_run") && !recording {
+            run = !run;
+        }
+        if input.pressed("toggle_overlay") {
+            show_overlay = !show_overlay;
+        }
+        for i in 0..overlay.layout().len() {
+            if input.pressed(&format!("toggle_panel_{}", i + 1)) {
+                let name = overlay.layout().name(i).unwrap().to_string();
+                overlay.layout_mut().toggle(&name);
+            }
+        }
+        if input.pressed("toggle_wireframe") && !recording {
+            render_kind = match render_kind {
+                RenderType::Textured => RenderType::WireFrame,
+                RenderType::WireFrame => RenderType::Textured,
+            };
+        }
+        if input.pressed("toggle_shadows") && !recording {
+            compute_shadows = !compute_shadows;
+        }
+        if input.pressed("chunk_up") {
+            chunk_size += 1;
+            println!("chunk size {}", chunk_size);
+        }
+        if input.pressed("chunk_down") && chunk_size > 1 {
+            chunk_size -= 1;
+            println!("chunk size {}", chunk_size);
+        }
+
+        if !recording {
+            if input.held("orbit") {
+                cam.orbit(Deg(input.axis("orbit")));
+            }
+            let zoom = input.axis("zoom");
+            if zoom != 0.0 {
+                cam.zoom(zoom);
+            }
+            let elevation = input.axis("elevation");
+            if elevation != 0.0 {
+                cam.change_elevation(elevation);
+            }
+        }
 
         cam.update(delta as f32);
-        terrain_prg.update(&ctx, delta);
-        terrain_normals_prg.update(&ctx, delta);
-        quad.update(&ctx, delta);
-        ssao.update(&ctx, delta);
-        blur.update(&ctx, delta);
+        programs.update(&ctx, delta);
+        overlay.update(&ctx, delta);
+        graph_prg.update(&ctx, delta);
+        model_prg.update(&ctx, delta);
+        scene_prg.update(&ctx, delta);
+        pipeline.update(&ctx, delta);
+        screen_capture.update(&ctx, delta);
+
+        // a toggle change compiles the new permutation the first time it is used,
+        // if it does not compile the toggle goes back to the previous one
+        let terrain_prg = match programs.load(&ctx,
+                                              "terrain_texture",
+                                              &terrain_defines(compute_shadows)) {
+            Ok(id) => id,
+            Err(err) => {
+                println!("shadows toggle failed, keeping the previous program: {:?}", err);
+                compute_shadows = !compute_shadows;
+                programs.load(&ctx, "terrain_texture", &terrain_defines(compute_shadows))
+                    .expect("previous permutation is cached")
+            }
+        };
+        let terrain_prg = programs.get(terrain_prg);
+        let terrain_normals_prg = programs.get(terrain_normals_id);
 
         // keep mut separated
         {
@@ -224,99 +397,211 @@ fn main() {
             let view_matrix = cam_mat * Matrix4::from_translation(Vector3::new(0.0, 0.0, 0.0));
 
             if run {
-                model_matrix = rot_mat * model_matrix;
-                //  model_matrix = model_matrix;
+                let step = delta as f32;
+                let rotation = Quaternion::from(Euler {
+                    x: Deg(0.0),
+                    y: Deg(TERRAIN_SPEED * step),
+                    z: Deg(0.0),
+                });
+                let sun_rot = Quaternion::from(Euler {
+                    x: Deg(SUN_SPEED * step),
+                    y: Deg(SUN_SPEED * step),
+                    z: Deg(0.0),
+                });
+                model_matrix = Matrix4::from(rotation) * model_matrix;
                 sun_pos = sun_rot.rotate_point(sun_pos);
             }
 
-            // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
-            //   matrix
-            // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
-            let pvm = perspective_matrix * view_matrix * model_matrix;
-            let inverse_matrix = pvm.inverse_transform().unwrap();
-
-            // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
-            //    render scene
-            // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
-
-            let uniforms = uniform! {
-                perspective: Into::<[[f32; 4]; 4]>::into(perspective_matrix),
-                view:        Into::<[[f32; 4]; 4]>::into(view_matrix),
-                model:       Into::<[[f32; 4]; 4]>::into(model_matrix),
-                pvm:         Into::<[[f32; 4]; 4]>::into(pvm),
-                //light_space_matrix: Into::<[[f32; 4]; 4]>::into(light_space_matrix),
-
-                //atlas_texture: &atlas_texture,
-                //atlas_side:    atlas_side as u32,
-                //shadow_texture: shadow_maker.depth_as_texture(),
-
-                sun_pos:    Into::<[f32; 3]>::into(sun_pos),
-                cam_pos:    Into::<[f32; 3]>::into(cam.get_eye()),
-                shadows_enabled:   compute_shadows,
-
-                height_map: &height_map,
-                height_size:    (size_x as u32, size_z as u32),
+            // the scene for a projection, the poster draws it once per tile
+            // not interactive, the poster tiles have no overlay and are not timed
+            let draw_scene = |perspective_matrix: Matrix4<f32>, interactive: bool| {
+                let timers = if interactive { Some(&gpu_timers) } else { None };
+
+                // begins first, so a failed pass still finishes the frame when leaving
+                let mut surface = DrawSurface::gl_begin(&ctx, render_kind);
+
+                // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+                //   matrix
+                // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+                let pvm = perspective_matrix * view_matrix * model_matrix;
+                let inverse_matrix = pvm.inverse_transform().unwrap();
+
+                // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+                //    render scene
+                // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+                let uniforms = uniform! {
+                    perspective: Into::<[[f32; 4]; 4]>::into(perspective_matrix),
+                    view:        Into::<[[f32; 4]; 4]>::into(view_matrix),
+                    model:       Into::<[[f32; 4]; 4]>::into(model_matrix),
+                    pvm:         Into::<[[f32; 4]; 4]>::into(pvm),
+                    //light_space_matrix: Into::<[[f32; 4]; 4]>::into(light_space_matrix),
+
+                    //atlas_texture: &atlas_texture,
+                    //atlas_side:    atlas_side as u32,
+                    //shadow_texture: shadow_maker.depth_as_texture(),
+
+                    sun_pos:    Into::<[f32; 3]>::into(sun_pos),
+                    cam_pos:    Into::<[f32; 3]>::into(cam.get_eye()),
+
+                    height_map: &height_map,
+                    height_size:    (size_x as u32, size_z as u32),
+
+                    screen_size: ctx.get_size(),
+                    color_map: &color_map,
+
+                    ssao_texture: &pipeline.targets.blur,
+                };
+
+                // ~~~~~~~~~ prepass: normals and depth  ~~~~~~~~~~~~~~~~
+
+                pipeline.prepass(&ctx,
+                                 new_terrain.get_geometry(),
+                                 new_terrain.get_tiles(),
+                                 terrain_normals_prg,
+                                 &uniforms,
+                                 timers)?;
+
+                // ~~~~~~~~~  SSAO and blur ~~~~~~~~~~~~~~~~
+
+                pipeline.ssao(&ctx, &inverse_matrix, timers)?;
+
+                // ~~~~~~~~~  render color ~~~~~~~~~~~~~~~~
+
+                surface.set_time_query(timers.and_then(|t| t.get("color")));
+                surface.draw_geometry(axis_plot.get_geometry(), &axis_plot, &uniforms)?;
+                // surface.draw_with_indices_and_program(&new_terrain, &terrain_prg, &uniforms);
+                surface.draw_geometry_instanced(new_terrain.get_geometry(),
+                                                new_terrain.get_tiles(),
+                                                terrain_prg,
+                                                &uniforms)?;
+                for part in quarf.parts() {
+                    let model_uniforms = uniform! {
+                        pvm:        Into::<[[f32; 4]; 4]>::into(pvm),
+                        model:      Into::<[[f32; 4]; 4]>::into(model_matrix),
+                        height_map: &height_map,
+                        place:      (size_x / 2.0, size_z / 2.0),
+                        scale:      MODEL_SCALE,
+                        sun_pos:    Into::<[f32; 3]>::into(sun_pos),
+                        diffuse:    part.material.diffuse,
+                    };
+                    surface.draw_geometry(part.geometry, &model_prg, &model_uniforms)?;
+                }
+                if let Some(ref scene) = scene {
+                    let textures = ctx.textures();
+                    for mesh in scene.meshes() {
+                        let instances = match mesh.instances {
+                            Some(instances) => instances,
+                            None => continue,
+                        };
+                        for primitive in &mesh.primitives {
+                            let material = scene.material(primitive);
+                            let base_color_map = material.base_color_texture
+                                .and_then(|t| scene.texture(t))
+                                .and_then(|t| textures.get_texture(t))
+                                .or_else(|| textures.get_texture(white))
+                                .unwrap();
+                            let alpha_cutoff = match material.alpha_mode {
+                                world::gltf::AlphaMode::Mask(cutoff) => cutoff,
+                                _ => 0.0,
+                            };
+                            let scene_uniforms = uniform! {
+                                pvm:            Into::<[[f32; 4]; 4]>::into(pvm),
+                                model:          Into::<[[f32; 4]; 4]>::into(model_matrix),
+                                height_map:     &height_map,
+                                place:          (size_x / 2.0, size_z / 2.0),
+                                scale:          MODEL_SCALE,
+                                sun_pos:        Into::<[f32; 3]>::into(sun_pos),
+                                base_color:     material.base_color,
+                                base_color_map: base_color_map,
+                                alpha_cutoff:   alpha_cutoff,
+                            };
+                            surface.draw_geometry_instanced(primitive.geometry,
+                                                            instances,
+                                                            &scene_prg,
+                                                            &scene_uniforms)?;
+                        }
+                    }
+                }
+
+                if interactive && show_overlay {
+                    surface.set_time_query(gpu_timers.get("overlay"));
+                    let targets = &pipeline.targets;
+                    overlay.draw(&ctx,
+                                 &mut surface,
+                                 &[("prepass", overlay::Source::Color(&targets.prepass)),
+                                   ("depth", overlay::Source::Depth(&targets.depth)),
+                                   ("ssao", overlay::Source::Color(&targets.ssao)),
+                                   ("blur", overlay::Source::Color(&targets.blur)),
+                                   ("noise", overlay::Source::Color(&targets.noise)),
+                                   ("height", overlay::Source::Color(&height_map)),
+                                   ("color", overlay::Source::Color(&color_map))])?;
+
+                    // stacked, one line per pass
+                    if let Some(viewport) = overlay.viewport(&ctx, "gpu times") {
+                        let mut plot = graphs::GraphPlot::new(&ctx,
+                                                              surface.get_frame(),
+                                                              counters.get_current_tick(),
+                                                              viewport,
+                                                              &graph_prg);
+                        for pass in &GPU_PASSES {
+                            let name = format!("{}{}", gpu_timers::PREFIX, pass);
+                            if let Some(values) = counters.get_measurements_for(&name) {
+                                plot.draw_values(&ctx, values);
+                            }
+                        }
+                    }
+                }
+
+                surface.gl_end()
+            };
 
-                screen_size: ctx.get_size(),
-                color_map: &color_map,
+            // errors are collected by the context and reported once per frame
+            let _ = draw_scene(ctx.get_projection(), !recording);
 
-                ssao_texture: &blur_texture,
-            };
+            // ~~~~~~~~~  recording ~~~~~~~~~~~~~~~~
 
-            // ~~~~~~~~~ prepass: normals and depth  ~~~~~~~~~~~~~~~~
-
-            let parameters = glium::DrawParameters {
-                backface_culling: glium::BackfaceCullingMode::CullClockwise,
-                depth: glium::Depth {
-                    test: glium::DepthTest::IfLess,
-                    write: true,
-                    ..Default::default()
-                },
-                polygon_mode: glium::PolygonMode::Fill,
-                provoking_vertex: glium::draw_parameters::ProvokingVertex::LastVertex,
-                ..Default::default()
-            };
+            if let Some(ref mut recorder) = recorder {
+                if let Err(err) = recorder.record(&ctx) {
+                    println!("recording failed: {:?}", err);
+                    std::process::exit(-1);
+                }
+                if Some(recorder.frames()) == options.frames {
+                    println!("recorded {} frames", recorder.frames());
+                    std::process::exit(0);
+                }
+            }
 
-            prepas_frame.clear_color_and_depth((0.0, 0.0, 0.0, 0.0), 1.0);
-            prepas_frame.draw((new_terrain.get_vertices(),
-                       new_terrain.get_tiles()
-                          .per_instance()
-                          .unwrap()),
-                      new_terrain.get_indices(),
-                      terrain_normals_prg.get_program(),
-                      &uniforms,
-                      &parameters)
-                .unwrap();
-
-            // ~~~~~~~~~  SSAO ~~~~~~~~~~~~~~~~
-
-            ssao.execute_pass(&inverse_matrix, &prepass_texture, &depth_tex, &noise_tex);
-
-            // ~~~~~~~~~  blur SSAO ~~~~~~~~~~~~~~~~
-
-            blur.execute_pass(&inverse_matrix, &ssao_texture, &depth_tex, &noise_tex);
-
-            // ~~~~~~~~~  render color ~~~~~~~~~~~~~~~~
-
-            let mut surface = DrawSurface::gl_begin(&ctx, render_kind);
-            surface.draw(&axis_plot, &uniforms);
-            // surface.draw_with_indices_and_program(&new_terrain, &terrain_prg, &uniforms);
-            surface.draw_instanciated_with_indices_and_program(&new_terrain,
-                                                               new_terrain.get_tiles(),
-                                                               &terrain_prg,
-                                                               &uniforms);
-
-            match preview {
-                Preview::Noise => surface.draw_overlay_quad(&quad, &noise_tex, false),
-                Preview::SSAO => surface.draw_overlay_quad(&quad, &ssao_texture, false),
-                Preview::Blur => surface.draw_overlay_quad(&quad, &blur_texture, false),
-                Preview::Prepass => surface.draw_overlay_quad(&quad, &prepass_texture, false),
-                Preview::Height => surface.draw_overlay_quad(&quad, &height_map, false),
-                Preview::Depth => surface.draw_overlay_quad(&quad, &depth_tex, true),
-                Preview::Color => surface.draw_overlay_quad(&quad, &color_map, false),
-            };
+            // ~~~~~~~~~  capture ~~~~~~~~~~~~~~~~
 
-            surface.gl_end();
+            if input.pressed("screenshot") {
+                let path = capture::next_path("quarfs");
+                let frame = screen_capture.grab(&ctx, &pipeline.targets, capture::Target::Frame);
+                save_capture(frame, &path);
+            }
+            if input.pressed("capture_targets") {
+                for target in capture::Target::all() {
+                    let path = capture::next_path(target.name());
+                    save_capture(screen_capture.grab(&ctx, &pipeline.targets, *target), &path);
+                }
+            }
+            if input.pressed("poster") {
+                let path = capture::next_path("poster");
+                save_capture(capture::poster(&ctx, POSTER_TILES, &|projection| {
+                                 draw_scene(projection, false)
+                             }),
+                             &path);
+            }
+            if input.pressed("export_terrain") {
+                let path = capture::next_file("terrain", "stl");
+                let mesh = export::surface(&height, export::Region::whole(&height), EXPORT_STEP);
+                save_mesh(&mesh, &path);
+            }
+            if input.pressed("export_blocks") {
+                let path = capture::next_file("blocks", "obj");
+                let mesh = export::blocks(&height, export::Region::whole(&height), EXPORT_STEP);
+                save_mesh(&mesh, &path);
+            }
         }
 
         // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
@@ -325,40 +610,31 @@ fn main() {
 
         // listing the events produced by the window and waiting to be received
         let mut resizes = Vec::new();
-       {
-           ctx.events_loop().poll_events(|event|{
-
-               use glium::glutin::Event;
-               use glium::glutin::WindowEvent;
-
-               if let Event::WindowEvent{ window_id: _, event: window_event} = event{
-                   match window_event {
-                       WindowEvent::Closed => std::process::exit(0),  // esc
-                       _ => {},
-                   }
-               }
-           });
-       }
-
-        // can not change window while context is borrowed
-        for (w, h) in resizes {
-            ctx.resize(w, h);
-            // FIXME, this is a fix
-            perspective_matrix = perspective(Deg(45.0), w as f32 / h as f32, NEAR, FAR);
-
-
-            ctx.get_size();
-            // texture = texture::Texture2d::empty_with_format(ctx.display(),
-            //                                                texture::UncompressedFloatFormat::F32,
-            //                                                texture::MipmapsOption::NoMipmap,
-            //                                                h, w).unwrap();
-
-            // depth = texture::DepthTexture2d::empty_with_format(ctx.display(),
-            //                                                   texture::DepthFormat::F32,
-            //                                                   texture::MipmapsOption::NoMipmap,
-            //                                                    h, w).unwrap();
+        input.begin_frame();
+        if let Some(events_loop) = ctx.events_loop() {
+            events_loop.poll_events(|event| {
+
+                use glium::glutin::Event;
+                use glium::glutin::WindowEvent;
+
+                if let Event::WindowEvent { window_id: _, event: window_event } = event {
+                    input.handle(&window_event);
+                    match window_event {
+                        WindowEvent::Closed => std::process::exit(0),  // esc
+                        WindowEvent::Resized(w, h) => resizes.push((w, h)),
+                        _ => {}
+                    }
+                }
+            });
+        }
+
+        // can not change window while context is borrowed, only the last size matters
+        if let Some((w, h)) = resizes.pop() {
+            if ctx.resize(w, h) {
+                pipeline.resize(&ctx, &img_atlas::generate_noise_with(ctx.get_size(), &mut rng));
+            }
         }
 
-    }, 1); // refresh every 5 secs
+    }, 1, timestep); // refresh every 5 secs
 
 }
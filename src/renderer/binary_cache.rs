@@ -0,0 +1,147 @@
+use glium;
+use glium::program::{Binary, ProgramCreationError, ProgramCreationInput, SourceCode};
+
+use std::fs;
+use std::io::prelude::*;
+use std::path;
+
+use assets;
+use renderer::context::Context;
+
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+//  Program binary cache,
+//  compiling the big tessellation programs takes a while on every start up.
+//  once built, the driver can give us the program binary, we store it keyed by
+//  the final source and the driver, and load it the next time.
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+
+/// fnv-1a, we need the hash to be the same from one build to the next
+fn hash(data: &[u8], mut h: u64) -> u64 {
+    for b in data {
+        h ^= *b as u64;
+        h = h.wrapping_mul(0x100000001b3);
+    }
+    h
+}
+
+/// the key changes with any change of the preprocessed code or of the driver
+fn cache_key(renderer: &str, version: &str, stages: &[Option<&str>]) -> u64 {
+    let mut h = 0xcbf29ce484222325;
+    h = hash(renderer.as_bytes(), h);
+    h = hash(version.as_bytes(), h);
+    for stage in stages {
+        // separator, so moving code from one stage to the next changes the key
+        h = hash(&[0xff], h);
+        if let Some(code) = *stage {
+            h = hash(code.as_bytes(), h);
+        }
+    }
+    h
+}
+
+fn cache_dir() -> path::PathBuf {
+    assets::cache_dir().join("programs")
+}
+
+fn cache_path(key: u64) -> path::PathBuf {
+    let mut path = cache_dir();
+    path.push(format!("{:016x}.bin", key));
+    path
+}
+
+/// file layout: format as little endian u32, followed by the binary content
+fn read_binary(path: &path::Path) -> Option<Binary> {
+    let mut data = Vec::new();
+    if fs::File::open(path).and_then(|mut f| f.read_to_end(&mut data)).is_err() ||
+       data.len() < 4 {
+        return None;
+    }
+    let format = data[0] as u32 | (data[1] as u32) << 8 | (data[2] as u32) << 16 |
+                 (data[3] as u32) << 24;
+    Some(Binary {
+        format: format,
+        content: data.split_off(4),
+    })
+}
+
+fn write_binary(path: &path::Path, binary: &Binary) {
+    let header = [binary.format as u8,
+                  (binary.format >> 8) as u8,
+                  (binary.format >> 16) as u8,
+                  (binary.format >> 24) as u8];
+
+    let res = fs::create_dir_all(cache_dir())
+        .and_then(|_| fs::File::create(path))
+        .and_then(|mut f| f.write_all(&header).and_then(|_| f.write_all(&binary.content)));
+    if let Err(err) = res {
+        println!("can not write program cache {}: {:?}", path.display(), err);
+    }
+}
+
+/// builds the program from the cached binary if we have one the driver accepts,
+/// otherwise compiles the source and stores the result for the next time.
+pub fn build_program(ctx: &Context,
+                     code: SourceCode)
+                     -> Result<glium::Program, ProgramCreationError> {
+
+    let key = cache_key(ctx.display().get_opengl_renderer_string(),
+                        ctx.display().get_opengl_version_string(),
+                        &[Some(code.vertex_shader),
+                          code.tessellation_control_shader,
+                          code.tessellation_evaluation_shader,
+                          code.geometry_shader,
+                          Some(code.fragment_shader)]);
+    let path = cache_path(key);
+
+    if let Some(binary) = read_binary(&path) {
+        let input = ProgramCreationInput::Binary {
+            data: binary,
+            outputs_srgb: false,
+            uses_point_size: false,
+        };
+        match glium::Program::new(ctx.display(), input) {
+            Ok(prog) => {
+                println!("   program loaded from cache {}", path.display());
+                return Ok(prog);
+            }
+            Err(err) => {
+                // driver updates may invalidate the binaries
+                println!("   cached program rejected, compile from source: {:?}", err);
+                let _ = fs::remove_file(&path);
+            }
+        }
+    }
+
+    let prog = glium::Program::new(ctx.display(), code)?;
+    match prog.get_binary() {
+        Ok(binary) => write_binary(&path, &binary),
+        Err(err) => println!("   program binary not available: {:?}", err),
+    }
+    Ok(prog)
+}
+
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+//  Tests
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+#[cfg(test)]
+mod tests {
+
+    use super::cache_key;
+
+    #[test]
+    fn key() {
+        let a = cache_key("mesa", "4.5", &[Some("void main(){}"), None, Some("void main(){}")]);
+        let b = cache_key("mesa", "4.5", &[Some("void main(){}"), None, Some("void main(){}")]);
+        assert_eq!(a, b);
+
+        // driver changes
+        let c = cache_key("nvidia", "4.5", &[Some("void main(){}"), None, Some("void main(){}")]);
+        assert!(a != c);
+
+        // code moved from one stage to another
+        let d = cache_key("mesa", "4.5", &[Some("void main(){}"), Some("void main(){}"), None]);
+        assert!(a != d);
+    }
+}
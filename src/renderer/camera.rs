@@ -1,6 +1,6 @@
 extern crate glium;
 
-use cgmath::{Point3, Vector3, Matrix4};
+use cgmath::{Deg, Point3, Vector3, Matrix4};
 
 // 60fps, more or less 60 units per second
 const CAMERA_SPEED: f32 = 60.0;
@@ -37,6 +37,22 @@ impl Camera {
         self.move_to(to);
     }
 
+    /// turns around the vertical axis of the point we look at
+    pub fn orbit(&mut self, angle: Deg<f32>) {
+        use cgmath::{Basis3, Rotation, Rotation3};
+        let rotation: Basis3<f32> = Rotation3::from_angle_y(angle);
+        let offset = rotation.rotate_vector(self.target_eye - self.view_center);
+        self.move_to(self.view_center + offset);
+    }
+
+    /// gets closer to the point we look at, never closer than one unit
+    pub fn zoom(&mut self, distance: f32) {
+        use cgmath::InnerSpace;
+        let offset = self.target_eye - self.view_center;
+        let length = (offset.magnitude() - distance).max(1.0);
+        self.move_to(self.view_center + offset.normalize() * length);
+    }
+
     #[inline]
     pub fn move_to(&mut self, target: Point3<f32>) {
         self.target_eye = target;
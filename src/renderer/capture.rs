@@ -0,0 +1,260 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use cgmath::{Matrix4, Vector3};
+use glium;
+use glium::texture;
+use image;
+
+use renderer::context::Context;
+use renderer::errors::RenderError;
+use renderer::pipeline::Targets;
+use renderer::ss_pass::ScreenSpacePass;
+
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+//    Capture,
+//    the final frame or any of the pipeline targets to an image.
+//    depth is converted to distance to the eye and stretched to the range
+//    found in the picture, so it can be seen.
+//    the poster renders the frame several times, each with a slice of the
+//    projection, and stitches the tiles into one big picture.
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+#[derive(Debug)]
+pub enum CaptureError {
+    ReadBackFailed,
+    RenderFailed(RenderError),
+    UnknownTarget(String),
+    SaveFailed(String),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Target {
+    Frame,
+    Prepass,
+    SSAO,
+    Blur,
+    Depth,
+}
+
+impl Target {
+    pub fn all() -> &'static [Target] {
+        const ALL: &'static [Target] =
+            &[Target::Frame, Target::Prepass, Target::SSAO, Target::Blur, Target::Depth];
+        ALL
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Target::Frame => "frame",
+            Target::Prepass => "prepass",
+            Target::SSAO => "ssao",
+            Target::Blur => "blur",
+            Target::Depth => "depth",
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn from_name(name: &str) -> Result<Target, CaptureError> {
+        Target::all()
+            .iter()
+            .find(|t| t.name() == name)
+            .cloned()
+            .ok_or(CaptureError::UnknownTarget(name.to_string()))
+    }
+}
+
+/// gl rows go from the bottom up, images from the top down
+pub fn to_image(raw: glium::texture::RawImage2d<u8>) -> image::RgbaImage {
+    let img = image::RgbaImage::from_raw(raw.width, raw.height, raw.data.into_owned())
+        .expect("rgba read back");
+    image::imageops::flip_vertical(&img)
+}
+
+fn read_color(texture: &texture::Texture2d) -> image::RgbaImage {
+    let raw: glium::texture::RawImage2d<u8> = texture.read();
+    to_image(raw)
+}
+
+/// linear depth rows, bottom up, to grey: near is white, the background black
+fn stretch_depth(rows: &[Vec<(f32, f32, f32, f32)>]) -> image::RgbaImage {
+    let height = rows.len() as u32;
+    let width = rows.first().map(|r| r.len()).unwrap_or(0) as u32;
+
+    let values = rows.iter().flat_map(|r| r.iter()).map(|p| p.0).filter(|v| *v < 1.0);
+    let (min, max) = values.fold((1.0f32, 0.0f32), |(min, max), v| (min.min(v), max.max(v)));
+    let range = (max - min).max(1e-6);
+
+    let mut img = image::RgbaImage::new(width, height);
+    for (y, row) in rows.iter().rev().enumerate() {
+        for (x, p) in row.iter().enumerate() {
+            let grey = if p.0 < 1.0 {
+                (255.0 * (1.0 - (p.0 - min) / range)) as u8
+            } else {
+                0
+            };
+            img.put_pixel(x as u32, y as u32, image::Rgba([grey, grey, grey, 255]));
+        }
+    }
+    img
+}
+
+pub struct Capture {
+    depth_pass: ScreenSpacePass,
+}
+
+impl Capture {
+    pub fn new(ctx: &Context) -> Capture {
+        Capture { depth_pass: ScreenSpacePass::new(ctx, "linear_depth") }
+    }
+
+    pub fn update(&mut self, ctx: &Context, delta: f64) {
+        self.depth_pass.update(ctx, delta);
+    }
+
+    /// what is in the target now, the frame is the last one presented
+    pub fn grab(&self,
+                ctx: &Context,
+                targets: &Targets,
+                target: Target)
+                -> Result<image::RgbaImage, CaptureError> {
+        match target {
+            Target::Frame => {
+                ctx.read_pixels().map(to_image).map_err(|_| CaptureError::ReadBackFailed)
+            }
+            Target::Prepass => Ok(read_color(&targets.prepass)),
+            Target::SSAO => Ok(read_color(&targets.ssao)),
+            Target::Blur => Ok(read_color(&targets.blur)),
+            Target::Depth => self.linear_depth(ctx, &targets.depth),
+        }
+    }
+
+    fn linear_depth(&self,
+                    ctx: &Context,
+                    depth: &texture::DepthTexture2d)
+                    -> Result<image::RgbaImage, CaptureError> {
+        let (w, h) = depth.dimensions();
+        let linear = texture::Texture2d::empty_with_format(ctx.display(),
+                                                           texture::UncompressedFloatFormat::F32F32F32F32,
+                                                           texture::MipmapsOption::NoMipmap,
+                                                           w,
+                                                           h)
+            .map_err(|_| CaptureError::ReadBackFailed)?;
+
+        let (near, far) = ctx.get_depth_range();
+        self.depth_pass.draw(ctx,
+                             &linear,
+                             &uniform! {
+                                 depth_texture: depth,
+                                 near: near,
+                                 far: far,
+                             },
+                             None)
+            .map_err(CaptureError::RenderFailed)?;
+
+        // read() only gives u8 pixels, the depth would be cut before it is stretched.
+        // the texture is rgba32f and reading it as floats is core since gl 3.0
+        let rows: Vec<Vec<(f32, f32, f32, f32)>> =
+            unsafe { linear.unchecked_read::<_, (f32, f32, f32, f32)>() };
+        Ok(stretch_depth(&rows))
+    }
+}
+
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+/// moves tile (i, j) of an n x n grid, counted from the bottom left, to the whole
+/// clip space
+fn tile_projection(n: u32, i: u32, j: u32) -> Matrix4<f32> {
+    let n = n as f32;
+    let offset = |k: u32| n - 1.0 - 2.0 * k as f32;
+    Matrix4::from_translation(Vector3::new(offset(i), offset(j), 0.0)) *
+    Matrix4::from_nonuniform_scale(n, n, 1.0)
+}
+
+/// n times the resolution of the context.
+/// draw must render and present the scene with the given projection, screen space
+/// effects do not see across tiles so seams may show.
+pub fn poster<F>(ctx: &Context, n: u32, draw: &F) -> Result<image::RgbaImage, CaptureError>
+    where F: Fn(Matrix4<f32>) -> Result<(), RenderError>
+{
+    let (w, h) = ctx.get_size();
+    let projection = ctx.get_projection();
+
+    let mut poster = image::RgbaImage::new(w * n, h * n);
+    for j in 0..n {
+        for i in 0..n {
+            draw(tile_projection(n, i, j) * projection).map_err(CaptureError::RenderFailed)?;
+            let tile = ctx.read_pixels()
+                .map(to_image)
+                .map_err(|_| CaptureError::ReadBackFailed)?;
+            image::imageops::replace(&mut poster, &tile, i * w, (n - 1 - j) * h);
+        }
+    }
+    Ok(poster)
+}
+
+pub fn save(img: &image::RgbaImage, path: &Path) -> Result<(), CaptureError> {
+    if let Some(dir) = path.parent() {
+        let _ = fs::create_dir_all(dir);
+    }
+    img.save(path).map_err(|err| CaptureError::SaveFailed(format!("{}: {}", path.display(), err)))
+}
+
+/// first free screenshots/<name>-NNNN.png in the working directory
+pub fn next_path(name: &str) -> PathBuf {
+    next_file(name, "png")
+}
+
+/// as next_path, for other kinds of files
+pub fn next_file(name: &str, extension: &str) -> PathBuf {
+    let dir = PathBuf::from("screenshots");
+    (0..)
+        .map(|n| dir.join(format!("{}-{:04}.{}", name, n, extension)))
+        .find(|path| !path.exists())
+        .unwrap()
+}
+
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+//  Tests
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+#[cfg(test)]
+mod tests {
+
+    use cgmath::Vector4;
+    use super::*;
+
+    #[test]
+    fn tiles_cover_clip_space() {
+        // second column, third row of 4x4: x in [-0.5, 0], y in [0, 0.5]
+        let m = tile_projection(4, 1, 2);
+        let low = m * Vector4::new(-0.5, 0.0, 0.3, 1.0);
+        let high = m * Vector4::new(0.0, 0.5, 0.3, 1.0);
+        assert_eq!((low.x, low.y, low.z), (-1.0, -1.0, 0.3));
+        assert_eq!((high.x, high.y), (1.0, 1.0));
+
+        // a single tile is the projection itself
+        assert_eq!(tile_projection(1, 0, 0), Matrix4::from_scale(1.0));
+    }
+
+    #[test]
+    fn depth_is_stretched() {
+        // two rows, bottom first. the background stays black
+        let rows = vec![vec![(0.2, 0.2, 0.2, 1.0), (1.0, 1.0, 1.0, 1.0)],
+                        vec![(0.4, 0.4, 0.4, 1.0), (0.3, 0.3, 0.3, 1.0)]];
+        let img = stretch_depth(&rows);
+        assert_eq!(img.dimensions(), (2, 2));
+        assert_eq!(img.get_pixel(0, 1).data[0], 255);
+        assert_eq!(img.get_pixel(1, 1).data[0], 0);
+        assert_eq!(img.get_pixel(0, 0).data[0], 0);
+        assert_eq!(img.get_pixel(1, 0).data[0], 127);
+    }
+
+    #[test]
+    fn target_names() {
+        for target in Target::all() {
+            assert_eq!(Target::from_name(target.name()).unwrap(), *target);
+        }
+        assert!(Target::from_name("albedo").is_err());
+    }
+}
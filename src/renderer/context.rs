@@ -1,21 +1,35 @@
 use glium;
+use glium::backend::Facade;
+use glium::draw_parameters::TimeElapsedQuery;
 use glutin;
-use std::collections::BTreeMap;
-//use glium::glutin::HeadlessRendererBuilder;
-
-pub type Backend = glium::backend::glutin::GlutinBackend;
-pub type Display = glium::backend::glutin::Display;
+use cgmath::{Deg, Matrix4, perspective};
+use std::fmt;
+use std::rc::Rc;
+use renderer::uniform_check::check_uniforms;
+use renderer::errors::{ErrorCollector, RenderError};
+use renderer::geometry_manager::GeomertyManager;
+use renderer::texture_manager::TextureManager;
+use renderer::handle::{Geometry, Handle};
+use std::cell::{Ref, RefCell, RefMut};
+
+/// any glium object can be created from it, no matter if we render to a window or offscreen
+pub type Display = Rc<glium::backend::Context>;
 pub type EventsLoop = glutin::EventsLoop;
 pub type VerticesT = glium::vertex::VertexBufferAny;
 pub type IndicesT = glium::index::IndexBufferAny;
 pub type PrimitiveT = glium::index::PrimitiveType;
-pub type IdType = usize;
 
 #[derive(Copy, Clone, Debug)]
 pub enum ManagerError {
     ItemRedefinition,
     FailToCreateContext,
     BackEndErrror,
+    /// the item was released, the handle is of an older generation
+    StaleHandle,
+    /// the geometry was created with another vertex type
+    VertexTypeMismatch,
+    /// the data does not fit in the buffer
+    OutOfRange,
 }
 
 #[derive(Copy, Clone)]
@@ -24,92 +38,549 @@ pub enum RenderType {
     WireFrame,
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Clone, Debug)]
 pub enum ContextError {
     HeadlessNotSupported,
     ContextNotSupported,
+    ReadBackFailed,
+    /// the configuration can not be honored whatever the driver, with the reason
+    InvalidConfig(String),
+    /// the platform could not open the window, with its reason
+    WindowCreationFailed(String),
+    /// the driver does not provide the requested version or profile
+    GlVersionNotSupported(Option<(u8, u8)>, Option<glutin::GlProfile>),
+    /// no pixel format with the requested multisampling or srgb
+    PixelFormatNotSupported { samples: u16, srgb: bool },
+    /// there is a context, but it misses what glium needs
+    IncompatibleOpenGl(String),
 }
 
+impl fmt::Display for ContextError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ContextError::HeadlessNotSupported => write!(f, "offscreen rendering is not supported"),
+            ContextError::ContextNotSupported => write!(f, "could not create a GL context"),
+            ContextError::ReadBackFailed => write!(f, "could not read the framebuffer back"),
+            ContextError::InvalidConfig(ref why) => write!(f, "invalid context config: {}", why),
+            ContextError::WindowCreationFailed(ref why) => {
+                write!(f, "could not create the window: {}", why)
+            }
+            ContextError::GlVersionNotSupported(version, profile) => {
+                write!(f, "the driver does not provide GL")?;
+                if let Some((major, minor)) = version {
+                    write!(f, " {}.{}", major, minor)?;
+                }
+                match profile {
+                    Some(glutin::GlProfile::Core) => write!(f, " core"),
+                    Some(glutin::GlProfile::Compatibility) => write!(f, " compatibility"),
+                    None => Ok(()),
+                }
+            }
+            ContextError::PixelFormatNotSupported { samples, srgb } => {
+                write!(f,
+                       "no pixel format with {} samples{}",
+                       samples,
+                       if srgb { " and srgb" } else { "" })
+            }
+            ContextError::IncompatibleOpenGl(ref why) => write!(f, "unusable GL context: {}", why),
+        }
+    }
+}
 
-/// this class wraps up all render stuff,
-/// glium should not be visible ouside of this... except for buffers?
-/// the idea is to simplify te calls to draw, and wrap all intialization
-pub struct Context {
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+// window and GL options
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
 
-    events_loop: EventsLoop,
-    display: Display,
-    id_cache: BTreeMap<String, IdType>,
-    pub width: u32,
-    pub height: u32,
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum WindowMode {
+    Windowed,
+    /// exclusive fullscreen on the primary monitor
+    Fullscreen,
+    /// undecorated window as big as the primary monitor
+    Borderless,
 }
 
+/// builder for the window context, everything not asked for is left to the driver
+#[derive(Clone, Debug)]
+pub struct ContextConfig {
+    width: u32,
+    height: u32,
+    title: String,
+    mode: WindowMode,
+    vsync: bool,
+    samples: u16,
+    gl_version: Option<(u8, u8)>,
+    gl_profile: Option<glutin::GlProfile>,
+    debug: bool,
+    srgb: bool,
+}
 
-impl Context {
-    pub fn new(width: u32, height: u32) -> Result<Context, ContextError> {
+impl ContextConfig {
+    pub fn new(width: u32, height: u32) -> ContextConfig {
+        ContextConfig {
+            width: width,
+            height: height,
+            title: "Quarfs!".to_string(),
+            mode: WindowMode::Windowed,
+            vsync: false,
+            samples: 0,
+            gl_version: None,
+            gl_profile: None,
+            debug: cfg!(debug_assertions),
+            srgb: false,
+        }
+    }
+
+    pub fn with_title(mut self, title: &str) -> ContextConfig {
+        self.title = title.to_string();
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn with_mode(mut self, mode: WindowMode) -> ContextConfig {
+        self.mode = mode;
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn with_vsync(mut self, vsync: bool) -> ContextConfig {
+        self.vsync = vsync;
+        self
+    }
+
+    #[allow(dead_code)]
+    /// 0 disables multisampling, otherwise a power of two
+    pub fn with_multisampling(mut self, samples: u16) -> ContextConfig {
+        self.samples = samples;
+        self
+    }
+
+    pub fn with_gl_version(mut self, major: u8, minor: u8) -> ContextConfig {
+        self.gl_version = Some((major, minor));
+        self
+    }
+
+    pub fn with_gl_profile(mut self, profile: glutin::GlProfile) -> ContextConfig {
+        self.gl_profile = Some(profile);
+        self
+    }
+
+    #[allow(dead_code)]
+    /// debug contexts report driver messages, on by default in debug builds
+    pub fn with_debug(mut self, debug: bool) -> ContextConfig {
+        self.debug = debug;
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn with_srgb(mut self, srgb: bool) -> ContextConfig {
+        self.srgb = srgb;
+        self
+    }
+
+    fn validate(&self) -> Result<(), ContextError> {
+        if self.width == 0 || self.height == 0 {
+            return Err(ContextError::InvalidConfig(format!("window size {}x{}",
+                                                           self.width,
+                                                           self.height)));
+        }
+        // glutin panics on anything else
+        if self.samples != 0 && !self.samples.is_power_of_two() {
+            let why = format!("{} samples, it must be a power of two", self.samples);
+            return Err(ContextError::InvalidConfig(why));
+        }
+        Ok(())
+    }
+
+    fn map_creation_error(&self, err: glium::backend::glutin::DisplayCreationError) -> ContextError {
+        use glium::backend::glutin::DisplayCreationError;
+        match err {
+            DisplayCreationError::GlutinCreationError(err) => {
+                match err {
+                    glutin::CreationError::OpenGlVersionNotSupported => {
+                        ContextError::GlVersionNotSupported(self.gl_version, self.gl_profile)
+                    }
+                    glutin::CreationError::NoAvailablePixelFormat => {
+                        ContextError::PixelFormatNotSupported {
+                            samples: self.samples,
+                            srgb: self.srgb,
+                        }
+                    }
+                    err => ContextError::WindowCreationFailed(format!("{}", err)),
+                }
+            }
+            DisplayCreationError::IncompatibleOpenGl(err) => {
+                ContextError::IncompatibleOpenGl(format!("{:?}", err))
+            }
+        }
+    }
+
+    /// opens the window
+    pub fn build(self) -> Result<Context, ContextError> {
+        self.validate()?;
 
         let events_loop = glutin::EventsLoop::new();
+        let monitor = events_loop.get_primary_monitor();
+
+        let mut window_builder = glutin::WindowBuilder::new().with_title(self.title.clone());
+        window_builder = match self.mode {
+            WindowMode::Windowed => window_builder.with_dimensions(self.width, self.height),
+            WindowMode::Fullscreen => window_builder.with_fullscreen(Some(monitor)),
+            WindowMode::Borderless => {
+                let (w, h) = monitor.get_dimensions();
+                window_builder.with_decorations(false).with_dimensions(w, h)
+            }
+        };
 
-        let window_builder = glutin::WindowBuilder::new()
-            .with_title("Quarfs!")
-            .with_dimensions(width, height);
+        let mut context = glutin::ContextBuilder::new()
+            .with_vsync(self.vsync)
+            .with_gl_debug_flag(self.debug)
+            .with_srgb(self.srgb);
+        if self.samples != 0 {
+            context = context.with_multisampling(self.samples);
+        }
+        if let Some((major, minor)) = self.gl_version {
+            context = context.with_gl(glutin::GlRequest::Specific(glutin::Api::OpenGl,
+                                                                  (major, minor)));
+        }
+        if let Some(profile) = self.gl_profile {
+            context = context.with_gl_profile(profile);
+        }
 
-        let context = glutin::ContextBuilder::new();
-        let display = glium::Display::new(window_builder, context, &events_loop).unwrap();
+        let window = glium::Display::new(window_builder, context, &events_loop)
+            .map_err(|err| self.map_creation_error(err))?;
+        let display = window.get_context().clone();
+        let get_integer = {
+            use glutin::GlContext;
+            window.gl_window().get_proc_address("glGetIntegerv")
+        };
+        let capabilities = Capabilities::query(&display, get_integer);
+        // the window may not be as big as requested
+        let (width, height) = window.get_framebuffer_dimensions();
 
         Ok(Context {
-            events_loop : events_loop,
+            surface: Surface::Window {
+                window: window,
+                events_loop: events_loop,
+            },
             display: display,
-            id_cache: BTreeMap::new(),
+            capabilities: capabilities,
+            projection: Projection::default(),
+            errors: RefCell::new(ErrorCollector::new()),
+            geometry: RefCell::new(GeomertyManager::new()),
+            textures: RefCell::new(TextureManager::new()),
             width: width,
             height: height,
         })
     }
+}
 
-    #[cfg(test)]
-    pub fn new_headless(w: u32, h: u32) -> Result<Context, ContextError> {
-        use glium::DisplayBuild;
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+// what the driver gave us
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
 
-        let display = glium::glutin::HeadlessRendererBuilder::new(w, h).build_glium();
+#[derive(Clone, Debug)]
+pub struct Capabilities {
+    pub version: (u8, u8),
+    pub profile: Option<glium::Profile>,
+    pub vendor: String,
+    pub renderer: String,
+    pub debug: bool,
+    /// GL_MAX_TESS_GEN_LEVEL, none without tessellation shaders
+    pub max_tessellation_level: Option<u32>,
+    pub max_patch_vertices: Option<u32>,
+    pub max_texture_size: u32,
+}
 
-        if display.is_err() {
-            return Err(ContextError::HeadlessNotSupported);
+const GL_MAX_TEXTURE_SIZE: u32 = 0x0D33;
+const GL_MAX_PATCH_VERTICES: u32 = 0x8E7D;
+const GL_MAX_TESS_GEN_LEVEL: u32 = 0x8E7E;
+
+impl Capabilities {
+    /// glium does not expose these limits, they are asked with glGetIntegerv
+    /// on the current context
+    fn query(display: &Display, proc_address: *const ()) -> Capabilities {
+        use std::mem;
+        type GetIntegerv = extern "system" fn(u32, *mut i32);
+
+        let get = |name: u32| -> Option<u32> {
+            if proc_address.is_null() {
+                return None;
+            }
+            let mut value: i32 = 0;
+            unsafe {
+                let get_integer: GetIntegerv = mem::transmute(proc_address);
+                get_integer(name, &mut value);
+            }
+            if value > 0 { Some(value as u32) } else { None }
+        };
+
+        let glium::Version(_, major, minor) = *display.get_opengl_version();
+        // asking for an unknown limit would leave an error behind for glium to find
+        let tessellation = major >= 4;
+
+        Capabilities {
+            version: (major, minor),
+            profile: display.get_opengl_profile(),
+            vendor: display.get_opengl_vendor_string().to_string(),
+            renderer: display.get_opengl_renderer_string().to_string(),
+            debug: display.is_debug(),
+            max_tessellation_level: if tessellation { get(GL_MAX_TESS_GEN_LEVEL) } else { None },
+            max_patch_vertices: if tessellation { get(GL_MAX_PATCH_VERTICES) } else { None },
+            max_texture_size: get(GL_MAX_TEXTURE_SIZE).unwrap_or(0),
         }
+    }
+}
+
+impl fmt::Display for Capabilities {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "GL {}.{}", self.version.0, self.version.1)?;
+        match self.profile {
+            Some(glium::Profile::Core) => write!(f, " core")?,
+            Some(glium::Profile::Compatibility) => write!(f, " compatibility")?,
+            None => {}
+        }
+        if self.debug {
+            write!(f, " debug")?;
+        }
+        write!(f, " on {} ({})", self.renderer, self.vendor)?;
+        write!(f, ", max texture size {}", self.max_texture_size)?;
+        match self.max_tessellation_level {
+            Some(level) => write!(f, ", max tessellation level {}", level),
+            None => write!(f, ", no tessellation"),
+        }
+    }
+}
+
+
+/// perspective parameters, the aspect ratio follows the window
+#[derive(Copy, Clone, Debug)]
+struct Projection {
+    fov: Deg<f32>,
+    near: f32,
+    far: f32,
+}
+
+impl Default for Projection {
+    fn default() -> Projection {
+        Projection {
+            fov: Deg(45.0),
+            near: 1.0,
+            far: 1000.0,
+        }
+    }
+}
+
+/// what keeps the GL context alive, a window or an offscreen buffer
+enum Surface {
+    Window {
+        window: glium::Display,
+        events_loop: EventsLoop,
+    },
+    Headless(glium::HeadlessRenderer),
+}
+
+/// this class wraps up all render stuff,
+/// glium should not be visible ouside of this... except for buffers?
+/// the idea is to simplify te calls to draw, and wrap all intialization
+pub struct Context {
+
+    surface: Surface,
+    display: Display,
+    capabilities: Capabilities,
+    projection: Projection,
+    errors: RefCell<ErrorCollector>,
+    geometry: RefCell<GeomertyManager>,
+    textures: RefCell<TextureManager>,
+    pub width: u32,
+    pub height: u32,
+}
+
+
+impl Context {
+    /// window with the default options, see ContextConfig for the rest
+    pub fn new(width: u32, height: u32) -> Result<Context, ContextError> {
+        ContextConfig::new(width, height).build()
+    }
+
+    /// offscreen context, no window and no events. glutin provides it through OSMesa,
+    /// so it runs on machines without GPU (llvmpipe).
+    /// we ask for the 4.1 core the tessellation shaders need, and take whatever the
+    /// driver gives if that fails.
+    pub fn new_headless(width: u32, height: u32) -> Result<Context, ContextError> {
+        let core = glutin::HeadlessRendererBuilder::new(width, height)
+            .with_gl(glutin::GlRequest::Specific(glutin::Api::OpenGl, (4, 1)))
+            .with_gl_profile(glutin::GlProfile::Core)
+            .build();
+        let context = match core {
+            Ok(context) => context,
+            Err(_) => {
+                glutin::HeadlessRendererBuilder::new(width, height)
+                    .build()
+                    .map_err(|_| ContextError::HeadlessNotSupported)?
+            }
+        };
+        let get_integer = {
+            use glutin::GlContext;
+            context.get_proc_address("glGetIntegerv")
+        };
+        let renderer = glium::HeadlessRenderer::new(context)
+            .map_err(|_| ContextError::ContextNotSupported)?;
+        let display = renderer.get_context().clone();
+        let capabilities = Capabilities::query(&display, get_integer);
 
         Ok(Context {
-            display: display.unwrap(),
-            id_cache: BTreeMap::new(),
-            width: 0,
-            height: 0,
+            surface: Surface::Headless(renderer),
+            display: display,
+            capabilities: capabilities,
+            projection: Projection::default(),
+            errors: RefCell::new(ErrorCollector::new()),
+            geometry: RefCell::new(GeomertyManager::new()),
+            textures: RefCell::new(TextureManager::new()),
+            width: width,
+            height: height,
         })
     }
 
-    pub fn get_id_for(&mut self, name: &str) -> IdType {
-        if let Some(x) = self.id_cache.get(&name.to_string()) {
-            return *x;
+    pub fn is_headless(&self) -> bool {
+        match self.surface {
+            Surface::Headless(_) => true,
+            Surface::Window { .. } => false,
         }
-        let id = self.id_cache.len();
-        self.id_cache.insert(name.to_string(), id);
-        id
     }
 
     pub fn display(&self) -> &Display {
         &self.display
     }
 
-    pub fn events_loop(&mut self) -> &mut EventsLoop{
-        &mut self.events_loop
+    /// version and limits negotiated with the driver
+    pub fn capabilities(&self) -> &Capabilities {
+        &self.capabilities
     }
 
-    pub fn resize(&mut self, w: u32, h: u32) {
+    /// window events, there are none for an offscreen context
+    pub fn events_loop(&mut self) -> Option<&mut EventsLoop> {
+        match self.surface {
+            Surface::Window { ref mut events_loop, .. } => Some(events_loop),
+            Surface::Headless(_) => None,
+        }
+    }
+
+    /// the window, if there is one
+    pub fn window(&self) -> Option<&glium::Display> {
+        match self.surface {
+            Surface::Window { ref window, .. } => Some(window),
+            Surface::Headless(_) => None,
+        }
+    }
+
+    /// the window changed its size, the projection follows.
+    /// returns true if the size changed, screen sized targets need to be allocated again.
+    /// a minimized window reports 0x0, and the offscreen buffer can not grow, both are ignored.
+    pub fn resize(&mut self, w: u32, h: u32) -> bool {
+        if w == 0 || h == 0 || (w, h) == (self.width, self.height) || self.is_headless() {
+            return false;
+        }
         println!("resize {}x{}", w, h);
         self.width = w;
         self.height = h;
-        //      TODO: change here the perspective matrix (which should be owned by ctx)
+        true
+    }
+
+    pub fn set_projection(&mut self, fov: Deg<f32>, near: f32, far: f32) {
+        self.projection = Projection {
+            fov: fov,
+            near: near,
+            far: far,
+        };
+    }
+
+    /// render errors panic right away, for tests
+    pub fn set_strict(&mut self, strict: bool) {
+        self.errors.borrow_mut().set_strict(strict);
+    }
+
+    /// keeps the error for the report of this frame, and hands it back
+    pub fn report_error(&self, err: RenderError) -> RenderError {
+        self.errors.borrow_mut().push(&err);
+        err
+    }
+
+    /// buffers drawn by handle, see DrawSurface::draw_geometry
+    pub fn geometry(&self) -> Ref<GeomertyManager> {
+        self.geometry.borrow()
+    }
+
+    /// to create, update and remove geometry. not while drawing
+    pub fn geometry_mut(&self) -> RefMut<GeomertyManager> {
+        self.geometry.borrow_mut()
+    }
+
+    /// images by handle, to be sampled in the uniforms
+    pub fn textures(&self) -> Ref<TextureManager> {
+        self.textures.borrow()
+    }
+
+    /// to create and release textures. not while the uniforms borrow them
+    pub fn textures_mut(&self) -> RefMut<TextureManager> {
+        self.textures.borrow_mut()
+    }
+
+    fn end_frame(&self) {
+        if let Some(report) = self.errors.borrow_mut().end_frame() {
+            println!("{}", report);
+        }
+    }
+
+    /// near and far planes of the projection
+    pub fn get_depth_range(&self) -> (f32, f32) {
+        (self.projection.near, self.projection.far)
+    }
+
+    /// perspective matrix for the current size
+    pub fn get_projection(&self) -> Matrix4<f32> {
+        let Projection { fov, near, far } = self.projection;
+        perspective(fov, self.width as f32 / self.height as f32, near, far)
     }
 
     pub fn get_size(&self) -> (u32, u32) {
         (self.width, self.height)
     }
+
+    /// size of the default framebuffer, the offscreen one is as big as requested
+    fn framebuffer_size(&self) -> (u32, u32) {
+        match self.surface {
+            Surface::Window { .. } => self.display.get_framebuffer_dimensions(),
+            Surface::Headless(_) => (self.width, self.height),
+        }
+    }
+
+    /// what ended on screen in the last frame, rgba rows from the bottom up
+    pub fn read_pixels(&self) -> Result<glium::texture::RawImage2d<'static, u8>, ContextError> {
+        use glium::Surface as GlSurface;
+
+        if let Surface::Window { .. } = self.surface {
+            return Ok(self.display.read_front_buffer::<glium::texture::RawImage2d<u8>>());
+        }
+
+        // the offscreen backend does not know its size, copy the area we asked for
+        let (w, h) = (self.width, self.height);
+        let texture = glium::texture::Texture2d::empty(&self.display, w, h)
+            .map_err(|_| ContextError::ReadBackFailed)?;
+        let frame = glium::Frame::new(self.display.clone(), (w, h));
+        frame.blit_whole_color_to(&texture.as_surface(),
+                                  &glium::BlitTarget {
+                                      left: 0,
+                                      bottom: 0,
+                                      width: w as i32,
+                                      height: h as i32,
+                                  },
+                                  glium::uniforms::MagnifySamplerFilter::Nearest);
+        // there is nothing to swap offscreen
+        frame.finish().map_err(|_| ContextError::ReadBackFailed)?;
+        Ok(texture.read())
+    }
 } // context
 
 // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
@@ -117,24 +588,30 @@ impl Context {
 // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
 
 pub struct DrawSurface<'a> {
-    //   ctx   : &'a Context,
-    target: glium::Frame,
+    ctx: &'a Context,
+    /// taken by gl_end, a dropped surface finishes the frame too
+    target: Option<glium::Frame>,
     render_params: glium::DrawParameters<'a>,
 }
 
 impl<'a> DrawSurface<'a> {
     pub fn get_frame(&mut self) -> &mut glium::Frame {
-        &mut self.target
+        self.target.as_mut().unwrap()
+    }
+
+    /// the frame to draw on and the parameters for it, borrowed at the same time
+    fn frame_and_params(&mut self) -> (&mut glium::Frame, &glium::DrawParameters<'a>) {
+        (self.target.as_mut().unwrap(), &self.render_params)
     }
 
     #[inline]
     pub fn gl_begin(ctx: &'a Context, render_type: RenderType) -> DrawSurface<'a> {
         use glium::Surface;
-        let mut target = ctx.display().draw();
+        let mut target = glium::Frame::new(ctx.display().clone(), ctx.framebuffer_size());
         target.clear_color_and_depth((0.2, 0.5, 0.4, 1.0), 1.0);
         DrawSurface {
-            //       ctx: ctx,
-            target: target,
+            ctx: ctx,
+            target: Some(target),
             render_params: glium::DrawParameters {
                 backface_culling: glium::BackfaceCullingMode::CullClockwise,
                 depth: glium::Depth {
@@ -151,102 +628,219 @@ impl<'a> DrawSurface<'a> {
         }
     }
 
+    /// the draws from now on are timed with the query, until it is changed again
+    pub fn set_time_query(&mut self, query: Option<&'a TimeElapsedQuery>) {
+        self.render_params.time_elapsed_query = query;
+    }
+
+    /// the error goes to the context collector as well
+    fn checked(&self,
+               program: &str,
+               result: Result<(), glium::DrawError>)
+               -> Result<(), RenderError> {
+        result.map_err(|err| self.ctx.report_error(RenderError::draw(program, &err)))
+    }
+
     #[inline]
-    pub fn draw<O, U>(&mut self, obj: &O, uniforms: &U)
+    #[allow(dead_code)]
+    pub fn draw<O, U>(&mut self, obj: &O, uniforms: &U) -> Result<(), RenderError>
         where O: DrawItem + Program,
               U: glium::uniforms::Uniforms
     {
-        // println!("a");
         use glium::Surface;
-        self.target
-            .draw(obj.get_vertices(),
-                  glium::index::NoIndices(obj.get_primitive()),
-                  obj.get_program(),
-                  uniforms,
-                  &self.render_params)
-            .unwrap();
+        check_uniforms(obj.get_program(), obj.get_source(), uniforms);
+        let result = {
+            let (frame, params) = self.frame_and_params();
+            frame.draw(obj.get_vertices(),
+                       glium::index::NoIndices(obj.get_primitive()),
+                       obj.get_program(),
+                       uniforms,
+                       params)
+        };
+        self.checked(obj.get_source(), result)
+    }
+
+    /// geometry of the context, a stale handle is an error
+    pub fn draw_geometry<P, U>(&mut self,
+                               id: Handle<Geometry>,
+                               prg: &P,
+                               uniforms: &U)
+                               -> Result<(), RenderError>
+        where P: Program,
+              U: glium::uniforms::Uniforms
+    {
+        use glium::Surface;
+        let ctx = self.ctx;
+        let geometry = ctx.geometry();
+        let geom = match geometry.get_geom(id) {
+            Some(geom) => geom,
+            None => return Err(ctx.report_error(RenderError::Geometry(format!("{:?}", id)))),
+        };
+
+        check_uniforms(prg.get_program(), prg.get_source(), uniforms);
+        let result = {
+            let (frame, params) = self.frame_and_params();
+            frame.draw(geom.get_vertices(),
+                       geom.get_indices(),
+                       prg.get_program(),
+                       uniforms,
+                       params)
+        };
+        self.checked(prg.get_source(), result)
+    }
+
+    /// once per vertex of the instances geometry
+    pub fn draw_geometry_instanced<P, U>(&mut self,
+                                         id: Handle<Geometry>,
+                                         instances: Handle<Geometry>,
+                                         prg: &P,
+                                         uniforms: &U)
+                                         -> Result<(), RenderError>
+        where P: Program,
+              U: glium::uniforms::Uniforms
+    {
+        use glium::Surface;
+        let ctx = self.ctx;
+        let geometry = ctx.geometry();
+        let (geom, per_instance) = match (geometry.get_geom(id), geometry.get_geom(instances)) {
+            (Some(geom), Some(inst)) => {
+                match inst.get_per_instance() {
+                    Ok(per_instance) => (geom, per_instance),
+                    Err(err) => {
+                        return Err(ctx.report_error(RenderError::draw(prg.get_source(), &err)))
+                    }
+                }
+            }
+            _ => {
+                let err = RenderError::Geometry(format!("{:?} or {:?}", id, instances));
+                return Err(ctx.report_error(err));
+            }
+        };
+
+        check_uniforms(prg.get_program(), prg.get_source(), uniforms);
+        let result = {
+            let (frame, params) = self.frame_and_params();
+            frame.draw((geom.get_vertices(), per_instance),
+                       geom.get_indices(),
+                       prg.get_program(),
+                       uniforms,
+                       params)
+        };
+        self.checked(prg.get_source(), result)
     }
 
     #[inline]
+    #[allow(dead_code)]
     pub fn draw_instanciated_with_indices_and_program<O, P, U>(&mut self,
                                                                obj: &O,
                                                                instances: &VerticesT,
                                                                prg: &P,
                                                                uniforms: &U)
+                                                               -> Result<(), RenderError>
         where O: DrawIndexed,
               P: Program,
               U: glium::uniforms::Uniforms
     {
-        // println!("b");
         use glium::Surface;
-        self.target
-            .draw((obj.get_vertices(), instances.per_instance().unwrap()),
-                  obj.get_indices(),
-                  prg.get_program(),
-                  uniforms,
-                  &self.render_params)
-            .unwrap();
+        check_uniforms(prg.get_program(), prg.get_source(), uniforms);
+        let per_instance = match instances.per_instance() {
+            Ok(per_instance) => per_instance,
+            Err(err) => {
+                let err = RenderError::draw(prg.get_source(), &err);
+                return Err(self.ctx.report_error(err));
+            }
+        };
+        let result = {
+            let (frame, params) = self.frame_and_params();
+            frame.draw((obj.get_vertices(), per_instance),
+                       obj.get_indices(),
+                       prg.get_program(),
+                       uniforms,
+                       params)
+        };
+        self.checked(prg.get_source(), result)
     }
 
     #[inline]
     #[allow(dead_code)]
-    pub fn draw_with_indices_and_program<O, P, U>(&mut self, obj: &O, prg: &P, uniforms: &U)
+    pub fn draw_with_indices_and_program<O, P, U>(&mut self,
+                                                  obj: &O,
+                                                  prg: &P,
+                                                  uniforms: &U)
+                                                  -> Result<(), RenderError>
         where O: DrawIndexed,
               P: Program,
               U: glium::uniforms::Uniforms
     {
-        // println!("b");
         use glium::Surface;
-        let x = self.target.draw(obj.get_vertices(),
-                                 obj.get_indices(),
-                                 prg.get_program(),
-                                 uniforms,
-                                 &self.render_params);
-
-        if let Err(err) = x {
-            println!("render error: {:?}", err);
-        }
+        check_uniforms(prg.get_program(), prg.get_source(), uniforms);
+        let result = {
+            let (frame, params) = self.frame_and_params();
+            frame.draw(obj.get_vertices(),
+                       obj.get_indices(),
+                       prg.get_program(),
+                       uniforms,
+                       params)
+        };
+        self.checked(prg.get_source(), result)
     }
 
-    pub fn draw_overlay_quad<O, T>(&mut self, quad: &O, texture: T, is_depth: bool)
+    /// quad on top of everything else, in the given rectangle of the window
+    pub fn draw_overlay_quad<O, U>(&mut self,
+                                   quad: &O,
+                                   viewport: glium::Rect,
+                                   uniforms: &U)
+                                   -> Result<(), RenderError>
         where O: DrawItem + Program,
-              T: glium::uniforms::AsUniformValue
+              U: glium::uniforms::Uniforms
     {
-
-        // println!("c");
         use glium::Surface;
+        check_uniforms(quad.get_program(), quad.get_source(), uniforms);
 
-        // generate uniforms because i doint know how to return the uniforms type
-        let quad_uniforms = uniform! {
-            quad_texture: texture,
-            is_depth: is_depth,
-        };
-
-        self.target
+        let query = self.render_params.time_elapsed_query;
+        let result = self.get_frame()
             .draw(quad.get_vertices(),
                   glium::index::NoIndices(quad.get_primitive()),
                   quad.get_program(),
-                  &quad_uniforms,
+                  uniforms,
                   &glium::DrawParameters {
-                      // backface_culling: glium::BackfaceCullingMode::CullClockwise,
-                      viewport: Some(glium::Rect {
-                          left: 10,
-                          bottom: 10,
-                          width: 640,
-                          height: 480,
-                      }),
+                      blend: glium::Blend::alpha_blending(),
+                      viewport: Some(viewport),
+                      time_elapsed_query: query,
                       ..Default::default()
-                  })
-            .unwrap();
-        // println!(" == ");
+                  });
+        self.checked(quad.get_source(), result)
+    }
+
+    /// presents the frame, the errors of the frame are reported if they changed
+    fn finish(&mut self) -> Result<(), RenderError> {
+        let target = match self.target.take() {
+            Some(target) => target,
+            None => return Ok(()),
+        };
+        let result = target.finish()
+            .map_err(|err| self.ctx.report_error(RenderError::Finish(format!("{:?}", err))));
+        self.ctx.end_frame();
+        result
     }
 
     #[inline]
-    pub fn gl_end(self) {
-        self.target.finish().unwrap();
+    pub fn gl_end(mut self) -> Result<(), RenderError> {
+        self.finish()
     }
 } // impl ctx
 
+impl<'a> Drop for DrawSurface<'a> {
+    /// glium panics if a frame is dropped unfinished, this happens when a draw error
+    /// makes the caller leave early
+    fn drop(&mut self) {
+        // while unwinding glium does not complain, and strict mode would panic again
+        if !::std::thread::panicking() {
+            let _ = self.finish();
+        }
+    }
+}
+
 
 // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
 // Traits:
@@ -255,6 +849,10 @@ impl<'a> DrawSurface<'a> {
 pub trait Program {
     fn get_program(&self) -> &glium::program::Program;
     fn with_tess(&self) -> bool;
+    /// the shader file, used to report problems with the program
+    fn get_source(&self) -> &str {
+        "<inline program>"
+    }
 }
 
 impl Program for glium::program::Program {
@@ -277,3 +875,66 @@ pub trait DrawIndexed {
     fn get_vertices(&self) -> &VerticesT;
     fn get_indices(&self) -> &IndicesT;
 }
+
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+//  Tests
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn invalid_config() {
+        match ContextConfig::new(640, 480).with_multisampling(3).build() {
+            Err(ContextError::InvalidConfig(_)) => {}
+            Err(err) => panic!("unexpected error {}", err),
+            Ok(_) => panic!("3 samples accepted"),
+        }
+        match ContextConfig::new(0, 480).build() {
+            Err(ContextError::InvalidConfig(_)) => {}
+            Err(err) => panic!("unexpected error {}", err),
+            Ok(_) => panic!("empty window accepted"),
+        }
+    }
+
+    #[test]
+    fn projection_follows_size() {
+        let mut ctx = Context::new_headless(64, 32).expect("create headless context");
+        ctx.set_projection(Deg(60.0), 1.0, 100.0);
+        let m = ctx.get_projection();
+        assert!((m[1][1] / m[0][0] - 2.0).abs() < 1e-5);
+
+        // the offscreen buffer keeps its size
+        assert!(!ctx.resize(128, 128));
+        assert!(!ctx.resize(0, 0));
+        assert_eq!(ctx.get_size(), (64, 32));
+    }
+
+    #[test]
+    fn headless_read_back() {
+        let ctx = Context::new_headless(64, 32).expect("create headless context");
+        assert!(ctx.is_headless());
+        assert!(ctx.window().is_none());
+
+        // gl_begin clears to the background color
+        let surface = DrawSurface::gl_begin(&ctx, RenderType::Textured);
+        surface.gl_end().expect("finish frame");
+
+        // the terrain needs tessellation
+        let caps = ctx.capabilities();
+        assert!(caps.max_texture_size >= 1024, "{}", caps);
+        if caps.version >= (4, 0) {
+            assert!(caps.max_tessellation_level.unwrap_or(0) >= 64, "{}", caps);
+        }
+
+        let pixels = ctx.read_pixels().expect("read back");
+        assert_eq!((pixels.width, pixels.height), (64, 32));
+        // gl rounds 0.5 * 255 either way
+        let expected = [51, 127, 102, 255];
+        for (got, expected) in pixels.data[0..4].iter().zip(expected.iter()) {
+            assert!((*got as i32 - *expected as i32).abs() <= 1, "{:?}", &pixels.data[0..4]);
+        }
+    }
+}
@@ -4,6 +4,7 @@
 extern crate glium;
 
 use renderer::context;
+use renderer::handle::{Geometry, Handle};
 use super::los::Los;
 
 // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
@@ -17,6 +18,8 @@ struct LosVert {
 
 pub struct LosQuad {
     los_program: glium::Program,
+    /// lines around the patches, written again on every update
+    geometry: Handle<Geometry>,
 }
 
 
@@ -52,13 +55,27 @@ impl LosQuad {
                                         None)
                 .unwrap();
 
-        LosQuad { los_program: los_program }
+        let geometry = {
+            let mut manager = ctx.geometry_mut();
+            let name = manager.unique_name("los");
+            manager.create_dynamic_geom(ctx,
+                                        &name,
+                                        &[LosVert { position: (0.0, 0.0) }],
+                                        None,
+                                        glium::index::PrimitiveType::LinesList)
+                .unwrap()
+        };
+
+        LosQuad {
+            los_program: los_program,
+            geometry: geometry,
+        }
     } // new
 
-    pub fn get_drawable<'s>(&'s self, ctx: &context::Context, los: &Los) -> LosQuadDraw<'s> {
+    /// the lines of the patches of this los, draw them with get_geometry
+    pub fn update(&self, ctx: &context::Context, los: &Los) {
 
         // generate vertices out of patches!
-        // yes, i know this should be cached or something, but for now it will make the job
 
         let (w, h) = los.dimensions();
         let patches = los.get_patches();
@@ -84,36 +101,24 @@ impl LosQuad {
             vertices.push(LosVert { position: (point.0, point.1) });
         }
 
-        let vert_buffer = glium::VertexBuffer::new(ctx.display(), vertices.as_slice()).unwrap();
-        LosQuadDraw::<'s> {
-            quad: self,
-            vert_buffer: vert_buffer.into(),
-        }
+        ctx.geometry_mut().replace_vertices(ctx, self.geometry, &vertices).unwrap();
     }
-}
-
-pub struct LosQuadDraw<'a> {
-    quad: &'a LosQuad,
-    vert_buffer: glium::vertex::VertexBufferAny,
-}
 
-
-use renderer::context::{DrawItem, Program};
-
-impl<'a> DrawItem for LosQuadDraw<'a> {
-    fn get_vertices(&self) -> &glium::vertex::VertexBufferAny {
-        &self.vert_buffer
-    }
-    fn get_primitive(&self) -> glium::index::PrimitiveType {
-        glium::index::PrimitiveType::LinesList
+    pub fn get_geometry(&self) -> Handle<Geometry> {
+        self.geometry
     }
 }
 
-impl<'a> Program for LosQuadDraw<'a> {
+use renderer::context::Program;
+
+impl Program for LosQuad {
     fn get_program(&self) -> &glium::Program {
-        &self.quad.los_program
+        &self.los_program
     }
     fn with_tess(&self) -> bool {
-        self.quad.los_program.has_tessellation_shaders()
+        self.los_program.has_tessellation_shaders()
+    }
+    fn get_source(&self) -> &str {
+        "culing::LosQuad"
     }
 }
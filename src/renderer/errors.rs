@@ -0,0 +1,152 @@
+use std::fmt;
+
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+//    Render errors,
+//    every draw returns them, and the context also keeps the ones of the
+//    current frame. the same error every frame is reported once, grouped with
+//    a count, and only again when the errors of a frame change.
+//    strict mode panics on the first error, tests want to fail right there.
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum RenderError {
+    /// a draw call failed, with the source of the program used
+    Draw { program: String, message: String },
+    /// a framebuffer for a render target could not be built
+    Target(String),
+    /// the frame could not be presented
+    Finish(String),
+    /// the handle is stale, the geometry was removed
+    Geometry(String),
+}
+
+impl RenderError {
+    pub fn draw(program: &str, err: &fmt::Debug) -> RenderError {
+        RenderError::Draw {
+            program: program.to_string(),
+            message: format!("{:?}", err),
+        }
+    }
+}
+
+impl fmt::Display for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RenderError::Draw { ref program, ref message } => {
+                write!(f, "draw with {} failed: {}", program, message)
+            }
+            RenderError::Target(ref why) => write!(f, "render target: {}", why),
+            RenderError::Finish(ref why) => write!(f, "could not present the frame: {}", why),
+            RenderError::Geometry(ref handle) => write!(f, "no geometry for {}", handle),
+        }
+    }
+}
+
+pub struct ErrorCollector {
+    strict: bool,
+    frame: Vec<(RenderError, usize)>,
+    last_report: Vec<(RenderError, usize)>,
+}
+
+impl ErrorCollector {
+    pub fn new() -> ErrorCollector {
+        ErrorCollector {
+            strict: false,
+            frame: Vec::new(),
+            last_report: Vec::new(),
+        }
+    }
+
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    pub fn push(&mut self, err: &RenderError) {
+        if self.strict {
+            panic!("render error: {}", err);
+        }
+        if let Some(entry) = self.frame.iter_mut().find(|&&mut (ref e, _)| e == err) {
+            entry.1 += 1;
+            return;
+        }
+        self.frame.push((err.clone(), 1));
+    }
+
+    /// errors of the current frame, with how many times each happened
+    #[allow(dead_code)]
+    pub fn frame_errors(&self) -> &[(RenderError, usize)] {
+        &self.frame
+    }
+
+    /// closes the frame. returns the report if it is not the same as the last one
+    pub fn end_frame(&mut self) -> Option<String> {
+        let frame: Vec<(RenderError, usize)> = self.frame.drain(..).collect();
+        if frame == self.last_report {
+            return None;
+        }
+        self.last_report = frame;
+        if self.last_report.is_empty() {
+            return Some("render errors gone".to_string());
+        }
+
+        let lines: Vec<String> = self.last_report
+            .iter()
+            .map(|&(ref err, count)| if count > 1 {
+                format!("  {} (x{})", err, count)
+            } else {
+                format!("  {}", err)
+            })
+            .collect();
+        Some(format!("render errors:\n{}", lines.join("\n")))
+    }
+}
+
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+//  Tests
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn err(message: &str) -> RenderError {
+        RenderError::Draw {
+            program: "shaders/test.glsl".to_string(),
+            message: message.to_string(),
+        }
+    }
+
+    #[test]
+    fn grouped_per_frame() {
+        let mut errors = ErrorCollector::new();
+        assert_eq!(errors.end_frame(), None);
+
+        errors.push(&err("UniformTypeMismatch"));
+        errors.push(&err("UniformTypeMismatch"));
+        errors.push(&err("ViewportTooLarge"));
+        assert_eq!(errors.frame_errors().len(), 2);
+
+        let report = errors.end_frame().expect("report");
+        assert!(report.contains("UniformTypeMismatch (x2)"), "{}", report);
+        assert!(errors.frame_errors().is_empty());
+
+        // the same frame again is not reported
+        errors.push(&err("UniformTypeMismatch"));
+        errors.push(&err("UniformTypeMismatch"));
+        errors.push(&err("ViewportTooLarge"));
+        assert_eq!(errors.end_frame(), None);
+
+        // and a clean frame says so once
+        assert!(errors.end_frame().is_some());
+        assert_eq!(errors.end_frame(), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "render error")]
+    fn strict() {
+        let mut errors = ErrorCollector::new();
+        errors.set_strict(true);
+        errors.push(&err("UniformTypeMismatch"));
+    }
+}
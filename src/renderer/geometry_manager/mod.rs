@@ -1,27 +1,38 @@
-
 use glium::vertex::*;
 use glium::index::*;
 
 use super::context::Context;
-use super::context::IdType;
 use super::context::ManagerError;
+use super::handle::{Geometry, Handle, Registry};
 
-use std::marker::PhantomData;
-use std::collections::BTreeMap;
+use std::any::Any;
 use std::ops::Deref;
 
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+//    Geometry manager,
+//    vertex and index buffers live here, the rest of the program keeps a
+//    handle. the context owns one, see Context::geometry.
+//    dynamic geometry is meant to be written every now and then, update
+//    writes in place, replace allocates a buffer of the new size.
+//    the vertex type is checked when updating, not when drawing.
 // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
 
-pub type VerticesT = VertexBufferAny;
 pub type IndicesBufT = IndexBufferAny;
 pub type PrimitiveT = PrimitiveType;
 
+/// the buffer can not be read per instance. glium does not export its own
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct InstancingNotSupported;
+
 // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
 
-trait Geomerty {
-    fn get_vertices(&self) -> &VerticesT;
-    fn get_indices(&self) -> &IndicesT;
-    fn get_primitive(&self) -> &PrimitiveT;
+pub trait Geomerty {
+    fn get_vertices(&self) -> VerticesSource;
+    fn get_per_instance(&self) -> Result<PerInstance, InstancingNotSupported>;
+    fn get_indices(&self) -> IndicesSource;
+    fn get_primitive(&self) -> PrimitiveT;
+    fn vertex_count(&self) -> usize;
+    fn as_any_mut(&mut self) -> &mut Any;
 }
 
 enum IndicesT {
@@ -31,106 +42,222 @@ enum IndicesT {
 
 // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
 
-struct GeomertyInstance<T> {
-    name: String,
-    vertices: VerticesT,
+struct GeomertyInstance<T>
+    where T: Copy
+{
+    vertices: VertexBuffer<T>,
     indices: IndicesT,
     primitive: PrimitiveT,
-    vertices_type: PhantomData<T>,
+    dynamic: bool,
 }
 
-impl<T> Geomerty for GeomertyInstance<T> {
-    fn get_vertices(&self) -> &VerticesT {
-        &self.vertices
+impl<T> Geomerty for GeomertyInstance<T>
+    where T: Vertex + Send + 'static
+{
+    fn get_vertices(&self) -> VerticesSource {
+        (&self.vertices).into_vertices_source()
+    }
+    fn get_per_instance(&self) -> Result<PerInstance, InstancingNotSupported> {
+        self.vertices.per_instance().map_err(|_| InstancingNotSupported)
     }
-    fn get_indices(&self) -> &IndicesT {
-        &self.indices
+    fn get_indices(&self) -> IndicesSource {
+        match self.indices {
+            IndicesT::NoIdx(kind) => NoIndices(kind).into(),
+            IndicesT::Idx(ref buffer) => buffer.into(),
+        }
+    }
+    fn get_primitive(&self) -> PrimitiveT {
+        self.primitive
     }
-    fn get_primitive(&self) -> &PrimitiveT {
-        &self.primitive
+    fn vertex_count(&self) -> usize {
+        self.vertices.len()
     }
+    fn as_any_mut(&mut self) -> &mut Any {
+        self
+    }
+}
+
+fn vertex_buffer<T>(ctx: &Context, data: &[T], dynamic: bool) -> Result<VertexBuffer<T>, ManagerError>
+    where T: Vertex + Send + 'static
+{
+    let buffer = if dynamic {
+        VertexBuffer::dynamic(ctx.display(), data)
+    } else {
+        VertexBuffer::new(ctx.display(), data)
+    };
+    buffer.map_err(|_| ManagerError::BackEndErrror)
 }
 
 // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
 
-struct GeomertyManager {
-    cache: BTreeMap<IdType, Box<Geomerty>>,
+pub struct GeomertyManager {
+    registry: Registry<Geometry>,
+    /// by the slot of the handle
+    cache: Vec<Option<Box<Geomerty>>>,
 }
 
 impl GeomertyManager {
-    fn new() -> GeomertyManager {
-        GeomertyManager { cache: BTreeMap::new() }
+    pub fn new() -> GeomertyManager {
+        GeomertyManager {
+            registry: Registry::new(),
+            cache: Vec::new(),
+        }
     }
 
-    // create a geomerty from data
-    fn create_geom_from_data<T>(&mut self,
-                                ctx: &mut Context,
-                                name: &str,
-                                data: &[T],
-                                kind: PrimitiveT)
-                                -> Result<IdType, ManagerError>
+    /// the slot is taken once the buffers are there
+    fn store(&mut self,
+             name: &str,
+             geom: Box<Geomerty>)
+             -> Result<Handle<Geometry>, ManagerError> {
+        let handle = self.registry.insert(name)?;
+        let i = self.registry.index(handle)?;
+        while self.cache.len() <= i {
+            self.cache.push(None);
+        }
+        self.cache[i] = Some(geom);
+        Ok(handle)
+    }
+
+    fn create<T>(&mut self,
+                 ctx: &Context,
+                 name: &str,
+                 data: &[T],
+                 indices: Option<&[u32]>,
+                 kind: PrimitiveT,
+                 dynamic: bool)
+                 -> Result<Handle<Geometry>, ManagerError>
         where T: Vertex + Send + 'static
     {
-        let id = ctx.get_id_for(name);
-        if self.cache.contains_key(&id) {
+        if self.registry.lookup(name).is_some() {
             return Err(ManagerError::ItemRedefinition);
         }
 
-        if let Ok(vertices) = VertexBuffer::new(ctx.display(), data) {
-            let g: Box<GeomertyInstance<T>> = Box::new(GeomertyInstance {
-                name: name.to_string(),
-                vertices: vertices.into(),
-                indices: IndicesT::NoIdx(kind),
-                primitive: kind,
-                vertices_type: PhantomData,
-            });
-
-            self.cache.insert(id, g);
-            Ok(id)
-        } else {
-            Err(ManagerError::BackEndErrror)
-        }
+        let vertices = vertex_buffer(ctx, data, dynamic)?;
+        let indices = match indices {
+            Some(indices) => {
+                let buffer = IndexBuffer::new(ctx.display(), kind, indices)
+                    .map_err(|_| ManagerError::BackEndErrror)?;
+                IndicesT::Idx(buffer.into())
+            }
+            None => IndicesT::NoIdx(kind),
+        };
+
+        let g: Box<GeomertyInstance<T>> = Box::new(GeomertyInstance {
+            vertices: vertices,
+            indices: indices,
+            primitive: kind,
+            dynamic: dynamic,
+        });
+        self.store(name, g)
+    }
 
+    // create a geomerty from data
+    pub fn create_geom_from_data<T>(&mut self,
+                                    ctx: &Context,
+                                    name: &str,
+                                    data: &[T],
+                                    kind: PrimitiveT)
+                                    -> Result<Handle<Geometry>, ManagerError>
+        where T: Vertex + Send + 'static
+    {
+        self.create(ctx, name, data, None, kind, false)
     }
 
-    fn create_geom_from_data_with_indices<T>(&mut self,
-                                             ctx: &mut Context,
-                                             name: &str,
-                                             data: &[T],
-                                             indices: &[u32],
-                                             kind: PrimitiveT)
-                                             -> Result<IdType, ManagerError>
+    pub fn create_geom_from_data_with_indices<T>(&mut self,
+                                                 ctx: &Context,
+                                                 name: &str,
+                                                 data: &[T],
+                                                 indices: &[u32],
+                                                 kind: PrimitiveT)
+                                                 -> Result<Handle<Geometry>, ManagerError>
         where T: Vertex + Send + 'static
     {
-        let id = ctx.get_id_for(name);
-        if self.cache.contains_key(&id) {
-            return Err(ManagerError::ItemRedefinition);
+        self.create(ctx, name, data, Some(indices), kind, false)
+    }
+
+    /// same, in a buffer meant to be updated
+    pub fn create_dynamic_geom<T>(&mut self,
+                                  ctx: &Context,
+                                  name: &str,
+                                  data: &[T],
+                                  indices: Option<&[u32]>,
+                                  kind: PrimitiveT)
+                                  -> Result<Handle<Geometry>, ManagerError>
+        where T: Vertex + Send + 'static
+    {
+        self.create(ctx, name, data, indices, kind, true)
+    }
+
+    /// the name, or the name with a number if it is taken
+    pub fn unique_name(&self, name: &str) -> String {
+        self.registry.unique_name(name)
+    }
+
+    fn get_instance<T>(&mut self,
+                       handle: Handle<Geometry>)
+                       -> Result<&mut GeomertyInstance<T>, ManagerError>
+        where T: Vertex + Send + 'static
+    {
+        let i = self.registry.index(handle)?;
+        match self.cache[i] {
+            Some(ref mut geom) => {
+                geom.as_any_mut()
+                    .downcast_mut::<GeomertyInstance<T>>()
+                    .ok_or(ManagerError::VertexTypeMismatch)
+            }
+            None => Err(ManagerError::StaleHandle),
         }
+    }
 
-        let vertices = VertexBuffer::new(ctx.display(), data);
-        let indices = IndexBuffer::new(ctx.display(), kind, indices);
-
-        if vertices.is_ok() && indices.is_ok() {
-            let g: Box<GeomertyInstance<T>> = Box::new(GeomertyInstance {
-                name: name.to_string(),
-                vertices: vertices.unwrap().into(),
-                indices: IndicesT::Idx(indices.unwrap().into()),
-                primitive: kind,
-                vertices_type: PhantomData,
-            });
-
-            self.cache.insert(id, g);
-            Ok(id)
-        } else {
-            Err(ManagerError::BackEndErrror)
+    /// writes the vertices from the offset on, the buffer keeps its size
+    pub fn update_vertices<T>(&mut self,
+                              handle: Handle<Geometry>,
+                              offset: usize,
+                              data: &[T])
+                              -> Result<(), ManagerError>
+        where T: Vertex + Send + 'static
+    {
+        let geom = self.get_instance::<T>(handle)?;
+        match geom.vertices.slice_mut(offset..offset + data.len()) {
+            Some(slice) => {
+                slice.write(data);
+                Ok(())
+            }
+            None => Err(ManagerError::OutOfRange),
         }
     }
 
-    fn get_geom(&self, id: IdType) -> Option<&Geomerty> {
-        if let Some(x) = self.cache.get(&id) {
-            return Some(x.deref());
+    /// new vertices, any number of them. the indices stay
+    pub fn replace_vertices<T>(&mut self,
+                               ctx: &Context,
+                               handle: Handle<Geometry>,
+                               data: &[T])
+                               -> Result<(), ManagerError>
+        where T: Vertex + Send + 'static
+    {
+        let geom = self.get_instance::<T>(handle)?;
+        geom.vertices = vertex_buffer(ctx, data, geom.dynamic)?;
+        Ok(())
+    }
+
+    /// none if the handle is stale
+    pub fn get_geom(&self, handle: Handle<Geometry>) -> Option<&Geomerty> {
+        match self.registry.index(handle) {
+            Ok(i) => self.cache[i].as_ref().map(|g| g.deref()),
+            Err(_) => None,
         }
-        None
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<Handle<Geometry>> {
+        self.registry.lookup(name)
+    }
+
+    /// the buffers are dropped, the name can be used again
+    pub fn remove(&mut self, handle: Handle<Geometry>) -> Result<(), ManagerError> {
+        let i = self.registry.index(handle)?;
+        self.registry.release(handle)?;
+        self.cache[i] = None;
+        Ok(())
     }
 }
 
@@ -156,10 +283,10 @@ mod tests {
 
         implement_vertex!(MyVertices, vert);
 
-        let mut ctx = Context::new_headless(100, 100).expect("create headless context");
+        let ctx = Context::new_headless(100, 100).expect("create headless context");
         let mut mgr = GeomertyManager::new();
 
-        let a = mgr.create_geom_from_data(&mut ctx,
+        let a = mgr.create_geom_from_data(&ctx,
                                           "test",
                                           &[MyVertices { vert: (1.0, 1.0, 1.0) }],
                                           PrimitiveType::TrianglesList);
@@ -167,14 +294,28 @@ mod tests {
         {
             let v = mgr.get_geom(a.unwrap());
             let v = v.expect("should exist");
-            assert_eq!(v.get_vertices().len(), 1);
+            assert_eq!(v.vertex_count(), 1);
         }
 
-        let b = mgr.create_geom_from_data(&mut ctx,
+        let b = mgr.create_geom_from_data(&ctx,
                                           "test",
                                           &[MyVertices { vert: (1.0, 1.0, 1.0) }],
                                           PrimitiveType::TrianglesList);
         assert!(b.is_err());
+
+        // removed, the name is free and the old handle is stale
+        let a = a.unwrap();
+        assert_eq!(mgr.lookup("test"), Some(a));
+        mgr.remove(a).expect("release");
+        assert!(mgr.get_geom(a).is_none());
+        assert!(mgr.remove(a).is_err());
+        let c = mgr.create_geom_from_data(&ctx,
+                                          "test",
+                                          &[MyVertices { vert: (1.0, 1.0, 1.0) }],
+                                          PrimitiveType::TrianglesList)
+            .expect("name is free");
+        assert!(c != a);
+        assert!(mgr.get_geom(c).is_some());
     }
 
     #[test]
@@ -187,10 +328,10 @@ mod tests {
 
         implement_vertex!(MyVertices, vert);
 
-        let mut ctx = Context::new_headless(100, 100).expect("create headless ctx");
+        let ctx = Context::new_headless(100, 100).expect("create headless ctx");
         let mut mgr = GeomertyManager::new();
 
-        let a = mgr.create_geom_from_data_with_indices(&mut ctx,
+        let a = mgr.create_geom_from_data_with_indices(&ctx,
                                                        "test",
                                                        &[MyVertices { vert: (1.0, 1.0) },
                                                          MyVertices { vert: (1.0, 1.0) }],
@@ -200,14 +341,54 @@ mod tests {
         {
             let v = mgr.get_geom(a.unwrap());
             let v = v.expect("should exist");
-            assert_eq!(v.get_vertices().len(), 2);
+            assert_eq!(v.vertex_count(), 2);
         }
 
-        let b = mgr.create_geom_from_data_with_indices(&mut ctx,
+        let b = mgr.create_geom_from_data_with_indices(&ctx,
                                                        "test",
                                                        &[MyVertices { vert: (1.0, 1.0) }],
                                                        &[0, 1, 2],
                                                        PrimitiveType::TrianglesList);
         assert!(b.is_err());
     }
+
+    #[test]
+    fn dynamic() {
+
+        #[derive(Copy, Clone)]
+        struct MyVertices {
+            vert: (f32, f32),
+        }
+
+        implement_vertex!(MyVertices, vert);
+
+        #[derive(Copy, Clone)]
+        struct Other {
+            other: f32,
+        }
+
+        implement_vertex!(Other, other);
+
+        let ctx = Context::new_headless(100, 100).expect("create headless ctx");
+        let mut mgr = GeomertyManager::new();
+        let v = MyVertices { vert: (1.0, 1.0) };
+
+        let a = mgr.create_dynamic_geom(&ctx, "lines", &[v, v], None, PrimitiveType::LinesList)
+            .expect("dynamic");
+        assert!(mgr.update_vertices(a, 1, &[v]).is_ok());
+        match mgr.update_vertices(a, 1, &[v, v]) {
+            Err(ManagerError::OutOfRange) => {}
+            other => panic!("wrote past the end: {:?}", other),
+        }
+        match mgr.update_vertices(a, 0, &[Other { other: 1.0 }]) {
+            Err(ManagerError::VertexTypeMismatch) => {}
+            other => panic!("wrote another vertex type: {:?}", other),
+        }
+
+        mgr.replace_vertices(&ctx, a, &[v, v, v, v]).expect("replace");
+        assert_eq!(mgr.get_geom(a).unwrap().vertex_count(), 4);
+
+        assert_eq!(mgr.unique_name("lines"), "lines#1");
+        assert_eq!(mgr.unique_name("points"), "points");
+    }
 }
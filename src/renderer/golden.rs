@@ -0,0 +1,330 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use cgmath::{Point3, Vector3, Matrix4, Deg, Transform};
+use glium;
+use image;
+use rand::{SeedableRng, XorShiftRng};
+
+use renderer::camera::Camera;
+use renderer::capture::to_image;
+use renderer::context::{Context, DrawSurface, RenderType};
+use renderer::pipeline::Pipeline;
+use renderer::shader::{Defines, ProgramCache};
+use world::image_atlas as img_atlas;
+use world::terrain::Terrain;
+
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+//    Golden images,
+//    known heightmaps are rendered offscreen from fixed cameras through
+//    prepass, ssao, blur and color, then compared against the pictures in
+//    tests/golden. drivers do not rasterize exactly the same, so the compare
+//    is perceptual and a few pixels may differ.
+//    - RQUARFS_BLESS=1 records the current output as reference.
+//    - on failure the output and a diff land in target/golden.
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+const WIDTH: u32 = 320;
+const HEIGHT: u32 = 240;
+
+/// tile detail and ssao noise must be the same on every run
+const SEED: [u32; 4] = [0x193a_6754, 0xa8a7_d469, 0x9783_0e05, 0x113b_a7bb];
+
+/// yiq distance for two pixels to be considered the same, 0 to 1
+const THRESHOLD: f64 = 0.1;
+/// fraction of pixels that may differ
+const MAX_DIFFERENT: f64 = 0.005;
+
+pub struct Scene {
+    name: &'static str,
+    heightmap: &'static str,
+    /// camera position, in terrain sizes, looking to the center of the terrain
+    eye: (f32, f32, f32),
+    /// a flat map seen from above has nothing to occlude, its ssao is plain white
+    ssao: bool,
+}
+
+/// stretched over the whole map, so texture coordinates show up in the pictures
+const COLOR_MAP: &'static str = "assets/C18W.png";
+
+pub const SCENES: &'static [Scene] = &[Scene {
+                                       name: "test_oblique",
+                                       heightmap: "assets/test.png",
+                                       eye: (0.3, 0.8, 1.6),
+                                       ssao: true,
+                                   },
+                                   Scene {
+                                       name: "test_top",
+                                       heightmap: "assets/test.png",
+                                       eye: (0.0, 1.5, 0.01),
+                                       ssao: true,
+                                   },
+                                   Scene {
+                                       name: "small_flat_oblique",
+                                       heightmap: "assets/small_flat.png",
+                                       eye: (0.3, 0.8, 1.6),
+                                       ssao: true,
+                                   },
+                                   Scene {
+                                       name: "small_flat_top",
+                                       heightmap: "assets/small_flat.png",
+                                       eye: (0.0, 1.5, 0.01),
+                                       ssao: false,
+                                   }];
+
+/// draws the scene on the frame, turned around the center of the terrain.
+/// everything random comes out of the seed, the pipeline keeps the output of the passes
+pub fn draw(ctx: &mut Context, scene: &Scene, turn: Deg<f32>) -> Pipeline {
+    let mut rng = XorShiftRng::from_seed(SEED);
+
+    let height = img_atlas::load_rgb(scene.heightmap);
+    let (size_x, size_z) = height.dimensions();
+    let terrain = Terrain::with_rng(&ctx, size_x, size_z, &mut rng);
+
+    // frame the box the tiles cover, from the ground to the highest texel
+    let (extent_x, extent_z) = terrain.get_extent();
+    let top = height.enumerate_pixels()
+        .filter(|&(x, z, _)| x <= extent_x && z <= extent_z)
+        .map(|(_, _, p)| p.data[0])
+        .max()
+        .unwrap_or(0) as f32;
+
+    let height_raw = glium::texture::RawImage2d::from_raw_rgb(height.into_raw(), (size_x, size_z));
+    let height_map = glium::texture::Texture2d::new(ctx.display(), height_raw).unwrap();
+
+    let color = img_atlas::load_rgb(COLOR_MAP);
+    let color_size = color.dimensions();
+    let color_raw = glium::texture::RawImage2d::from_raw_rgb(color.into_raw(), color_size);
+    let color_map = glium::texture::Texture2d::new(ctx.display(), color_raw).unwrap();
+    let pipeline = Pipeline::new(&ctx, &img_atlas::generate_noise_with(ctx.get_size(), &mut rng));
+
+    let mut programs = ProgramCache::new();
+    let color_prg = programs.load(&ctx, "terrain_texture", &Defines::new())
+        .expect("terrain_texture");
+    let normals_prg = programs.load(&ctx, "terrain_normals", &Defines::new())
+        .expect("terrain_normals");
+
+    // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+    let size = (extent_x.max(extent_z) as f32).max(top);
+    let center = Point3::new(0.0, top / 2.0, 0.0);
+    let eye = center + Vector3::new(scene.eye.0, scene.eye.1, scene.eye.2) * size;
+    let cam = Camera::new(eye, center);
+
+    ctx.set_projection(Deg(45.0), 1.0, size * 4.0);
+    let perspective_matrix = ctx.get_projection();
+    let view_matrix: Matrix4<f32> = cam.into();
+    let model_matrix = Matrix4::from_angle_y(turn) *
+                       Matrix4::from_translation(Vector3::new(-(extent_x as f32 / 2.0),
+                                                              0.0,
+                                                              -(extent_z as f32 / 2.0)));
+    let pvm = perspective_matrix * view_matrix * model_matrix;
+    let inverse_matrix = pvm.inverse_transform().unwrap();
+    let sun_pos = Point3::new(0.0, 75.0, size);
+
+    let uniforms = uniform! {
+        perspective: Into::<[[f32; 4]; 4]>::into(perspective_matrix),
+        view:        Into::<[[f32; 4]; 4]>::into(view_matrix),
+        model:       Into::<[[f32; 4]; 4]>::into(model_matrix),
+        pvm:         Into::<[[f32; 4]; 4]>::into(pvm),
+        sun_pos:     Into::<[f32; 3]>::into(sun_pos),
+        cam_pos:     Into::<[f32; 3]>::into(eye),
+        height_map:  &height_map,
+        height_size: (size_x, size_z),
+        screen_size: ctx.get_size(),
+        color_map:   &color_map,
+        ssao_texture: &pipeline.targets.blur,
+    };
+
+    // strict, any error panics right there
+    pipeline.prepass(&ctx,
+                     terrain.get_geometry(),
+                     terrain.get_tiles(),
+                     programs.get(normals_prg),
+                     &uniforms,
+                     None)
+        .unwrap();
+    pipeline.ssao(&ctx, &inverse_matrix, None).unwrap();
+
+    let mut surface = DrawSurface::gl_begin(&ctx, RenderType::Textured);
+    surface.draw_geometry_instanced(terrain.get_geometry(),
+                                    terrain.get_tiles(),
+                                    programs.get(color_prg),
+                                    &uniforms)
+        .unwrap();
+    surface.gl_end().unwrap();
+    pipeline
+}
+
+/// final frame and the blurred occlusion
+fn render(scene: &Scene) -> Vec<(String, image::RgbaImage)> {
+    let mut ctx = Context::new_headless(WIDTH, HEIGHT).expect("create headless context");
+    ctx.set_strict(true);
+    let pipeline = draw(&mut ctx, scene, Deg(0.0));
+
+    let frame = ctx.read_pixels().expect("read back frame");
+    let blur: glium::texture::RawImage2d<u8> = pipeline.targets.blur.read();
+
+    let mut res = vec![(format!("{}_color", scene.name), to_image(frame))];
+    if scene.ssao {
+        res.push((format!("{}_ssao", scene.name), to_image(blur)));
+    }
+    res
+}
+
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+//    perceptual compare, yiq distance as in pixelmatch
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+/// max value of the squared yiq distance
+const MAX_DELTA: f64 = 35215.0;
+
+fn blend(c: u8, alpha: f64) -> f64 {
+    // over white
+    255.0 + (c as f64 - 255.0) * alpha
+}
+
+fn yiq(p: &image::Rgba<u8>) -> (f64, f64, f64) {
+    let alpha = p.data[3] as f64 / 255.0;
+    let (r, g, b) = (blend(p.data[0], alpha), blend(p.data[1], alpha), blend(p.data[2], alpha));
+    (r * 0.29889531 + g * 0.58662247 + b * 0.11448223,
+     r * 0.59597799 - g * 0.27417610 - b * 0.32180189,
+     r * 0.21147017 - g * 0.52261711 + b * 0.31114694)
+}
+
+/// 0 for the same color, close to 1 for black against white
+fn distance(a: &image::Rgba<u8>, b: &image::Rgba<u8>) -> f64 {
+    if a == b {
+        return 0.0;
+    }
+    let (ya, ia, qa) = yiq(a);
+    let (yb, ib, qb) = yiq(b);
+    let (y, i, q) = (ya - yb, ia - ib, qa - qb);
+    ((0.5053 * y * y + 0.299 * i * i + 0.1957 * q * q) / MAX_DELTA).sqrt()
+}
+
+/// number of different pixels and an image showing them in red over the faded reference
+fn diff(actual: &image::RgbaImage, expected: &image::RgbaImage) -> (usize, image::RgbaImage) {
+    let mut count = 0;
+    let mut img = image::RgbaImage::new(actual.width(), actual.height());
+    for (x, y, e) in expected.enumerate_pixels() {
+        if distance(actual.get_pixel(x, y), e) > THRESHOLD {
+            count += 1;
+            img.put_pixel(x, y, image::Rgba([255, 0, 0, 255]));
+        } else {
+            let (luma, _, _) = yiq(e);
+            let grey = (255.0 - (255.0 - luma) * 0.1) as u8;
+            img.put_pixel(x, y, image::Rgba([grey, grey, grey, 255]));
+        }
+    }
+    (count, img)
+}
+
+fn golden_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("golden")
+}
+
+fn output_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("target").join("golden")
+}
+
+fn compare(name: &str, actual: &image::RgbaImage) -> Result<(), String> {
+    let reference = golden_dir().join(format!("{}.png", name));
+
+    if env::var_os("RQUARFS_BLESS").is_some() {
+        fs::create_dir_all(golden_dir()).map_err(|e| format!("{}: {}", name, e))?;
+        actual.save(&reference).map_err(|e| format!("{}: {}", name, e))?;
+        println!("recorded {}", reference.display());
+        return Ok(());
+    }
+
+    let expected = image::open(&reference)
+        .map_err(|_| {
+            format!("{}: no reference at {}, record it with RQUARFS_BLESS=1",
+                    name,
+                    reference.display())
+        })?
+        .to_rgba();
+
+    if expected.dimensions() != actual.dimensions() {
+        return Err(format!("{}: size {:?}, the reference is {:?}",
+                           name,
+                           actual.dimensions(),
+                           expected.dimensions()));
+    }
+
+    let (count, diff_img) = diff(actual, &expected);
+    let total = (actual.width() * actual.height()) as f64;
+    if count as f64 <= total * MAX_DIFFERENT {
+        return Ok(());
+    }
+
+    let out = output_dir();
+    let _ = fs::create_dir_all(&out);
+    let _ = actual.save(out.join(format!("{}.png", name)));
+    let _ = diff_img.save(out.join(format!("{}.diff.png", name)));
+    Err(format!("{}: {} of {} pixels differ, see {}",
+                name,
+                count,
+                total,
+                out.join(format!("{}.diff.png", name)).display()))
+}
+
+fn check(heightmap: &str) {
+    let failures: Vec<String> = SCENES.iter()
+        .filter(|scene| scene.heightmap == heightmap)
+        .flat_map(|scene| render(scene))
+        .filter_map(|(name, img)| compare(&name, &img).err())
+        .collect();
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+//  Tests
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+mod tests {
+
+    use image;
+    use super::{check, diff, distance, THRESHOLD};
+
+    // references depend on a gl 4.1 driver, run them with
+    // cargo test --features golden
+    #[test]
+    #[cfg_attr(not(feature = "golden"), ignore)]
+    fn golden_test_map() {
+        check("assets/test.png");
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "golden"), ignore)]
+    fn golden_small_flat() {
+        check("assets/small_flat.png");
+    }
+
+    #[test]
+    fn perceptual_distance() {
+        let black = image::Rgba([0, 0, 0, 255]);
+        let white = image::Rgba([255, 255, 255, 255]);
+        let almost_black = image::Rgba([2, 1, 3, 255]);
+        assert_eq!(distance(&black, &black), 0.0);
+        assert!(distance(&black, &white) > 0.9);
+        assert!(distance(&black, &almost_black) < THRESHOLD);
+        // transparent is seen over white
+        assert!(distance(&image::Rgba([0, 0, 0, 0]), &white) < THRESHOLD);
+    }
+
+    #[test]
+    fn diff_image() {
+        let expected = image::RgbaImage::from_pixel(4, 4, image::Rgba([10, 20, 30, 255]));
+        let mut actual = expected.clone();
+        actual.put_pixel(1, 2, image::Rgba([200, 20, 30, 255]));
+        actual.put_pixel(3, 3, image::Rgba([11, 20, 30, 255]));
+
+        let (count, img) = diff(&actual, &expected);
+        assert_eq!(count, 1);
+        assert_eq!(img.get_pixel(1, 2), &image::Rgba([255, 0, 0, 255]));
+        assert!(img.get_pixel(3, 3).data[1] > 200);
+    }
+}
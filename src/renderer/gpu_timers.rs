@@ -0,0 +1,128 @@
+use glium::draw_parameters::TimeElapsedQuery;
+
+use renderer::context::Context;
+use utils::PerformaceCounters;
+
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+//    GPU timers,
+//    gl calls return before the work is done, timing them on the cpu says
+//    nothing. each pass gets a time elapsed query per frame, passed in the
+//    draw parameters of its draws.
+//    queries are read two frames later, when the gpu is most likely done with
+//    them. a query still not ready is dropped, the cpu never waits.
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+/// results go to the performance counters with this in front of the pass name
+pub const PREFIX: &'static str = "gpu_";
+
+pub struct GpuTimers {
+    passes: Vec<String>,
+    /// the queries of this frame and of the last one
+    frames: [Vec<Option<TimeElapsedQuery>>; 2],
+    current: usize,
+    /// seconds per pass, of the last frame read
+    results: Vec<(String, f64)>,
+    /// results read since the last report
+    unreported: bool,
+}
+
+impl GpuTimers {
+    pub fn new(passes: &[&str]) -> GpuTimers {
+        GpuTimers {
+            passes: passes.iter().map(|p| p.to_string()).collect(),
+            frames: [Vec::new(), Vec::new()],
+            current: 0,
+            results: Vec::new(),
+            unreported: false,
+        }
+    }
+
+    /// reads the queries of two frames ago and creates the ones of this frame.
+    /// without timer query support all of them are none
+    pub fn begin_frame(&mut self, ctx: &Context) {
+        self.current = 1 - self.current;
+
+        let old = ::std::mem::replace(&mut self.frames[self.current], Vec::new());
+        let mut results = Vec::new();
+        for (pass, query) in self.passes.iter().zip(old.into_iter()) {
+            if let Some(query) = query {
+                if query.is_ready() {
+                    results.push((pass.clone(), query.get() as f64 * 1e-9));
+                }
+            }
+        }
+        if !results.is_empty() {
+            self.results = results;
+            self.unreported = true;
+        }
+
+        self.frames[self.current] = self.passes
+            .iter()
+            .map(|_| TimeElapsedQuery::new(ctx.display()).ok())
+            .collect();
+    }
+
+    /// the query for the draws of the pass in this frame
+    pub fn get(&self, pass: &str) -> Option<&TimeElapsedQuery> {
+        self.passes
+            .iter()
+            .position(|p| p == pass)
+            .and_then(|i| self.frames[self.current].get(i))
+            .and_then(|query| query.as_ref())
+    }
+
+    /// seconds the pass took, the last time it could be read
+    #[allow(dead_code)]
+    pub fn last(&self, pass: &str) -> Option<f64> {
+        self.results.iter().find(|&&(ref p, _)| p == pass).map(|&(_, t)| t)
+    }
+
+    /// adds the results read since the last report to the counter history,
+    /// a frame without ready queries adds nothing
+    pub fn report(&mut self, counters: &mut PerformaceCounters) {
+        if !self.unreported {
+            return;
+        }
+        self.unreported = false;
+        for &(ref pass, seconds) in &self.results {
+            counters.add_sample(&format!("{}{}", PREFIX, pass), seconds);
+        }
+    }
+}
+
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+//  Tests
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn reported_as_samples() {
+        let mut timers = GpuTimers::new(&["prepass", "ssao"]);
+        assert!(timers.get("prepass").is_none());
+        assert_eq!(timers.last("prepass"), None);
+
+        let mut counters = PerformaceCounters::new();
+        timers.report(&mut counters);
+        assert_eq!(counters.get_last_measure("gpu_prepass"), None);
+
+        timers.results = vec![("prepass".to_string(), 0.002), ("ssao".to_string(), 0.001)];
+        timers.unreported = true;
+        timers.report(&mut counters);
+        assert_eq!(counters.get_last_measure("gpu_prepass"), Some(0.002));
+        assert_eq!(counters.get_last_measure("gpu_ssao"), Some(0.001));
+
+        // a new read averages in, the frames after it add nothing
+        timers.results = vec![("prepass".to_string(), 0.004)];
+        timers.unreported = true;
+        timers.report(&mut counters);
+        timers.report(&mut counters);
+        timers.report(&mut counters);
+        assert_eq!(counters.get_last_measure("gpu_prepass"), Some(0.003));
+        assert_eq!(counters.get_last_measure("gpu_ssao"), Some(0.001));
+        assert_eq!(timers.last("prepass"), Some(0.004));
+    }
+}
@@ -21,6 +21,7 @@ pub struct GraphPlot<'a, 'b, D>
     where D: glium::Surface + 'a
 {
     frame: &'a mut D,
+    viewport: glium::Rect,
     count: u32,
     program: &'b ProgramReloader,
 }
@@ -33,6 +34,7 @@ impl<'a, 'b, D> GraphPlot<'a, 'b, D>
     pub fn new(ctx: &context::Context,
                frame: &'a mut D,
                tick: f32, // the current possition between 0 and 1
+               viewport: glium::Rect, // see overlay::Overlay::viewport
                program: &'b ProgramReloader)
                -> GraphPlot<'a, 'b, D> {
 
@@ -58,18 +60,14 @@ impl<'a, 'b, D> GraphPlot<'a, 'b, D>
                   program.get_program(),
                   &uniforms,
                   &glium::DrawParameters {
-                      viewport: Some(glium::Rect {
-                          left: 660,
-                          bottom: 10,
-                          width: 1200,
-                          height: 480,
-                      }),
+                      viewport: Some(viewport),
                       ..Default::default()
                   })
             .unwrap();
 
         GraphPlot {
             frame: frame,
+            viewport: viewport,
             count: 1,
             program: program,
         }
@@ -103,12 +101,7 @@ impl<'a, 'b, D> GraphPlot<'a, 'b, D>
                   &uniforms,
                   &glium::DrawParameters {
                       // backface_culling: glium::BackfaceCullingMode::CullClockwise,
-                      viewport: Some(glium::Rect {
-                          left: 660,
-                          bottom: 10,
-                          width: 1200,
-                          height: 480,
-                      }),
+                      viewport: Some(self.viewport),
                       ..Default::default()
                   })
             .unwrap();
@@ -0,0 +1,213 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+
+use renderer::context::ManagerError;
+
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+//    Handles,
+//    resources are referred by a slot and the generation of that slot. a
+//    released slot is used again with the next generation, so the handles
+//    still around are detected as stale instead of reaching the new item.
+//    the kind is part of the type, a geometry handle is not a texture one.
+//    each manager keeps a registry for its kind, with the names.
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+/// kind of the handles of the geometry manager
+pub enum Geometry {}
+/// kind of the handles of the texture manager
+pub enum Texture {}
+
+pub struct Handle<T> {
+    slot: u32,
+    generation: u32,
+    kind: PhantomData<T>,
+}
+
+// derive would ask the kind for the same traits
+
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Handle<T> {
+        *self
+    }
+}
+
+impl<T> Copy for Handle<T> {}
+
+impl<T> PartialEq for Handle<T> {
+    fn eq(&self, other: &Handle<T>) -> bool {
+        self.slot == other.slot && self.generation == other.generation
+    }
+}
+
+impl<T> Eq for Handle<T> {}
+
+impl<T> Hash for Handle<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.slot.hash(state);
+        self.generation.hash(state);
+    }
+}
+
+impl<T> fmt::Debug for Handle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Handle({}v{})", self.slot, self.generation)
+    }
+}
+
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+struct Slot {
+    generation: u32,
+    /// none while the slot is free
+    name: Option<String>,
+}
+
+pub struct Registry<T> {
+    slots: Vec<Slot>,
+    free: Vec<u32>,
+    names: BTreeMap<String, u32>,
+    kind: PhantomData<T>,
+}
+
+impl<T> Registry<T> {
+    pub fn new() -> Registry<T> {
+        Registry {
+            slots: Vec::new(),
+            free: Vec::new(),
+            names: BTreeMap::new(),
+            kind: PhantomData,
+        }
+    }
+
+    /// names are unique while the item lives
+    pub fn insert(&mut self, name: &str) -> Result<Handle<T>, ManagerError> {
+        if self.names.contains_key(name) {
+            return Err(ManagerError::ItemRedefinition);
+        }
+
+        let slot = match self.free.pop() {
+            Some(slot) => {
+                let entry = &mut self.slots[slot as usize];
+                entry.generation += 1;
+                entry.name = Some(name.to_string());
+                slot
+            }
+            None => {
+                self.slots.push(Slot {
+                    generation: 0,
+                    name: Some(name.to_string()),
+                });
+                self.slots.len() as u32 - 1
+            }
+        };
+        self.names.insert(name.to_string(), slot);
+
+        Ok(Handle {
+            slot: slot,
+            generation: self.slots[slot as usize].generation,
+            kind: PhantomData,
+        })
+    }
+
+    /// the handle must be the live one, a stale handle does not release the new item
+    pub fn release(&mut self, handle: Handle<T>) -> Result<(), ManagerError> {
+        self.index(handle)?;
+        let name = self.slots[handle.slot as usize].name.take().unwrap();
+        self.names.remove(&name);
+        self.free.push(handle.slot);
+        Ok(())
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<Handle<T>> {
+        self.names.get(name).map(|&slot| {
+            Handle {
+                slot: slot,
+                generation: self.slots[slot as usize].generation,
+                kind: PhantomData,
+            }
+        })
+    }
+
+    /// the name, or the name with a number if it is taken
+    pub fn unique_name(&self, name: &str) -> String {
+        if self.lookup(name).is_none() {
+            return name.to_string();
+        }
+        (1..)
+            .map(|n| format!("{}#{}", name, n))
+            .find(|candidate| self.lookup(candidate).is_none())
+            .unwrap()
+    }
+
+    pub fn name(&self, handle: Handle<T>) -> Option<&str> {
+        self.index(handle).ok().and_then(|i| self.slots[i].name.as_ref()).map(|n| n.as_str())
+    }
+
+    pub fn is_valid(&self, handle: Handle<T>) -> bool {
+        self.index(handle).is_ok()
+    }
+
+    /// position of the item in the storage of the manager, slots are dense
+    pub fn index(&self, handle: Handle<T>) -> Result<usize, ManagerError> {
+        match self.slots.get(handle.slot as usize) {
+            Some(slot) if slot.generation == handle.generation && slot.name.is_some() => {
+                Ok(handle.slot as usize)
+            }
+            _ => Err(ManagerError::StaleHandle),
+        }
+    }
+
+    /// items alive
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+}
+
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+//  Tests
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn names() {
+        let mut registry: Registry<Geometry> = Registry::new();
+        let cube = registry.insert("cube").unwrap();
+        let quad = registry.insert("quad").unwrap();
+        assert!(cube != quad);
+        assert_eq!(registry.lookup("cube"), Some(cube));
+        assert_eq!(registry.name(quad), Some("quad"));
+        assert_eq!(registry.lookup("sphere"), None);
+        assert_eq!(registry.unique_name("sphere"), "sphere");
+        assert_eq!(registry.unique_name("cube"), "cube#1");
+
+        match registry.insert("cube") {
+            Err(ManagerError::ItemRedefinition) => {}
+            other => panic!("redefinition accepted: {:?}", other),
+        }
+        assert_eq!(registry.len(), 2);
+    }
+
+    #[test]
+    fn stale() {
+        let mut registry: Registry<Texture> = Registry::new();
+        let old = registry.insert("depth").unwrap();
+        registry.release(old).unwrap();
+        assert!(!registry.is_valid(old));
+        assert_eq!(registry.lookup("depth"), None);
+        assert_eq!(registry.len(), 0);
+
+        // the slot is used again, the old handle does not reach the new item
+        let new = registry.insert("depth").unwrap();
+        assert_eq!(registry.index(new).unwrap(), 0);
+        assert!(registry.is_valid(new) && !registry.is_valid(old));
+        assert_eq!(registry.name(old), None);
+        assert!(registry.release(old).is_err());
+        assert!(registry.is_valid(new));
+    }
+}
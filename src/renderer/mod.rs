@@ -1,43 +1,60 @@
 extern crate time;
 
+use std::collections::HashMap;
+use std::hash::Hash;
+
 pub mod context;
 pub mod camera;
 pub mod shader;
+mod shader_pack;
 pub mod texquad;
+pub mod overlay;
 pub mod shadowmapper;
+pub mod pipeline;
+pub mod capture;
+pub mod errors;
+pub mod handle;
+pub mod gpu_timers;
+pub mod recorder;
 mod ss_pass;
+mod binary_cache;
+mod uniform_check;
+#[cfg(test)]
+mod golden;
 pub mod graphs;
 
-mod geometry_manager;
-mod texture_manager;
-
-pub type ScreenSpacePass<'a> = ss_pass::ScreenSpacePass<'a>;
+pub mod geometry_manager;
+pub mod texture_manager;
 
 // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
 //    convert to vertex + index
 // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
 
-#[allow(dead_code)]
+/// what a vertex is looked up by when indexing, the bits of its fields
+/// (f32::to_bits). floats do not hash, and the raw bytes would take the padding
+pub trait VertexKey {
+    type Key: Hash + Eq;
+    fn key(&self) -> Self::Key;
+}
+
+/// vertices with the same key are merged. equal values with different bits
+/// (0.0 and -0.0) are kept twice
 pub fn index_vertex_list<T>(vertices_org: &[T]) -> (Vec<T>, Vec<u32>)
-    where T: PartialEq + Clone
+    where T: VertexKey + Copy
 {
 
     let mut vertices: Vec<T> = Vec::new();
     let mut indices: Vec<u32> = Vec::with_capacity(vertices_org.len());
+    let mut seen: HashMap<T::Key, u32> = HashMap::new();
 
     // for each vertex, search in vertices list, if not there, insert the last one.
     for v in vertices_org {
-        let pos = vertices.iter().position(|r| *r == *v);
-        match pos {
-            None => {
-                indices.push(vertices.len() as u32);
-                vertices.push(v.clone());
-            }
-            Some(x) => indices.push(x as u32),
-        }
+        let i = *seen.entry(v.key()).or_insert_with(|| {
+            vertices.push(*v);
+            (vertices.len() - 1) as u32
+        });
+        indices.push(i);
     }
-    // vertices.shrink_to_fit();
-    // indices.shrink_to_fit();
 
     (vertices, indices)
 }
@@ -49,17 +66,17 @@ pub fn index_vertex_list<T>(vertices_org: &[T]) -> (Vec<T>, Vec<u32>)
 #[cfg(test)]
 mod tools {
 
-    use super::index_vertex_list;
+    use super::{index_vertex_list, VertexKey};
 
     // unidimensional test
     #[derive(Copy, Clone)]
     struct Vertex {
         point: (f32, f32, f32),
     }
-    impl PartialEq for Vertex {
-        fn eq(&self, other: &Vertex) -> bool {
-            self.point.0 == other.point.0 && self.point.1 == other.point.1 &&
-            self.point.2 == other.point.2
+    impl VertexKey for Vertex {
+        type Key = (u32, u32, u32);
+        fn key(&self) -> Self::Key {
+            (self.point.0.to_bits(), self.point.1.to_bits(), self.point.2.to_bits())
         }
     }
 
@@ -80,6 +97,7 @@ mod tools {
         let (vertices, indices) = index_vertex_list(&v);
         assert_eq!(vertices.len(), 4);
         assert_eq!(indices.len(), 8);
+        assert_eq!(indices, vec![0, 1, 2, 3, 0, 1, 2, 3]);
     }
 
 }
@@ -0,0 +1,388 @@
+use std::collections::HashMap;
+
+use glium;
+use glium::texture;
+use glium::uniforms::MagnifySamplerFilter;
+use image;
+
+use renderer::context::{Context, DrawSurface};
+use renderer::errors::RenderError;
+use renderer::texquad::TexQuad;
+
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+//    Debug overlay,
+//    named textures tiled in a grid inside a region of the window, each one
+//    with its name on top. rectangles are fractions of the window, from the
+//    bottom left corner like gl does.
+//    depth textures are shown as linear distance, single channel ones (only
+//    red is used) in false colour. panels are toggled one by one.
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+/// labels are drawn this many pixels per font pixel
+const LABEL_SCALE: u32 = 2;
+/// space between panels, fraction of the window
+const MARGIN: f32 = 0.004;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Rect {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Rect {
+    pub fn new(x: f32, y: f32, width: f32, height: f32) -> Rect {
+        Rect {
+            x: x,
+            y: y,
+            width: width,
+            height: height,
+        }
+    }
+
+    /// in pixels of a surface with the given size
+    pub fn viewport(&self, (w, h): (u32, u32)) -> glium::Rect {
+        glium::Rect {
+            left: (self.x * w as f32) as u32,
+            bottom: (self.y * h as f32) as u32,
+            width: (self.width * w as f32).max(1.0) as u32,
+            height: (self.height * h as f32).max(1.0) as u32,
+        }
+    }
+}
+
+struct Panel {
+    name: String,
+    false_color: bool,
+    visible: bool,
+}
+
+/// which panels there are and where they go, no gl in here
+pub struct Layout {
+    panels: Vec<Panel>,
+    region: Rect,
+    columns: u32,
+}
+
+impl Layout {
+    pub fn new(region: Rect, columns: u32) -> Layout {
+        Layout {
+            panels: Vec::new(),
+            region: region,
+            columns: columns.max(1),
+        }
+    }
+
+    pub fn set_region(&mut self, region: Rect, columns: u32) {
+        self.region = region;
+        self.columns = columns.max(1);
+    }
+
+    /// a panel with the same name is replaced, in its place
+    pub fn add(&mut self, name: &str, false_color: bool) {
+        let panel = Panel {
+            name: name.to_string(),
+            false_color: false_color,
+            visible: true,
+        };
+        match self.panels.iter().position(|p| p.name == name) {
+            Some(i) => self.panels[i] = panel,
+            None => self.panels.push(panel),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.panels.len()
+    }
+
+    pub fn name(&self, i: usize) -> Option<&str> {
+        self.panels.get(i).map(|p| p.name.as_str())
+    }
+
+    pub fn is_visible(&self, name: &str) -> bool {
+        self.panels.iter().any(|p| p.name == name && p.visible)
+    }
+
+    #[allow(dead_code)]
+    pub fn set_visible(&mut self, name: &str, visible: bool) {
+        if let Some(panel) = self.panels.iter_mut().find(|p| p.name == name) {
+            panel.visible = visible;
+        }
+    }
+
+    /// the new state, none if there is no such panel
+    pub fn toggle(&mut self, name: &str) -> Option<bool> {
+        self.panels.iter_mut().find(|p| p.name == name).map(|panel| {
+            panel.visible = !panel.visible;
+            panel.visible
+        })
+    }
+
+    /// visible panels share the region, rows from the top, left to right
+    pub fn rect(&self, name: &str) -> Option<Rect> {
+        let visible: Vec<&Panel> = self.panels.iter().filter(|p| p.visible).collect();
+        let i = match visible.iter().position(|p| p.name == name) {
+            Some(i) => i as u32,
+            None => return None,
+        };
+
+        let n = visible.len() as u32;
+        let columns = self.columns.min(n);
+        let rows = (n + columns - 1) / columns;
+        let cell_w = self.region.width / columns as f32;
+        let cell_h = self.region.height / rows as f32;
+        let (col, row) = (i % columns, i / columns);
+
+        Some(Rect::new(self.region.x + col as f32 * cell_w + MARGIN,
+                       self.region.y + self.region.height - (row + 1) as f32 * cell_h + MARGIN,
+                       cell_w - 2.0 * MARGIN,
+                       cell_h - 2.0 * MARGIN))
+    }
+}
+
+/// what a panel shows this frame
+pub enum Source<'a> {
+    Color(&'a texture::Texture2d),
+    Depth(&'a texture::DepthTexture2d),
+}
+
+pub struct Overlay {
+    layout: Layout,
+    quad: TexQuad,
+    labels: HashMap<String, texture::Texture2d>,
+}
+
+impl Overlay {
+    pub fn new(ctx: &Context, region: Rect, columns: u32) -> Overlay {
+        Overlay {
+            layout: Layout::new(region, columns),
+            quad: TexQuad::new(ctx),
+            labels: HashMap::new(),
+        }
+    }
+
+    pub fn update(&mut self, ctx: &Context, delta: f64) {
+        self.quad.update(ctx, delta);
+    }
+
+    pub fn add_panel(&mut self, ctx: &Context, name: &str, false_color: bool) {
+        let img = label_image(name);
+        let dim = img.dimensions();
+        let raw = glium::texture::RawImage2d::from_raw_rgba_reversed(&img.into_raw(), dim);
+        let label = texture::Texture2d::new(ctx.display(), raw).unwrap();
+
+        self.labels.insert(name.to_string(), label);
+        self.layout.add(name, false_color);
+    }
+
+    pub fn layout(&self) -> &Layout {
+        &self.layout
+    }
+
+    pub fn layout_mut(&mut self) -> &mut Layout {
+        &mut self.layout
+    }
+
+    /// pixels of the panel in the current window, to draw something else there
+    #[allow(dead_code)]
+    pub fn viewport(&self, ctx: &Context, name: &str) -> Option<glium::Rect> {
+        self.layout.rect(name).map(|rect| rect.viewport(ctx.get_size()))
+    }
+
+    /// panels without a source this frame only show the label
+    pub fn draw(&self,
+                ctx: &Context,
+                surface: &mut DrawSurface,
+                sources: &[(&str, Source)])
+                -> Result<(), RenderError> {
+        let (near, far) = ctx.get_depth_range();
+
+        for panel in self.layout.panels.iter().filter(|p| p.visible) {
+            let viewport = match self.layout.rect(&panel.name) {
+                Some(rect) => rect.viewport(ctx.get_size()),
+                None => continue,
+            };
+
+            match sources.iter().find(|&&(name, _)| name == panel.name) {
+                Some(&(_, Source::Color(texture))) => {
+                    let uniforms = uniform! {
+                        quad_texture: texture,
+                        mode: if panel.false_color { 2 } else { 0 },
+                        near: near,
+                        far: far,
+                    };
+                    surface.draw_overlay_quad(&self.quad, viewport, &uniforms)?;
+                }
+                Some(&(_, Source::Depth(texture))) => {
+                    let uniforms = uniform! {
+                        quad_texture: texture,
+                        mode: 1,
+                        near: near,
+                        far: far,
+                    };
+                    surface.draw_overlay_quad(&self.quad, viewport, &uniforms)?;
+                }
+                None => {}
+            }
+
+            if let Some(label) = self.labels.get(&panel.name) {
+                let (w, h) = label.dimensions();
+                let (w, h) = (w * LABEL_SCALE, h * LABEL_SCALE);
+                let top = viewport.bottom + viewport.height;
+                let label_viewport = glium::Rect {
+                    left: viewport.left,
+                    bottom: if top > h { top - h } else { 0 },
+                    width: w.min(viewport.width),
+                    height: h,
+                };
+                let uniforms = uniform! {
+                    quad_texture: label.sampled().magnify_filter(MagnifySamplerFilter::Nearest),
+                    mode: 0,
+                    near: near,
+                    far: far,
+                };
+                surface.draw_overlay_quad(&self.quad, label_viewport, &uniforms)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+//    Labels,
+//    a 5x7 font is enough for target names. rows top down, the five low bits
+//    of each row from left to right. lowercase is shown as uppercase, what
+//    is not in the font as a question mark.
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+const GLYPHS: &'static str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789 -_.:/?";
+
+const FONT: [[u8; 7]; 43] = [[0x0E, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+                             [0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E],
+                             [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E],
+                             [0x1E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x1E],
+                             [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F],
+                             [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10],
+                             [0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F],
+                             [0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+                             [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E],
+                             [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0C],
+                             [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11],
+                             [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F],
+                             [0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11],
+                             [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11],
+                             [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+                             [0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10],
+                             [0x0E, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0D],
+                             [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11],
+                             [0x0F, 0x10, 0x10, 0x0E, 0x01, 0x01, 0x1E],
+                             [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],
+                             [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+                             [0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04],
+                             [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0A],
+                             [0x11, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x11],
+                             [0x11, 0x11, 0x11, 0x0A, 0x04, 0x04, 0x04],
+                             [0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F],
+                             [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E],
+                             [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E],
+                             [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F],
+                             [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E],
+                             [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02],
+                             [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E],
+                             [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E],
+                             [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+                             [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E],
+                             [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C],
+                             [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+                             [0x00, 0x00, 0x00, 0x1F, 0x00, 0x00, 0x00],
+                             [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1F],
+                             [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C],
+                             [0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x0C, 0x00],
+                             [0x00, 0x01, 0x02, 0x04, 0x08, 0x10, 0x00],
+                             [0x0E, 0x11, 0x01, 0x02, 0x04, 0x00, 0x04]];
+
+fn glyph(c: char) -> &'static [u8; 7] {
+    let c = c.to_ascii_uppercase();
+    let i = GLYPHS.chars().position(|g| g == c).unwrap_or(GLYPHS.len() - 1);
+    &FONT[i]
+}
+
+/// white text on a dark translucent box, one pixel of border, top row first
+fn label_image(text: &str) -> image::RgbaImage {
+    let count = text.chars().count() as u32;
+    let mut img = image::RgbaImage::from_pixel(count * 6 + 1, 9, image::Rgba([0, 0, 0, 160]));
+
+    for (n, c) in text.chars().enumerate() {
+        for (row, bits) in glyph(c).iter().enumerate() {
+            for col in 0..5 {
+                if bits & (0x10 >> col) != 0 {
+                    img.put_pixel(1 + n as u32 * 6 + col,
+                                  1 + row as u32,
+                                  image::Rgba([255, 255, 255, 255]));
+                }
+            }
+        }
+    }
+    img
+}
+
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+//  Tests
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-5
+    }
+
+    #[test]
+    fn grid() {
+        let mut layout = Layout::new(Rect::new(0.0, 0.0, 1.0, 0.5), 2);
+        for name in &["prepass", "depth", "ssao"] {
+            layout.add(name, false);
+        }
+
+        // two rows, the last one half full
+        let first = layout.rect("prepass").unwrap();
+        assert!(close(first.x, MARGIN) && close(first.y, 0.25 + MARGIN));
+        assert!(close(first.width, 0.5 - 2.0 * MARGIN));
+        let third = layout.rect("ssao").unwrap();
+        assert!(close(third.x, MARGIN) && close(third.y, MARGIN));
+
+        // hidden panels leave their place to the next ones
+        assert_eq!(layout.toggle("depth"), Some(false));
+        assert!(!layout.is_visible("depth"));
+        assert_eq!(layout.rect("depth"), None);
+        let third = layout.rect("ssao").unwrap();
+        assert!(close(third.x, 0.5 + MARGIN) && close(third.height, 0.5 - 2.0 * MARGIN));
+
+        assert_eq!(layout.toggle("albedo"), None);
+
+        // same name, same place
+        layout.add("prepass", true);
+        assert_eq!(layout.len(), 3);
+        assert_eq!(layout.name(0), Some("prepass"));
+    }
+
+    #[test]
+    fn viewport() {
+        let vp = Rect::new(0.25, 0.5, 0.5, 0.25).viewport((800, 600));
+        assert_eq!((vp.left, vp.bottom, vp.width, vp.height), (200, 300, 400, 150));
+    }
+
+    #[test]
+    fn labels() {
+        let img = label_image("Blur");
+        assert_eq!(img.dimensions(), (25, 9));
+        // top of the B, and the background around it
+        assert_eq!(img.get_pixel(1, 1).data, [255, 255, 255, 255]);
+        assert_eq!(img.get_pixel(5, 1).data, [0, 0, 0, 160]);
+        assert_eq!(glyph('b'), glyph('B'));
+        assert_eq!(glyph('#'), glyph('?'));
+    }
+}
@@ -0,0 +1,168 @@
+use glium;
+use glium::texture;
+use glium::Surface;
+use cgmath::Matrix4;
+use image;
+
+use renderer::context::{Context, Program};
+use renderer::errors::RenderError;
+use renderer::gpu_timers::GpuTimers;
+use renderer::handle::{Geometry, Handle};
+use renderer::ss_pass::ScreenSpacePass;
+use renderer::uniform_check::check_uniforms;
+
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+//    Deferred passes,
+//    prepass writes normals and depth, then ssao and blur run on top of it.
+//    the color pass reads the blurred occlusion and draws on screen.
+//    with timers, the passes are timed as "prepass", "ssao" and "blur".
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+/// screen sized render targets
+pub struct Targets {
+    pub prepass: texture::Texture2d,
+    pub depth: texture::DepthTexture2d,
+    pub ssao: texture::Texture2d,
+    pub blur: texture::Texture2d,
+    pub noise: texture::Texture2d,
+}
+
+fn color_target(ctx: &Context, (w, h): (u32, u32)) -> texture::Texture2d {
+    texture::Texture2d::empty_with_format(ctx.display(),
+                                          texture::UncompressedFloatFormat::F32F32F32F32,
+                                          texture::MipmapsOption::NoMipmap,
+                                          w,
+                                          h)
+        .unwrap()
+}
+
+impl Targets {
+    pub fn new(ctx: &Context, size: (u32, u32), noise: &image::RgbImage) -> Targets {
+        let depth = texture::DepthTexture2d::empty_with_format(ctx.display(),
+                                                               texture::DepthFormat::F32,
+                                                               texture::MipmapsOption::NoMipmap,
+                                                               size.0,
+                                                               size.1)
+            .unwrap();
+
+        let noise_raw = glium::texture::RawImage2d::from_raw_rgb(noise.clone().into_raw(),
+                                                                 noise.dimensions());
+        let noise = texture::Texture2d::new(ctx.display(), noise_raw).unwrap();
+
+        Targets {
+            prepass: color_target(ctx, size),
+            depth: depth,
+            ssao: color_target(ctx, size),
+            blur: color_target(ctx, size),
+            noise: noise,
+        }
+    }
+}
+
+pub struct Pipeline {
+    ssao: ScreenSpacePass,
+    blur: ScreenSpacePass,
+    pub targets: Targets,
+}
+
+impl Pipeline {
+    /// targets as big as the context
+    pub fn new(ctx: &Context, noise: &image::RgbImage) -> Pipeline {
+        Pipeline {
+            ssao: ScreenSpacePass::new(ctx, "ssao"),
+            blur: ScreenSpacePass::new(ctx, "blur"),
+            targets: Targets::new(ctx, ctx.get_size(), noise),
+        }
+    }
+
+    /// allocates the targets again if the context changed its size.
+    /// ssao samples the noise per pixel, so it comes at the new size too.
+    pub fn resize(&mut self, ctx: &Context, noise: &image::RgbImage) {
+        if self.targets.prepass.dimensions() == ctx.get_size() {
+            return;
+        }
+        self.targets = Targets::new(ctx, ctx.get_size(), noise);
+    }
+
+    pub fn update(&mut self, ctx: &Context, delta: f64) {
+        self.ssao.update(ctx, delta);
+        self.blur.update(ctx, delta);
+    }
+
+    /// normals and depth of the instanced geometry
+    pub fn prepass<P, U>(&self,
+                         ctx: &Context,
+                         id: Handle<Geometry>,
+                         instances: Handle<Geometry>,
+                         prg: &P,
+                         uniforms: &U,
+                         timers: Option<&GpuTimers>)
+                         -> Result<(), RenderError>
+        where P: Program,
+              U: glium::uniforms::Uniforms
+    {
+        let mut frame =
+            glium::framebuffer::SimpleFrameBuffer::with_depth_buffer(ctx.display(),
+                                                                     &self.targets.prepass,
+                                                                     &self.targets.depth)
+                .map_err(|err| {
+                    ctx.report_error(RenderError::Target(format!("prepass: {:?}", err)))
+                })?;
+
+        let parameters = glium::DrawParameters {
+            backface_culling: glium::BackfaceCullingMode::CullClockwise,
+            depth: glium::Depth {
+                test: glium::DepthTest::IfLess,
+                write: true,
+                ..Default::default()
+            },
+            polygon_mode: glium::PolygonMode::Fill,
+            provoking_vertex: glium::draw_parameters::ProvokingVertex::LastVertex,
+            time_elapsed_query: timers.and_then(|t| t.get("prepass")),
+            ..Default::default()
+        };
+
+        check_uniforms(prg.get_program(), prg.get_source(), uniforms);
+
+        frame.clear_color_and_depth((0.0, 0.0, 0.0, 0.0), 1.0);
+        let geometry = ctx.geometry();
+        let (geom, inst) = match (geometry.get_geom(id), geometry.get_geom(instances)) {
+            (Some(geom), Some(inst)) => (geom, inst),
+            _ => {
+                let err = RenderError::Geometry(format!("{:?} or {:?}", id, instances));
+                return Err(ctx.report_error(err));
+            }
+        };
+        let per_instance = inst.get_per_instance()
+            .map_err(|err| ctx.report_error(RenderError::draw(prg.get_source(), &err)))?;
+        frame.draw((geom.get_vertices(), per_instance),
+                  geom.get_indices(),
+                  prg.get_program(),
+                  uniforms,
+                  &parameters)
+            .map_err(|err| ctx.report_error(RenderError::draw(prg.get_source(), &err)))
+    }
+
+    /// ambient occlusion out of the prepass, then blurred
+    pub fn ssao(&self,
+                ctx: &Context,
+                inverse_matrix: &Matrix4<f32>,
+                timers: Option<&GpuTimers>)
+                -> Result<(), RenderError> {
+        let targets = &self.targets;
+        self.ssao.execute_pass(ctx,
+                               &targets.ssao,
+                               inverse_matrix,
+                               &targets.prepass,
+                               &targets.depth,
+                               &targets.noise,
+                               timers.and_then(|t| t.get("ssao")))?;
+        self.blur.execute_pass(ctx,
+                               &targets.blur,
+                               inverse_matrix,
+                               &targets.ssao,
+                               &targets.depth,
+                               &targets.noise,
+                               timers.and_then(|t| t.get("blur")))
+    }
+}
@@ -0,0 +1,160 @@
+use std::fs;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+use image;
+
+use renderer::capture::{self, CaptureError};
+use renderer::context::Context;
+
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+//    Recorder,
+//    every presented frame goes to a numbered png sequence or to a raw video
+//    stream. the loop must run with a fixed timestep of 1/fps
+//    (utils::Timestep::Fixed) so the video is the same on any machine.
+//    raw video is rgba, 8 bits per channel, top row first, for example:
+//      ffmpeg -f rawvideo -pix_fmt rgba -s 1920x1080 -r 30 -i frames.rgba out.mp4
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+enum Sink {
+    Png(PathBuf),
+    Raw(Box<Write>),
+}
+
+pub struct Recorder {
+    sink: Sink,
+    fps: u32,
+    frame: u64,
+}
+
+impl Recorder {
+    /// frames as dir/frame-000000.png, dir/frame-000001.png...
+    pub fn png_sequence(dir: &Path, fps: u32) -> Result<Recorder, CaptureError> {
+        fs::create_dir_all(dir)
+            .map_err(|err| CaptureError::SaveFailed(format!("{}: {}", dir.display(), err)))?;
+        Ok(Recorder::with_sink(Sink::Png(dir.to_path_buf()), fps))
+    }
+
+    /// frames one after the other into the file, which can be a named pipe
+    pub fn raw_video(path: &Path, fps: u32) -> Result<Recorder, CaptureError> {
+        let file = fs::File::create(path)
+            .map_err(|err| CaptureError::SaveFailed(format!("{}: {}", path.display(), err)))?;
+        Ok(Recorder::with_sink(Sink::Raw(Box::new(::std::io::BufWriter::new(file))), fps))
+    }
+
+    fn with_sink(sink: Sink, fps: u32) -> Recorder {
+        Recorder {
+            sink: sink,
+            fps: fps.max(1),
+            frame: 0,
+        }
+    }
+
+    /// seconds of simulation between frames
+    pub fn timestep(&self) -> f64 {
+        1.0 / self.fps as f64
+    }
+
+    /// frames written so far
+    pub fn frames(&self) -> u64 {
+        self.frame
+    }
+
+    /// the frame presented last
+    pub fn record(&mut self, ctx: &Context) -> Result<(), CaptureError> {
+        let img = ctx.read_pixels()
+            .map(capture::to_image)
+            .map_err(|_| CaptureError::ReadBackFailed)?;
+        self.write_frame(&img)
+    }
+
+    fn write_frame(&mut self, img: &image::RgbaImage) -> Result<(), CaptureError> {
+        match self.sink {
+            Sink::Png(ref dir) => {
+                capture::save(img, &dir.join(format!("frame-{:06}.png", self.frame)))?;
+            }
+            Sink::Raw(ref mut out) => {
+                // flushed every frame, the program may exit at any moment
+                out.write_all(img)
+                    .and_then(|_| out.flush())
+                    .map_err(|err| CaptureError::SaveFailed(format!("raw video: {}", err)))?;
+            }
+        }
+        self.frame += 1;
+        Ok(())
+    }
+}
+
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+//  Tests
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+#[cfg(test)]
+mod tests {
+
+    use std::env;
+    use std::fs;
+    use std::io::Read;
+    use std::path::Path;
+    use cgmath::Deg;
+    use image;
+    use renderer::context::Context;
+    use renderer::golden;
+    use super::Recorder;
+
+    #[test]
+    fn sequences() {
+        let dir = env::temp_dir().join("rquarfs-recorder-test");
+        let _ = fs::remove_dir_all(&dir);
+        let img = image::RgbaImage::from_pixel(4, 2, image::Rgba([1, 2, 3, 255]));
+
+        let mut png = Recorder::png_sequence(&dir.join("png"), 25).unwrap();
+        assert_eq!(png.timestep(), 0.04);
+        png.write_frame(&img).unwrap();
+        png.write_frame(&img).unwrap();
+        assert_eq!(png.frames(), 2);
+        assert!(dir.join("png").join("frame-000001.png").exists());
+
+        let raw_path = dir.join("frames.rgba");
+        let mut raw = Recorder::raw_video(&raw_path, 30).unwrap();
+        raw.write_frame(&img).unwrap();
+        raw.write_frame(&img).unwrap();
+        assert_eq!(fs::metadata(&raw_path).unwrap().len(), 2 * 4 * 2 * 4);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    const SEEDED_FRAMES: usize = 3;
+
+    /// the golden scene, turning a bit every frame
+    fn record_seeded(path: &Path) -> Vec<u8> {
+        let mut ctx = Context::new_headless(64, 48).expect("create headless context");
+        let mut recorder = Recorder::raw_video(path, 30).unwrap();
+        for frame in 0..SEEDED_FRAMES {
+            let _ = golden::draw(&mut ctx, &golden::SCENES[0], Deg(frame as f32 * 10.0));
+            recorder.record(&ctx).unwrap();
+        }
+        drop(recorder);
+
+        let mut bytes = Vec::new();
+        fs::File::open(path).and_then(|mut f| f.read_to_end(&mut bytes)).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn deterministic() {
+        let dir = env::temp_dir().join("rquarfs-recorder-seeded");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let first = record_seeded(&dir.join("first.rgba"));
+        let second = record_seeded(&dir.join("second.rgba"));
+        let frame = 64 * 48 * 4;
+        assert_eq!(first.len(), SEEDED_FRAMES * frame);
+        // the scene turns, so something got drawn
+        assert!(first[..frame] != first[frame..2 * frame]);
+        assert!(first == second, "recordings of the same seed differ");
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
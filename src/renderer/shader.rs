@@ -1,18 +1,53 @@
-use regex::*;
 use glium;
+use notify;
+use notify::Watcher;
 
 use std::path;
-use std::fs;
 use std::vec::*;
 use std::time;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::sync::mpsc;
 
+use assets;
 use context::Context;
+use renderer::binary_cache;
+use renderer::shader_pack::{ShaderPack, display_path};
 
-fn print_err(err: glium::program::ProgramCreationError) {
+pub use renderer::shader_pack::{Defines, ShaderParseError};
+
+/// translates the glium error into something pointing to our files
+fn map_err(code: &ShaderPack, err: glium::program::ProgramCreationError) -> ShaderParseError {
+    use glium::program::ProgramCreationError::*;
     match err {
-        glium::program::ProgramCreationError::CompilationError(x) => println!("{}", x),
-        glium::program::ProgramCreationError::LinkingError(y) => println!("{}", y),
-        z => println!("{:?}", z),
+        CompilationError(log) => {
+            let (log, first) = code.map_log(&log);
+            println!("{}", log);
+            first.unwrap_or(ShaderParseError::CompileError {
+                file: display_path(&code.files[0]),
+                line: 0,
+                stage: "unknown".to_string(),
+                message: log,
+            })
+        }
+        LinkingError(log) => {
+            println!("{}", log);
+            ShaderParseError::CompileError {
+                file: display_path(&code.files[0]),
+                line: 0,
+                stage: "link".to_string(),
+                message: log,
+            }
+        }
+        z => {
+            println!("{:?}", z);
+            ShaderParseError::CompileError {
+                file: display_path(&code.files[0]),
+                line: 0,
+                stage: "unknown".to_string(),
+                message: format!("{:?}", z),
+            }
+        }
     }
 }
 
@@ -24,14 +59,23 @@ fn get_slice(a: &Option<String>) -> Option<&str> {
     }
 }
 
-/// loads both shaders and compiles program
-fn load_program(ctx: &Context, path: &path::PathBuf) -> Option<glium::Program> {
-    let code = ShaderPack::new(path);
+
+/// loads both shaders and compiles program,
+/// returns as well the list of files the program was built from
+fn load_program(ctx: &Context,
+                path: &path::PathBuf,
+                defines: &Defines)
+                -> Result<(glium::Program, Vec<path::PathBuf>), ShaderParseError> {
+    let code = ShaderPack::load(path, defines, &read_source);
     if let Err(x) = code {
         println!("{:?}", x);
-        return None;
+        return Err(x);
     }
     let code = code.unwrap();
+    if code.compute.is_some() {
+        let msg = "compute shaders can not be used as a program".to_string();
+        return Err(ShaderParseError::SyntaxError(msg));
+    }
 
     let glium_code = glium::program::SourceCode {
         vertex_shader: code.vertex.as_str(),
@@ -41,12 +85,11 @@ fn load_program(ctx: &Context, path: &path::PathBuf) -> Option<glium::Program> {
         tessellation_evaluation_shader: get_slice(&code.tess_eval),
     };
 
-    // compile
-    let prog = glium::Program::new(ctx.display(), glium_code);
+    // compile, or load the binary from a previous run
+    let prog = binary_cache::build_program(ctx, glium_code);
     // compile_program(ctx.display(), &vs.unwrap(), &fs.unwrap());
     if let Err(x) = prog {
-        print_err(x);
-        return None;
+        return Err(map_err(&code, x));
     }
 
     let prog = prog.unwrap();
@@ -67,229 +110,372 @@ fn load_program(ctx: &Context, path: &path::PathBuf) -> Option<glium::Program> {
         println!("     - {}", name);
     }
 
-    Some(prog)
+    Ok((prog, code.files))
 }
 
-fn get_date(name: &path::PathBuf) -> time::SystemTime {
-    let metadata = fs::metadata(name).unwrap();
-    metadata.modified().unwrap()
+/// loads the compute section and compiles it
+fn load_compute(ctx: &Context,
+                path: &path::PathBuf,
+                defines: &Defines)
+                -> Result<(glium::program::ComputeShader, Vec<path::PathBuf>), ShaderParseError> {
+    let code = ShaderPack::load(path, defines, &read_source);
+    if let Err(x) = code {
+        println!("{:?}", x);
+        return Err(x);
+    }
+    let code = code.unwrap();
+
+    let shader = match code.compute {
+        Some(ref compute) => glium::program::ComputeShader::from_source(ctx.display(), compute),
+        None => return Err(ShaderParseError::MissingShader),
+    };
+    if let Err(x) = shader {
+        return Err(map_err(&code, x));
+    }
+
+    println!("Compute shader loaded");
+    Ok((shader.unwrap(), code.files))
 }
 
 // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
 // Program wrapper:
 // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
 
+/// location of a shader pack by name
+fn shader_path(name: &str) -> path::PathBuf {
+    assets::locate(&format!("shaders/{}.glsl", name))
+}
+
+/// shader files come from the asset source, disk or embedded
+fn read_source(path: &path::Path) -> Option<String> {
+    assets::read_string(path).ok()
+}
+
+/// time to wait for the editor to finish writing before reloading
+const RELOAD_DELAY_MS: u64 = 200;
+
+/// keeps track of the files a shader was built from, tells when any of them changed.
+/// filesystem notifications are collected in the background, nothing is polled.
+struct SourceWatcher {
+    path: path::PathBuf,
+    /// folder of the pack, includes are resolved from here
+    root: path::PathBuf,
+    sources: Vec<path::PathBuf>,
+    dirs: BTreeSet<path::PathBuf>,
+    watcher: Option<notify::RecommendedWatcher>,
+    events: mpsc::Receiver<notify::DebouncedEvent>,
+    /// the last build failed, its sources are unknown
+    stale: bool,
+}
+
+impl SourceWatcher {
+    fn new(path: path::PathBuf, sources: Vec<path::PathBuf>) -> SourceWatcher {
+        let (tx, rx) = mpsc::channel();
+        let delay = time::Duration::from_millis(RELOAD_DELAY_MS);
+        let mut watcher = if !assets::hot_reload() {
+            // embedded shaders never change
+            None
+        } else {
+            match notify::watcher(tx, delay) {
+                Ok(watcher) => Some(watcher),
+                Err(err) => {
+                    println!("can not watch shader files, hot reload disabled: {:?}", err);
+                    None
+                }
+            }
+        };
+
+        // the whole folder is watched, an include may show up after a failed build
+        let root = path.parent().map(|p| p.to_path_buf()).unwrap_or(path::PathBuf::from("."));
+        if let Some(ref mut watcher) = watcher {
+            if let Err(err) = watcher.watch(&root, notify::RecursiveMode::Recursive) {
+                println!("can not watch {}: {:?}", root.display(), err);
+            }
+        }
+
+        let mut res = SourceWatcher {
+            path: path,
+            root: root,
+            sources: Vec::new(),
+            dirs: BTreeSet::new(),
+            watcher: watcher,
+            events: rx,
+            stale: false,
+        };
+        res.set_sources(sources);
+        res
+    }
+
+    /// editors often save by replacing the file, we watch the folders and not the files
+    /// so the watch survives it.
+    fn set_sources(&mut self, sources: Vec<path::PathBuf>) {
+        for dir in sources.iter().filter_map(|s| s.parent()) {
+            if dir.starts_with(&self.root) || self.dirs.contains(dir) {
+                continue;
+            }
+            if let Some(ref mut watcher) = self.watcher {
+                if let Err(err) = watcher.watch(dir, notify::RecursiveMode::NonRecursive) {
+                    println!("can not watch {}: {:?}", dir.display(), err);
+                }
+            }
+            self.dirs.insert(dir.to_path_buf());
+        }
+        self.sources = sources;
+        self.stale = false;
+    }
+
+    /// after a failed build we do not know which files it needs,
+    /// any change in the pack folder triggers a new try
+    fn set_stale(&mut self) {
+        self.stale = true;
+    }
+
+    fn changed(&mut self) -> bool {
+        use notify::DebouncedEvent::*;
+
+        // any of the included files may trigger the reload
+        let mut changed = false;
+        while let Ok(event) = self.events.try_recv() {
+            changed |= match event {
+                Create(ref file) |
+                Write(ref file) |
+                Chmod(ref file) |
+                Rename(_, ref file) => {
+                    self.sources.contains(file) || (self.stale && file.starts_with(&self.root))
+                }
+                Rescan => true,
+                _ => false,
+            };
+        }
+        changed
+    }
+}
+
+/// state of a reloadable shader
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ShaderStatus {
+    /// built from the current sources
+    Ok,
+    /// the last reload failed, the last program which compiled is still in use
+    Stale,
+}
+
+/// reports when a shader goes stale or recovers
+fn log_status(path: &path::Path,
+              before: ShaderStatus,
+              after: ShaderStatus,
+              err: Option<&ShaderParseError>) {
+    if before == after {
+        return;
+    }
+    match err {
+        Some(err) => {
+            println!(" ~~ {} is stale, keeping the last good one: {:?} ~~ ",
+                     display_path(path),
+                     err)
+        }
+        None => println!(" ~~ {} is back in use ~~ ", display_path(path)),
+    }
+}
+
 /// listens to filesystem to reload if file was changed
 pub struct ProgramReloader {
     program: glium::program::Program,
-    path: path::PathBuf,
-    date: time::SystemTime,
-    last_check: f64,
+    defines: Defines,
+    source: String,
+    watcher: SourceWatcher,
+    last_error: Option<ShaderParseError>,
 }
 
 impl ProgramReloader {
     pub fn new(ctx: &Context, name: &str) -> Result<ProgramReloader, ShaderParseError> {
-        println!("load shader from: {}.glsl", name);
+        ProgramReloader::with_defines(ctx, name, &Defines::new())
+    }
 
-        let mut path = fs::canonicalize(".").unwrap();
-        path.push("shaders");
-        path.push(format!("{}.glsl", name));
+    /// loads the permutation of the program built with the given defines
+    pub fn with_defines(ctx: &Context,
+                        name: &str,
+                        defines: &Defines)
+                        -> Result<ProgramReloader, ShaderParseError> {
+        println!("load shader from: {}.glsl {}", name, defines);
 
-        let prog = load_program(ctx, &path);
-        if prog.is_none() {
-            return Err(ShaderParseError::CompileError);
-        }
+        let path = shader_path(name);
+        let (prog, sources) = load_program(ctx, &path, defines)?;
 
         Ok(ProgramReloader {
-            program: prog.unwrap(),
-            path: path,
-            date: time::SystemTime::now(),
-            last_check: 0.0,
+            program: prog,
+            defines: defines.clone(),
+            source: display_path(&path),
+            watcher: SourceWatcher::new(path, sources),
+            last_error: None,
         })
     }
 
-    pub fn update(&mut self, ctx: &Context, delta: f64) {
-
-        self.last_check += delta;
-        // one second?
-        if self.last_check < 1.0 {
+    pub fn update(&mut self, ctx: &Context, _: f64) {
+        if !self.watcher.changed() {
             return;
         }
-        self.last_check = 0.0;
-
-        let date = get_date(&self.path);
-        if self.date < date {
-            self.date = date;
 
-            if let Some(prog) = load_program(ctx, &self.path) {
+        let before = self.status();
+        match load_program(ctx, &self.watcher.path, &self.defines) {
+            Ok((prog, sources)) => {
                 println!(" ~~ shader updated ~~ ");
                 self.program = prog;
+                self.watcher.set_sources(sources);
+                self.last_error = None;
+            }
+            Err(err) => {
+                self.watcher.set_stale();
+                self.last_error = Some(err);
             }
         }
+        log_status(&self.watcher.path, before, self.status(), self.last_error());
     }
-}
 
-// make my program to undestand this type
-use renderer::context;
-impl context::Program for ProgramReloader {
-    fn get_program(&self) -> &glium::program::Program {
-        &self.program
-    }
-    fn with_tess(&self) -> bool {
-        self.program.has_tessellation_shaders()
+    /// error of the last reload, if it failed
+    pub fn last_error(&self) -> Option<&ShaderParseError> {
+        self.last_error.as_ref()
     }
-}
 
-// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
-//  Shader Pack,
-//  this implements One file shaders, one day this would be an stand alone library
-//  we do not compile the shaders, just do a basic parsing to extract them from a
-//  source code-like file
-// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
-
-#[derive(Debug)]
-pub enum ShaderParseError {
-    InvalidPath,
-    SyntaxError(String),
-    MissingShader,
-    CompileError,
+    pub fn status(&self) -> ShaderStatus {
+        match self.last_error {
+            Some(_) => ShaderStatus::Stale,
+            None => ShaderStatus::Ok,
+        }
+    }
 }
 
-#[derive(Debug)]
-struct ShaderPack {
-    vertex: String,
-    fragment: String,
-    tess_control: Option<String>,
-    tess_eval: Option<String>,
-    geom: Option<String>,
+/// same as the program reloader, for packs with a compute section
+pub struct ComputeReloader {
+    shader: glium::program::ComputeShader,
+    watcher: SourceWatcher,
+    last_error: Option<ShaderParseError>,
 }
 
-impl ShaderPack {
-    fn new(path: &path::PathBuf) -> Result<ShaderPack, ShaderParseError> {
+impl ComputeReloader {
+    pub fn new(ctx: &Context, name: &str) -> Result<ComputeReloader, ShaderParseError> {
+        println!("load compute shader from: {}.glsl", name);
 
-        // parse file
-        let units = parse_file(path)?;
+        let path = shader_path(name);
+        let (shader, sources) = load_compute(ctx, &path, &Defines::new())?;
 
-        let mut res = ShaderPack {
-            vertex: "".to_string(),
-            fragment: "".to_string(),
-            tess_control: None,
-            tess_eval: None,
-            geom: None,
-        };
+        Ok(ComputeReloader {
+            shader: shader,
+            watcher: SourceWatcher::new(path, sources),
+            last_error: None,
+        })
+    }
 
-        for (kind, code) in units {
+    pub fn update(&mut self, ctx: &Context, _: f64) {
+        if !self.watcher.changed() {
+            return;
+        }
 
-            match kind {
-                ParseState::Common => {} // not implemented yet
-                ParseState::Vertex => res.vertex = code,
-                ParseState::Fragment => res.fragment = code,
-                ParseState::TessC => res.tess_control = Some(code),
-                ParseState::TessE => res.tess_eval = Some(code),
-                ParseState::Geom => res.geom = Some(code),
+        let before = self.status();
+        match load_compute(ctx, &self.watcher.path, &Defines::new()) {
+            Ok((shader, sources)) => {
+                println!(" ~~ compute shader updated ~~ ");
+                self.shader = shader;
+                self.watcher.set_sources(sources);
+                self.last_error = None;
+            }
+            Err(err) => {
+                self.watcher.set_stale();
+                self.last_error = Some(err);
             }
-
         }
+        log_status(&self.watcher.path, before, self.status(), self.last_error());
+    }
 
-        // validate, fragment and vertex can not be empty
-        if res.vertex.is_empty() || res.fragment.is_empty() {
-            println!("{:?}", res);
-            return Err(ShaderParseError::MissingShader);
+    /// error of the last reload, if it failed
+    pub fn last_error(&self) -> Option<&ShaderParseError> {
+        self.last_error.as_ref()
+    }
+
+    pub fn status(&self) -> ShaderStatus {
+        match self.last_error {
+            Some(_) => ShaderStatus::Stale,
+            None => ShaderStatus::Ok,
         }
+    }
 
-        Ok(res)
+    pub fn get_shader(&self) -> &glium::program::ComputeShader {
+        &self.shader
     }
 }
 
-// parse state machine
-// we are looking for // <- TEXT
-// very basic regex line based parsing
-#[derive(Copy, Clone, Debug)]
-enum ParseState {
-    Common,
-    Vertex,
-    Fragment,
-    TessC,
-    TessE,
-    Geom,
-}
 
-type ShaderParse = (ParseState, String);
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+// Permutations:
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
 
-lazy_static! {
-    static ref RE: Regex = Regex::new(r"\s*//\s*<-\s*(\w+)").unwrap();
-}
+pub type PermutationId = usize;
 
-struct Parser {
-    accum: String,
-    state: ParseState,
-    items: Vec<ShaderParse>,
+/// programs cached by file and define set,
+/// every live permutation is rebuilt when its file changes
+pub struct ProgramCache {
+    programs: Vec<ProgramReloader>,
+    index: BTreeMap<(String, Defines), PermutationId>,
 }
 
-impl Parser {
-    fn new() -> Parser {
-        use self::ParseState::*;
-        Parser {
-            accum: "".to_string(),
-            state: Common,
-            items: Vec::new(),
+impl ProgramCache {
+    pub fn new() -> ProgramCache {
+        ProgramCache {
+            programs: Vec::new(),
+            index: BTreeMap::new(),
         }
     }
 
-    fn parse_line(&mut self, line: String) -> Result<(), ShaderParseError> {
-        if let Some(cap) = RE.captures(&line) {
-            let name = cap.get(1).map_or("", |m| m.as_str()).to_lowercase();
-            self.items.push((self.state, self.accum.clone()));
-            self.accum = String::new();
-
-            if name == "vertex" {
-                self.state = ParseState::Vertex;
-            } else if name == "fragment" {
-                self.state = ParseState::Fragment;
-            } else if name == "tessellation_control" {
-                self.state = ParseState::TessC;
-            } else if name == "tessellation_evaluation" {
-                self.state = ParseState::TessE;
-            } else if name == "geometry" {
-                self.state = ParseState::Geom;
-            } else {
-                return Err(ShaderParseError::SyntaxError(format!("Unknow section {}", name)
-                    .to_string()));
-            }
-        } else {
-            self.accum.push_str(line.as_str());
-            self.accum.push_str("\n");
+    /// compiles the permutation the first time it is requested
+    pub fn load(&mut self,
+                ctx: &Context,
+                name: &str,
+                defines: &Defines)
+                -> Result<PermutationId, ShaderParseError> {
+        let key = (name.to_string(), defines.clone());
+        if let Some(id) = self.index.get(&key) {
+            return Ok(*id);
         }
-        Ok(())
-    }
-    fn get_items(mut self) -> Vec<ShaderParse> {
-        self.items.push((self.state, self.accum));
-        self.items
-    }
-}
-
-fn parse_file(path: &path::PathBuf) -> Result<Vec<ShaderParse>, ShaderParseError> {
 
-    use std::io::BufReader;
-    use std::io::prelude::*;
-    use std::fs::File;
-    use self::ShaderParseError::*;
-    // use self::ParseState::*;
-
-    let mut parser = Parser::new();
+        let prog = ProgramReloader::with_defines(ctx, name, defines)?;
+        let id = self.programs.len();
+        self.programs.push(prog);
+        self.index.insert(key, id);
+        Ok(id)
+    }
 
-    if let Ok(f) = File::open(path) {
-        let f = BufReader::new(f);
+    pub fn get(&self, id: PermutationId) -> &ProgramReloader {
+        &self.programs[id]
+    }
 
-        for l in f.lines() {
-            parser.parse_line(l.unwrap())?;
+    pub fn update(&mut self, ctx: &Context, delta: f64) {
+        for prog in &mut self.programs {
+            prog.update(ctx, delta);
         }
-        return Ok(parser.get_items());
     }
 
-    Err(InvalidPath)
+    /// errors of the permutations whose last reload failed
+    #[allow(dead_code)]
+    pub fn last_errors(&self) -> Vec<&ShaderParseError> {
+        self.programs.iter().filter_map(|p| p.last_error()).collect()
+    }
 }
 
+// make my program to undestand this type
+use renderer::context;
+impl context::Program for ProgramReloader {
+    fn get_program(&self) -> &glium::program::Program {
+        &self.program
+    }
+    fn with_tess(&self) -> bool {
+        self.program.has_tessellation_shaders()
+    }
+    fn get_source(&self) -> &str {
+        &self.source
+    }
+}
+
+
 // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
 //  Tests
 // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
@@ -298,47 +484,70 @@ fn parse_file(path: &path::PathBuf) -> Result<Vec<ShaderParse>, ShaderParseError
 mod tests {
 
     use super::ProgramReloader;
-    use super::ShaderPack;
-
-    use glium::glutin::HeadlessRendererBuilder;
-    use glium::DisplayBuild;
+    use super::ProgramCache;
+    use super::SourceWatcher;
+    use super::Defines;
 
     use std::fs;
+    use std::env;
+    use std::path::PathBuf;
 
     #[test]
-    fn missing_file() {
-        let mut path = fs::canonicalize(".").unwrap();
-        path.push("shaders");
-        path.push("error.glsl");
-
-        let x = ShaderPack::new(&path);
-        if let Ok(_) = x {
-            assert!(false);
-        }
-    }
+    fn stale_watch() {
+        use std::io::Write;
+        use std::thread;
+        use std::time::Duration;
+
+        let dir = env::temp_dir().join("rquarfs_stale_watch");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("lib")).unwrap();
+        let dir = fs::canonicalize(&dir).unwrap();
+        let pack = dir.join("pack.glsl");
+        let touch = |file: &PathBuf| {
+            fs::File::create(file).and_then(|mut f| f.write_all(b"//\n")).unwrap();
+            thread::sleep(Duration::from_millis(800));
+        };
+        touch(&pack);
 
-    #[test]
-    fn single_file() {
+        let mut watcher = SourceWatcher::new(pack.clone(), vec![pack.clone()]);
+        touch(&pack);
+        assert!(watcher.changed());
 
-        let mut path = fs::canonicalize(".").unwrap();
-        path.push("shaders");
-        path.push("geom.glsl");
+        // files the build does not use are ignored
+        touch(&dir.join("lib/other.glsl"));
+        assert!(!watcher.changed());
 
-        let x = ShaderPack::new(&path);
-        match x {
-            Err(_) => assert!(false),
-            Ok(x) => println!("{:?}", x),
-        }
+        // a failed build waits for anything, like an include which did not exist
+        watcher.set_stale();
+        touch(&dir.join("lib/missing.glsl"));
+        assert!(watcher.changed());
+
+        watcher.set_sources(vec![pack.clone()]);
+        touch(&dir.join("lib/missing.glsl"));
+        assert!(!watcher.changed());
     }
 
     #[test]
     fn create() {
         use renderer::context::Context;
-        let mut ctx = Context::new_headless(100, 100).expect("create headless context");
+        let ctx = Context::new_headless(100, 100).expect("create headless context");
 
         let bad = ProgramReloader::new(&ctx, "nonsense");
         assert!(bad.is_err());
         let good = ProgramReloader::new(&ctx, "test");
         assert!(good.is_ok());
     }
+
+    #[test]
+    fn permutations() {
+        use renderer::context::Context;
+        let ctx = Context::new_headless(100, 100).expect("create headless context");
+
+        let mut cache = ProgramCache::new();
+        let plain = cache.load(&ctx, "terrain_texture", &Defines::new()).expect("plain");
+        let shadows = Defines::new().define("SHADOWS", "1");
+        let shadowed = cache.load(&ctx, "terrain_texture", &shadows).expect("shadows");
+        assert!(plain != shadowed);
+        assert_eq!(cache.load(&ctx, "terrain_texture", &shadows).unwrap(), shadowed);
+    }
 }
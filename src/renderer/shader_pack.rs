@@ -0,0 +1,900 @@
+use regex::*;
+
+use std::path;
+use std::fs;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::fmt;
+
+/// paths are printed relative to the working directory, as in shaders/terrain_texture.glsl
+pub fn display_path(file: &path::Path) -> String {
+    if let Ok(cwd) = fs::canonicalize(".") {
+        if let Ok(relative) = file.strip_prefix(&cwd) {
+            return format!("{}", relative.display());
+        }
+    }
+    format!("{}", file.display())
+}
+
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+// Permutations:
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+/// set of #define NAME VALUE injected right after the #version line,
+/// ordered so it can be used as key for the programs cache.
+#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Defines {
+    values: BTreeMap<String, String>,
+}
+
+impl Defines {
+    pub fn new() -> Defines {
+        Defines { values: BTreeMap::new() }
+    }
+
+    /// builds a set out of NAME=VALUE strings, NAME alone means NAME=1
+    pub fn from_list(list: &[&str]) -> Defines {
+        let mut res = Defines::new();
+        for item in list {
+            let mut split = item.splitn(2, '=');
+            let name = split.next().unwrap_or("").trim();
+            let value = split.next().unwrap_or("1").trim();
+            if !name.is_empty() {
+                res.set(name, value);
+            }
+        }
+        res
+    }
+
+    pub fn define(mut self, name: &str, value: &str) -> Defines {
+        self.set(name, value);
+        self
+    }
+
+    pub fn set(&mut self, name: &str, value: &str) {
+        self.values.insert(name.to_string(), value.to_string());
+    }
+
+    fn lines(&self) -> Vec<String> {
+        self.values
+            .iter()
+            .map(|(name, value)| format!("#define {} {}", name, value))
+            .collect()
+    }
+}
+
+impl fmt::Display for Defines {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let list: Vec<String> = self.values
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect();
+        write!(f, "[{}]", list.join(", "))
+    }
+}
+
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+//  Shader Pack,
+//  this implements One file shaders, one day this would be an stand alone library
+//  we do not compile the shaders, just do a basic parsing to extract them from a
+//  source code-like file
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+#[derive(Debug)]
+pub enum ShaderParseError {
+    InvalidPath,
+    SyntaxError(String),
+    MissingShader,
+    CompileError {
+        file: String,
+        line: usize,
+        stage: String,
+        message: String,
+    },
+    IncludeNotFound(String),
+    IncludeCycle(String),
+}
+
+#[derive(Debug)]
+pub struct ShaderPack {
+    pub vertex: String,
+    pub fragment: String,
+    pub tess_control: Option<String>,
+    pub tess_eval: Option<String>,
+    pub geom: Option<String>,
+    pub compute: Option<String>,
+    /// the pack file comes first, followed by all included files
+    pub files: Vec<path::PathBuf>,
+    /// for each stage, where its lines come from
+    line_tables: Vec<(ParseState, Vec<LineOffset>)>,
+}
+
+/// name of the common block used when a section does not ask for a specific one
+const DEFAULT_COMMON: &'static str = "default";
+/// stages marked with this modifier do not get any common text
+const NO_COMMON: &'static str = "none";
+
+/// reads a pack or included file, the renderer reads through the asset source
+pub type ReadFn = Fn(&path::Path) -> Option<String>;
+
+/// plain read from disk
+pub fn read_file(path: &path::Path) -> Option<String> {
+    use std::io::prelude::*;
+    let mut text = String::new();
+    fs::File::open(path).and_then(|mut f| f.read_to_string(&mut text)).ok().map(|_| text)
+}
+
+/// resolves . and .. without touching the filesystem, files may not be on disk
+fn normalize(path: &path::Path) -> path::PathBuf {
+    let mut res = path::PathBuf::new();
+    for component in path.components() {
+        match component {
+            path::Component::CurDir => {}
+            path::Component::ParentDir => {
+                res.pop();
+            }
+            c => res.push(c.as_os_str()),
+        }
+    }
+    res
+}
+
+impl ShaderPack {
+    pub fn new(path: &path::PathBuf, defines: &Defines) -> Result<ShaderPack, ShaderParseError> {
+        ShaderPack::load(path, defines, &read_file)
+    }
+
+    /// same as new, files are read with the given function
+    pub fn load(path: &path::Path,
+                defines: &Defines,
+                read: &ReadFn)
+                -> Result<ShaderPack, ShaderParseError> {
+        // parse file
+        let text = read(path).ok_or(ShaderParseError::InvalidPath)?;
+        let units = parse_text(&text)?;
+        // includes are resolved relative to the shaders folder
+        let dir = path.parent().ok_or(ShaderParseError::InvalidPath)?;
+        ShaderPack::from_sections(units, path, dir, defines, read)
+    }
+
+    #[allow(dead_code)]
+    pub fn from_source(code: &str,
+                   dir: &path::Path,
+                   defines: &Defines)
+                   -> Result<ShaderPack, ShaderParseError> {
+        let units = parse_text(code)?;
+        ShaderPack::from_sections(units, &dir.join("<source>"), dir, defines, &read_file)
+    }
+
+    fn from_sections(units: Vec<ShaderParse>,
+                     path: &path::Path,
+                     dir: &path::Path,
+                     defines: &Defines,
+                     read: &ReadFn)
+                     -> Result<ShaderPack, ShaderParseError> {
+
+        let mut res = ShaderPack {
+            vertex: "".to_string(),
+            fragment: "".to_string(),
+            tess_control: None,
+            tess_eval: None,
+            geom: None,
+            compute: None,
+            files: vec![path.to_path_buf()],
+            line_tables: Vec::new(),
+        };
+
+        // common blocks go first, they can be declared anywhere in the file
+        let mut commons: BTreeMap<String, Vec<ShaderParse>> = BTreeMap::new();
+        let mut stages = Vec::new();
+        for unit in units {
+            match unit.kind {
+                ParseState::Common => {
+                    let name = unit.modifier.clone().unwrap_or(DEFAULT_COMMON.to_string());
+                    commons.entry(name).or_insert_with(Vec::new).push(unit);
+                }
+                _ => stages.push(unit),
+            }
+        }
+
+        let no_common = Vec::new();
+        for unit in stages {
+
+            let common = match unit.modifier.as_ref().map(|m| m.as_str()) {
+                None => commons.get(DEFAULT_COMMON).unwrap_or(&no_common),
+                Some(NO_COMMON) => &no_common,
+                Some(name) => {
+                    match commons.get(name) {
+                        Some(common) => common,
+                        None => {
+                            let msg = format!("Unknow common section {}", name);
+                            return Err(ShaderParseError::SyntaxError(msg));
+                        }
+                    }
+                }
+            };
+
+            // every stage is a translation unit on its own, include guards start over
+            let mut code = MappedText::new();
+            {
+                let mut state = IncludeState {
+                    dir: dir,
+                    read: read,
+                    files: &mut res.files,
+                    stack: Vec::new(),
+                    included: BTreeSet::new(),
+                };
+                expand_includes(&compose(common, &unit, defines), &mut state, &mut code)?;
+            }
+
+            res.line_tables.push((unit.kind, code.offsets));
+            let code = code.text;
+
+            match unit.kind {
+                ParseState::Common => unreachable!(),
+                ParseState::Vertex => res.vertex = code,
+                ParseState::Fragment => res.fragment = code,
+                ParseState::TessC => res.tess_control = Some(code),
+                ParseState::TessE => res.tess_eval = Some(code),
+                ParseState::Geom => res.geom = Some(code),
+                ParseState::Compute => res.compute = Some(code),
+            }
+
+        }
+
+        // validate, a compute shader goes alone
+        if res.compute.is_some() {
+            if !res.vertex.is_empty() || !res.fragment.is_empty() || res.tess_control.is_some() ||
+               res.tess_eval.is_some() || res.geom.is_some() {
+                let msg = "compute section can not be mixed with other stages".to_string();
+                return Err(ShaderParseError::SyntaxError(msg));
+            }
+            return Ok(res);
+        }
+
+        // otherwise, fragment and vertex can not be empty
+        if res.vertex.is_empty() || res.fragment.is_empty() {
+            println!("{:?}", res);
+            return Err(ShaderParseError::MissingShader);
+        }
+
+        Ok(res)
+    }
+
+    /// the stages present in the pack, in pipeline order
+    #[allow(dead_code)]
+    pub fn stages(&self) -> Vec<(ParseState, &str)> {
+        let mut res = Vec::new();
+        if let Some(ref compute) = self.compute {
+            res.push((ParseState::Compute, compute.as_str()));
+            return res;
+        }
+        res.push((ParseState::Vertex, self.vertex.as_str()));
+        if let Some(ref code) = self.tess_control {
+            res.push((ParseState::TessC, code.as_str()));
+        }
+        if let Some(ref code) = self.tess_eval {
+            res.push((ParseState::TessE, code.as_str()));
+        }
+        if let Some(ref code) = self.geom {
+            res.push((ParseState::Geom, code.as_str()));
+        }
+        res.push((ParseState::Fragment, self.fragment.as_str()));
+        res
+    }
+
+    /// finds where a line of a stage comes from, (file, line)
+    fn locate(&self, stage: usize, line: usize) -> Option<(&path::Path, usize)> {
+        self.line_tables
+            .iter()
+            .find(|&&(kind, _)| kind as usize == stage)
+            .and_then(|&(_, ref table)| locate_line(table, line))
+            .map(|(file, line)| (self.files[file].as_path(), line))
+    }
+
+    /// rewrites a driver log so every message points to the original file and line.
+    /// the stage is known thanks to the #line directive, which uses the stage as source number.
+    /// the first error found is returned as well.
+    pub fn map_log(&self, log: &str) -> (String, Option<ShaderParseError>) {
+        let mut res = String::new();
+        let mut first = None;
+        for log_line in log.lines() {
+            let mapped = LOG_RE.captures(log_line).and_then(|cap| {
+                let stage = cap.get(2).map_or("", |m| m.as_str()).parse::<usize>().ok()?;
+                let line = cap.get(3)
+                    .or(cap.get(4))
+                    .map_or("", |m| m.as_str())
+                    .parse::<usize>()
+                    .ok()?;
+                let (file, line) = self.locate(stage, line)?;
+                let message = match cap.get(1) {
+                    Some(prefix) => {
+                        format!("{}: {}",
+                                prefix.as_str().trim().trim_right_matches(':').to_lowercase(),
+                                cap.get(5).map_or("", |m| m.as_str()))
+                    }
+                    None => cap.get(5).map_or("", |m| m.as_str()).to_string(),
+                };
+                Some((display_path(file), line, stage, message))
+            });
+
+            match mapped {
+                Some((file, line, stage, message)) => {
+                    res.push_str(&format!("{}:{}: {}\n", file, line, message));
+                    if first.is_none() {
+                        first = Some(ShaderParseError::CompileError {
+                            file: file,
+                            line: line,
+                            stage: stage_name(stage).to_string(),
+                            message: message,
+                        });
+                    }
+                }
+                None => {
+                    res.push_str(log_line);
+                    res.push_str("\n");
+                }
+            }
+        }
+        (res, first)
+    }
+}
+
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+//  source mapping, every line of the generated stages knows where it comes from
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+/// index of the pack file in the files list
+const MAIN_FILE: usize = 0;
+
+/// a run of consecutive lines which come from consecutive lines of the same file.
+/// all numbers are 1 based, like in the compiler logs
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct LineOffset {
+    start: usize,
+    file: usize,
+    line: usize,
+}
+
+fn locate_line(table: &[LineOffset], line: usize) -> Option<(usize, usize)> {
+    table.iter()
+        .rev()
+        .find(|offset| offset.start <= line)
+        .map(|offset| (offset.file, offset.line + (line - offset.start)))
+}
+
+/// text plus the offset table for its lines
+#[derive(Debug)]
+struct MappedText {
+    text: String,
+    offsets: Vec<LineOffset>,
+    count: usize,
+}
+
+impl MappedText {
+    fn new() -> MappedText {
+        MappedText {
+            text: String::new(),
+            offsets: Vec::new(),
+            count: 0,
+        }
+    }
+
+    fn push_line(&mut self, line: &str, file: usize, src_line: usize) {
+        self.count += 1;
+        self.text.push_str(line);
+        self.text.push_str("\n");
+
+        // only start a new run when the line does not follow the previous one
+        if let Some(last) = self.offsets.last() {
+            if last.file == file && last.line + (self.count - last.start) == src_line {
+                return;
+            }
+        }
+        self.offsets.push(LineOffset {
+            start: self.count,
+            file: file,
+            line: src_line,
+        });
+    }
+
+    fn lines(&self) -> Vec<(&str, usize, usize)> {
+        self.text
+            .lines()
+            .enumerate()
+            .map(|(i, l)| {
+                let (file, line) = locate_line(&self.offsets, i + 1).unwrap_or((MAIN_FILE, 0));
+                (l, file, line)
+            })
+            .collect()
+    }
+}
+
+pub fn stage_name(stage: usize) -> &'static str {
+    match stage {
+        x if x == ParseState::Vertex as usize => "vertex",
+        x if x == ParseState::Fragment as usize => "fragment",
+        x if x == ParseState::TessC as usize => "tessellation_control",
+        x if x == ParseState::TessE as usize => "tessellation_evaluation",
+        x if x == ParseState::Geom as usize => "geometry",
+        x if x == ParseState::Compute as usize => "compute",
+        _ => "unknown",
+    }
+}
+
+/// #line directive placed at `at` so the following lines keep their numbers.
+/// before 330 the directive sets the number of the next line minus one.
+fn line_directive(version: Option<&str>, at: usize, stage: ParseState) -> String {
+    let number = version.and_then(|v| {
+            v.split_whitespace().nth(1).and_then(|n| n.parse::<u32>().ok())
+        })
+        .unwrap_or(110);
+    let es = version.map_or(false, |v| v.contains("es"));
+    let next = if number >= 330 || (es && number >= 300) {
+        at + 1
+    } else {
+        at
+    };
+    format!("#line {} {}", next, stage as usize)
+}
+
+/// stage code is prepended with the common text, the #version directive must be the first
+/// thing in the shader so it is lifted to the top. the one in the stage wins over the common.
+/// other version lines are left empty so the rest of lines keep their position.
+fn compose(common: &[ShaderParse], stage: &ShaderParse, defines: &Defines) -> MappedText {
+
+    let find_version = |unit: &ShaderParse| {
+        unit.code
+            .lines()
+            .enumerate()
+            .find(|&(_, l)| VERSION_RE.is_match(l))
+            .map(|(i, l)| (l.trim().to_string(), unit.first_line + i))
+    };
+    let version = find_version(stage).or_else(|| common.iter().filter_map(&find_version).next());
+
+    // defines go right after the version, they do not exist in the source
+    let mut res = MappedText::new();
+    match version {
+        Some((ref v, line)) => {
+            res.push_line(v, MAIN_FILE, line);
+            for define in defines.lines() {
+                res.push_line(&define, MAIN_FILE, line);
+            }
+            let at = res.count + 1;
+            res.push_line(&line_directive(Some(v), at, stage.kind), MAIN_FILE, line);
+        }
+        None => {
+            for define in defines.lines() {
+                res.push_line(&define, MAIN_FILE, stage.first_line);
+            }
+            let at = res.count + 1;
+            res.push_line(&line_directive(None, at, stage.kind),
+                          MAIN_FILE,
+                          stage.first_line)
+        }
+    }
+
+    for unit in common.iter().chain(Some(stage)) {
+        for (i, l) in unit.code.lines().enumerate() {
+            let l = if VERSION_RE.is_match(l) { "" } else { l };
+            res.push_line(l, MAIN_FILE, unit.first_line + i);
+        }
+    }
+    res
+}
+
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+//  includes
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+/// files being included while building one stage
+struct IncludeState<'a, 'b> {
+    dir: &'a path::Path,
+    read: &'a ReadFn,
+    files: &'b mut Vec<path::PathBuf>,
+    stack: Vec<path::PathBuf>,
+    included: BTreeSet<path::PathBuf>,
+}
+
+/// replaces every #include "file" line with the content of the file.
+/// each file is included once per stage, as if it had include guards, and we fail if a file
+/// ends up including itself.
+fn expand_includes(code: &MappedText,
+                   state: &mut IncludeState,
+                   res: &mut MappedText)
+                   -> Result<(), ShaderParseError> {
+    for (line, file_idx, src_line) in code.lines() {
+        let cap = INCLUDE_RE.captures(line);
+        if cap.is_none() {
+            res.push_line(line, file_idx, src_line);
+            continue;
+        }
+        let name = cap.unwrap().get(1).map_or("", |m| m.as_str()).to_string();
+
+        let file = normalize(&state.dir.join(&name));
+
+        if state.stack.contains(&file) {
+            let mut chain: Vec<String> =
+                state.stack.iter().map(|f| format!("{}", f.display())).collect();
+            chain.push(format!("{}", file.display()));
+            return Err(ShaderParseError::IncludeCycle(chain.join(" -> ")));
+        }
+        if state.included.contains(&file) {
+            res.push_line("", file_idx, src_line);
+            continue;
+        }
+
+        let text = (state.read)(&file).ok_or(ShaderParseError::IncludeNotFound(name.clone()))?;
+
+        let idx = match state.files.iter().position(|f| *f == file) {
+            Some(idx) => idx,
+            None => {
+                state.files.push(file.clone());
+                state.files.len() - 1
+            }
+        };
+        let mut included = MappedText::new();
+        for (i, l) in text.lines().enumerate() {
+            included.push_line(l, idx, i + 1);
+        }
+
+        state.included.insert(file.clone());
+        state.stack.push(file);
+        expand_includes(&included, state, res)?;
+        state.stack.pop();
+    }
+    Ok(())
+}
+
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+//  parser
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+// parse state machine
+// we are looking for // <- TEXT
+// an optional modifier can follow the section name:
+//   // <- COMMON(name)    declares a named common block
+//   // <- VERTEX(name)    uses the named common block instead of the default one
+//   // <- VERTEX(none)    does not use any common text
+// very basic regex line based parsing
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ParseState {
+    Common,
+    Vertex,
+    Fragment,
+    TessC,
+    TessE,
+    Geom,
+    Compute,
+}
+
+#[derive(Debug)]
+struct ShaderParse {
+    kind: ParseState,
+    modifier: Option<String>,
+    code: String,
+    /// line of the file where the code starts
+    first_line: usize,
+}
+
+lazy_static! {
+    static ref RE: Regex = Regex::new(r"\s*//\s*<-\s*(\w+)(?:\s*\(\s*(\w+)\s*\))?").unwrap();
+    static ref VERSION_RE: Regex = Regex::new(r"^\s*#\s*version\b").unwrap();
+    static ref INCLUDE_RE: Regex = Regex::new(r#"^\s*#\s*include\s+"([^"]+)""#).unwrap();
+    // mesa:   0:12(5): error: ...
+    // nvidia: 0(12) : error C0000: ...
+    // amd:    ERROR: 0:12: ...
+    static ref LOG_RE: Regex =
+        Regex::new(r"^\s*(ERROR:\s*|WARNING:\s*)?(\d+)(?::(\d+)(?:\(\d+\))?|\((\d+)\))\s*:\s*(.*)$")
+            .unwrap();
+}
+
+struct Parser {
+    accum: String,
+    state: ParseState,
+    modifier: Option<String>,
+    first_line: usize,
+    line: usize,
+    items: Vec<ShaderParse>,
+}
+
+impl Parser {
+    fn new() -> Parser {
+        use self::ParseState::*;
+        Parser {
+            accum: "".to_string(),
+            state: Common,
+            modifier: None,
+            first_line: 1,
+            line: 0,
+            items: Vec::new(),
+        }
+    }
+
+    fn parse_line(&mut self, line: String) -> Result<(), ShaderParseError> {
+        self.line += 1;
+        if let Some(cap) = RE.captures(&line) {
+            let name = cap.get(1).map_or("", |m| m.as_str()).to_lowercase();
+            let modifier = cap.get(2).map(|m| m.as_str().to_lowercase());
+            self.items.push(ShaderParse {
+                kind: self.state,
+                modifier: self.modifier.take(),
+                code: self.accum.clone(),
+                first_line: self.first_line,
+            });
+            self.accum = String::new();
+            self.modifier = modifier;
+            self.first_line = self.line + 1;
+
+            if name == "common" {
+                self.state = ParseState::Common;
+            } else if name == "vertex" {
+                self.state = ParseState::Vertex;
+            } else if name == "fragment" {
+                self.state = ParseState::Fragment;
+            } else if name == "tessellation_control" {
+                self.state = ParseState::TessC;
+            } else if name == "tessellation_evaluation" {
+                self.state = ParseState::TessE;
+            } else if name == "geometry" {
+                self.state = ParseState::Geom;
+            } else if name == "compute" {
+                self.state = ParseState::Compute;
+            } else {
+                return Err(ShaderParseError::SyntaxError(format!("Unknow section {}", name)
+                    .to_string()));
+            }
+        } else {
+            self.accum.push_str(line.as_str());
+            self.accum.push_str("\n");
+        }
+        Ok(())
+    }
+    fn get_items(mut self) -> Vec<ShaderParse> {
+        self.items.push(ShaderParse {
+            kind: self.state,
+            modifier: self.modifier,
+            code: self.accum,
+            first_line: self.first_line,
+        });
+        self.items
+    }
+}
+
+fn parse_text(text: &str) -> Result<Vec<ShaderParse>, ShaderParseError> {
+    let mut parser = Parser::new();
+    for l in text.lines() {
+        parser.parse_line(l.to_string())?;
+    }
+    Ok(parser.get_items())
+}
+
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+//  Tests
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+#[cfg(test)]
+mod tests {
+
+    use super::ShaderPack;
+    use super::ShaderParseError;
+    use super::ParseState;
+    use super::Defines;
+
+    use std::fs;
+    use std::env;
+    use std::path::PathBuf;
+
+    fn shaders_dir() -> PathBuf {
+        let mut path = fs::canonicalize(".").unwrap();
+        path.push("shaders");
+        path
+    }
+
+    #[test]
+    fn missing_file() {
+        let mut path = fs::canonicalize(".").unwrap();
+        path.push("shaders");
+        path.push("error.glsl");
+
+        let x = ShaderPack::new(&path, &Defines::new());
+        if let Ok(_) = x {
+            assert!(false);
+        }
+    }
+
+    #[test]
+    fn single_file() {
+
+        let mut path = fs::canonicalize(".").unwrap();
+        path.push("shaders");
+        path.push("geom.glsl");
+
+        let x = ShaderPack::new(&path, &Defines::new());
+        match x {
+            Err(_) => assert!(false),
+            Ok(x) => println!("{:?}", x),
+        }
+    }
+
+    #[test]
+    fn common_section() {
+        let code = "// <- COMMON\n\
+                    #version 330\n\
+                    uniform mat4 pvm;\n\
+                    // <- VERTEX\n\
+                    void main(){}\n\
+                    // <- FRAGMENT\n\
+                    #version 410 core\n\
+                    void main(){}\n";
+
+        let x = ShaderPack::from_source(code, &shaders_dir(), &Defines::new()).expect("parse");
+        assert!(x.vertex.starts_with("#version 330\n"));
+        assert!(x.vertex.contains("uniform mat4 pvm;"));
+        assert_eq!(x.vertex.matches("#version").count(), 1);
+
+        // the stage version overrides the common one
+        assert!(x.fragment.starts_with("#version 410 core\n"));
+        assert!(x.fragment.contains("uniform mat4 pvm;"));
+        assert_eq!(x.fragment.matches("#version").count(), 1);
+    }
+
+    #[test]
+    fn common_opt_out_and_override() {
+        let code = "// <- COMMON\n\
+                    #version 330\n\
+                    uniform mat4 pvm;\n\
+                    // <- COMMON(quad)\n\
+                    #version 140\n\
+                    uniform sampler2D quad_texture;\n\
+                    // <- VERTEX(none)\n\
+                    void main(){}\n\
+                    // <- FRAGMENT(quad)\n\
+                    void main(){}\n";
+
+        let x = ShaderPack::from_source(code, &shaders_dir(), &Defines::new()).expect("parse");
+        assert!(!x.vertex.contains("#version"));
+        assert!(!x.vertex.contains("uniform"));
+        assert!(x.fragment.starts_with("#version 140\n"));
+        assert!(x.fragment.contains("quad_texture"));
+        assert!(!x.fragment.contains("pvm"));
+
+        let bad = "// <- VERTEX(nonsense)\nvoid main(){}\n// <- FRAGMENT\nvoid main(){}\n";
+        assert!(ShaderPack::from_source(bad, &shaders_dir(), &Defines::new()).is_err());
+    }
+
+    #[test]
+    fn include_guards() {
+        let code = "// <- COMMON\n\
+                    #version 410 core\n\
+                    uniform mat4 pvm;\n\
+                    uniform sampler2D height_map;\n\
+                    #include \"lib/terrain_height.glsl\"\n\
+                    // <- VERTEX\n\
+                    #include \"lib/terrain_height.glsl\"\n\
+                    void main(){}\n\
+                    // <- FRAGMENT(none)\n\
+                    void main(){}\n";
+
+        let x = ShaderPack::from_source(code, &shaders_dir(), &Defines::new()).expect("parse");
+        assert_eq!(x.vertex.matches("vec4 project(").count(), 1);
+        assert_eq!(x.fragment.matches("vec4 project(").count(), 0);
+        assert_eq!(x.files.len(), 2);
+
+        let missing = "// <- VERTEX\n#include \"nonsense.glsl\"\n// <- FRAGMENT\n";
+        match ShaderPack::from_source(missing, &shaders_dir(), &Defines::new()) {
+            Err(ShaderParseError::IncludeNotFound(_)) => {}
+            x => panic!("unexpected {:?}", x),
+        }
+    }
+
+    #[test]
+    fn include_cycle() {
+        use std::io::Write;
+
+        let mut dir = env::temp_dir();
+        dir.push("rquarfs_include_cycle");
+        let _ = fs::create_dir_all(&dir);
+        fs::File::create(dir.join("a.glsl"))
+            .and_then(|mut f| f.write_all(b"#include \"b.glsl\"\n"))
+            .unwrap();
+        fs::File::create(dir.join("b.glsl"))
+            .and_then(|mut f| f.write_all(b"#include \"a.glsl\"\n"))
+            .unwrap();
+
+        let code = "// <- VERTEX\n#include \"a.glsl\"\n// <- FRAGMENT\nvoid main(){}\n";
+        match ShaderPack::from_source(code, &dir, &Defines::new()) {
+            Err(ShaderParseError::IncludeCycle(_)) => {}
+            x => panic!("unexpected {:?}", x),
+        }
+    }
+
+    #[test]
+    fn line_mapping() {
+        let code = "// <- COMMON\n\
+                    #version 410 core\n\
+                    uniform mat4 pvm;\n\
+                    uniform sampler2D height_map;\n\
+                    #include \"lib/terrain_height.glsl\"\n\
+                    // <- VERTEX\n\
+                    void main(){\n\
+                        undefined_call();\n\
+                    }\n\
+                    // <- FRAGMENT\n\
+                    void main(){}\n";
+
+        let x = ShaderPack::from_source(code, &shaders_dir(), &Defines::new()).expect("parse");
+
+        // version lifted and #line directive
+        let vertex: Vec<&str> = x.vertex.lines().collect();
+        assert_eq!(vertex[0], "#version 410 core");
+        assert_eq!(vertex[1], "#line 3 1");
+
+        // the call is in the 8th line of the source
+        let n = vertex.iter().position(|l| l.contains("undefined_call")).unwrap() + 1;
+        let (file, line) = x.locate(ParseState::Vertex as usize, n).unwrap();
+        assert_eq!(file, x.files[0].as_path());
+        assert_eq!(line, 8);
+
+        // and the helpers come from the included file
+        let n = vertex.iter().position(|l| l.contains("vec4 project(")).unwrap() + 1;
+        let (file, line) = x.locate(ParseState::Vertex as usize, n).unwrap();
+        assert!(file.ends_with("lib/terrain_height.glsl"));
+        assert!(line > 1);
+
+        // mesa and nvidia logs are rewritten
+        let log = format!("1:{}(5): error: no function with name 'undefined_call'\n\
+                           {}({}) : error C1008: undefined variable\n",
+                          vertex.iter().position(|l| l.contains("undefined_call")).unwrap() + 1,
+                          ParseState::Vertex as usize,
+                          vertex.iter().position(|l| l.contains("undefined_call")).unwrap() + 1);
+        let (mapped, first) = x.map_log(&log);
+        assert!(mapped.lines().all(|l| l.contains(":8: error")));
+        match first {
+            Some(ShaderParseError::CompileError { line, ref stage, .. }) => {
+                assert_eq!(line, 8);
+                assert_eq!(stage, "vertex");
+            }
+            x => panic!("unexpected {:?}", x),
+        }
+    }
+
+    #[test]
+    fn compute_section() {
+        let code = "// <- COMMON\n\
+                    #version 430\n\
+                    // <- COMPUTE\n\
+                    layout(local_size_x = 16, local_size_y = 16) in;\n\
+                    void main(){}\n";
+
+        let x = ShaderPack::from_source(code, &shaders_dir(), &Defines::new()).expect("parse");
+        let compute = x.compute.expect("compute stage");
+        assert!(compute.starts_with("#version 430\n"));
+        assert!(compute.contains("local_size_x"));
+
+        let mixed = "// <- COMPUTE\nvoid main(){}\n// <- FRAGMENT\nvoid main(){}\n";
+        assert!(ShaderPack::from_source(mixed, &shaders_dir(), &Defines::new()).is_err());
+    }
+
+    #[test]
+    fn defines() {
+        let code = "// <- COMMON\n\
+                    #version 410 core\n\
+                    // <- VERTEX\n\
+                    void main(){}\n\
+                    // <- FRAGMENT(none)\n\
+                    void main(){}\n";
+
+        let defines = Defines::from_list(&["SHADOWS=1", "DEBUG_LOD"]);
+        assert_eq!(defines, Defines::new().define("DEBUG_LOD", "1").define("SHADOWS", "1"));
+
+        let x = ShaderPack::from_source(code, &shaders_dir(), &defines).expect("parse");
+        let vertex: Vec<&str> = x.vertex.lines().collect();
+        assert_eq!(vertex[0], "#version 410 core");
+        assert_eq!(vertex[1], "#define DEBUG_LOD 1");
+        assert_eq!(vertex[2], "#define SHADOWS 1");
+        assert_eq!(vertex[3], "#line 5 1");
+        assert!(x.fragment.starts_with("#define DEBUG_LOD 1\n#define SHADOWS 1\n#line 3 2"));
+    }
+
+}
@@ -4,7 +4,10 @@ use renderer::context;
 use renderer::context::Program;
 use renderer::context::Context;
 use renderer::shader::ProgramReloader;
+use renderer::uniform_check::check_uniforms;
+use renderer::errors::RenderError;
 use glium::texture;
+use glium::draw_parameters::TimeElapsedQuery;
 use glium::Surface;
 use cgmath::Matrix4;
 
@@ -18,22 +21,16 @@ struct QuadVert {
     tex_coords: (f32, f32),
 }
 
-pub struct ScreenSpacePass<'a> {
+pub struct ScreenSpacePass {
     quad_buffer: glium::vertex::VertexBufferAny,
     program: ProgramReloader,
-    fb: glium::framebuffer::SimpleFrameBuffer<'a>,
-    size: (u32, u32),
 }
 
 
 implement_vertex!(QuadVert, position, tex_coords);
 
-impl<'a> ScreenSpacePass<'a> {
-    pub fn new(ctx: &context::Context,
-               program: &str,
-               output_texture: &'a glium::texture::Texture2d,
-               depth_buffer: &'a texture::DepthTexture2d)
-               -> ScreenSpacePass<'a> {
+impl ScreenSpacePass {
+    pub fn new(ctx: &context::Context, program: &str) -> ScreenSpacePass {
 
         let quad_buffer = glium::VertexBuffer::new(ctx.display(),
                                                    &[QuadVert {
@@ -53,20 +50,11 @@ impl<'a> ScreenSpacePass<'a> {
                                                          tex_coords: (1.0, 0.0),
                                                      }]);
 
-
-        let mut frame = glium::framebuffer::SimpleFrameBuffer::with_depth_buffer(ctx.display(),
-                                                                                 output_texture,
-                                                                                 depth_buffer)
-            .unwrap();
-        frame.clear_depth(1.0);
         let program = ProgramReloader::new(ctx, program).unwrap();
-        let dim = frame.get_dimensions();
 
         ScreenSpacePass {
             quad_buffer: quad_buffer.unwrap().into(),
             program: program,
-            fb: frame,
-            size: dim,
         }
     } // new
 
@@ -74,39 +62,59 @@ impl<'a> ScreenSpacePass<'a> {
         self.program.update(ctx, delta);
     }
 
-    pub fn execute_pass(&mut self,
+    /// draws the full screen quad into the output texture.
+    /// the framebuffer is built every time, glium keeps the GL object cached.
+    pub fn execute_pass(&self,
+                        ctx: &Context,
+                        output_texture: &glium::texture::Texture2d,
                         inverse_matrix: &Matrix4<f32>,
                         input_texture: &glium::texture::Texture2d,
                         depth_texture: &texture::DepthTexture2d,
-                        noise_texture: &glium::texture::Texture2d) {
+                        noise_texture: &glium::texture::Texture2d,
+                        query: Option<&TimeElapsedQuery>)
+                        -> Result<(), RenderError> {
         let uniforms = uniform! {
                 input_texture: input_texture,
                 depth_texture: depth_texture,
                 noise_texture: noise_texture,
-                inversei_matrix: Into::<[[f32; 4]; 4]>::into(*inverse_matrix),
-                frame_size: self.size,
+                inverse_matrix: Into::<[[f32; 4]; 4]>::into(*inverse_matrix),
+                frame_size: output_texture.dimensions(),
         };
+        self.draw(ctx, output_texture, &uniforms, query)
+    }
+
+    /// same quad, for passes with other inputs
+    pub fn draw<U>(&self,
+                   ctx: &Context,
+                   output_texture: &glium::texture::Texture2d,
+                   uniforms: &U,
+                   query: Option<&TimeElapsedQuery>)
+                   -> Result<(), RenderError>
+        where U: glium::uniforms::Uniforms
+    {
+        let mut fb = glium::framebuffer::SimpleFrameBuffer::new(ctx.display(), output_texture)
+            .map_err(|err| {
+                let err = format!("{}: {:?}", self.program.get_source(), err);
+                ctx.report_error(RenderError::Target(err))
+            })?;
+
+        check_uniforms(self.program.get_program(),
+                       self.program.get_source(),
+                       uniforms);
 
         let parameters = glium::DrawParameters {
             backface_culling: glium::BackfaceCullingMode::CullCounterClockwise,
-            depth: glium::Depth {
-                test: glium::DepthTest::IfLessOrEqual,
-                write: false,
-                ..Default::default()
-            },
             polygon_mode: glium::PolygonMode::Fill,
             provoking_vertex: glium::draw_parameters::ProvokingVertex::LastVertex,
+            time_elapsed_query: query,
             ..Default::default()
         };
 
-        self.fb
-            .draw(&self.quad_buffer,
-                  glium::index::NoIndices(glium::index::PrimitiveType::TriangleStrip),
-                  self.program.get_program(),
-                  &uniforms,
-                  &parameters)
-            .unwrap();
-
-
+        fb.draw(&self.quad_buffer,
+                glium::index::NoIndices(glium::index::PrimitiveType::TriangleStrip),
+                self.program.get_program(),
+                uniforms,
+                &parameters)
+            .map_err(|err| ctx.report_error(RenderError::draw(self.program.get_source(), &err)))
     }
 }
@@ -83,4 +83,7 @@ impl Program for TexQuad {
     fn with_tess(&self) -> bool {
         self.quad_program.get_program().has_tessellation_shaders()
     }
+    fn get_source(&self) -> &str {
+        self.quad_program.get_source()
+    }
 }
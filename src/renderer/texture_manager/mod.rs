@@ -1,18 +1,17 @@
 use super::context::Context;
-use super::context::IdType;
 use super::context::ManagerError;
+use super::handle::{Handle, Registry, Texture};
 
-use std::collections::BTreeMap;
+use std::collections::HashMap;
 
 use glium::texture;
+use image;
 
 /// texture manager takes care of images to read from and to write to.
 ///  Textures
 ///  Depth buffer
 ///  and frame buffers which are backed up by the former two.
 
-trait Texture {}
-
 // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
 
 struct Texture2D {
@@ -27,25 +26,33 @@ struct Canvas {}
 
 // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
 
-struct TextureManager {
-    textures: BTreeMap<IdType, Texture2D>,
-    sinks: BTreeMap<IdType, Canvas>,
+pub struct TextureManager {
+    registry: Registry<Texture>,
+    /// by the slot of the handle
+    textures: Vec<Option<Texture2D>>,
+    /// canvases write into the texture of the same handle
+    sinks: HashMap<Handle<Texture>, Canvas>,
 }
 
 impl TextureManager {
-    fn new() -> TextureManager {
+    pub fn new() -> TextureManager {
         TextureManager {
-            textures: BTreeMap::new(),
-            sinks: BTreeMap::new(),
+            registry: Registry::new(),
+            textures: Vec::new(),
+            sinks: HashMap::new(),
         }
     }
 
     fn create_texture_2D(&mut self,
-                         ctx: &mut Context,
+                         ctx: &Context,
                          name: &str,
                          h: u32,
                          w: u32)
-                         -> Result<IdType, ManagerError> {
+                         -> Result<Handle<Texture>, ManagerError> {
+        if self.registry.lookup(name).is_some() {
+            return Err(ManagerError::ItemRedefinition);
+        }
+
         let tex =
             texture::Texture2d::empty_with_format(ctx.display(),
                                                   texture::UncompressedFloatFormat::F32F32F32F32,
@@ -55,40 +62,103 @@ impl TextureManager {
         if tex.is_err() {
             return Err(ManagerError::BackEndErrror);
         }
-        let id = ctx.get_id_for(name);
-        self.textures.insert(id, Texture2D { tex: tex.unwrap() });
-        Ok(id)
+        self.store(name, tex.unwrap())
     }
 
-    fn create_depth_texture(&mut self, ctx: &mut Context) -> Result<IdType, ManagerError> {
+    /// the slot is taken once the texture is there
+    fn store(&mut self,
+             name: &str,
+             tex: texture::Texture2d)
+             -> Result<Handle<Texture>, ManagerError> {
+        let handle = self.registry.insert(name)?;
+        let i = self.registry.index(handle)?;
+        while self.textures.len() <= i {
+            self.textures.push(None);
+        }
+        self.textures[i] = Some(Texture2D { tex: tex });
+        Ok(handle)
+    }
+
+    /// an image to sample from, with mipmaps. the first row of the image is at t = 0
+    pub fn create_texture_from_image(&mut self,
+                                     ctx: &Context,
+                                     name: &str,
+                                     img: image::RgbaImage)
+                                     -> Result<Handle<Texture>, ManagerError> {
+        if self.registry.lookup(name).is_some() {
+            return Err(ManagerError::ItemRedefinition);
+        }
+
+        let dimensions = img.dimensions();
+        let raw = texture::RawImage2d::from_raw_rgba(img.into_raw(), dimensions);
+        let tex = texture::Texture2d::with_mipmaps(ctx.display(),
+                                                   raw,
+                                                   texture::MipmapsOption::AutoGeneratedMipmaps)
+            .map_err(|_| ManagerError::BackEndErrror)?;
+        self.store(name, tex)
+    }
+
+    fn create_depth_texture(&mut self, ctx: &Context) -> Result<Handle<Texture>, ManagerError> {
         unimplemented!();
     }
 
-    fn create_canvas(&mut self, ctx: &mut Context, tex_id: IdType) -> Result<IdType, ManagerError> {
+    fn create_canvas(&mut self,
+                     ctx: &Context,
+                     tex: Handle<Texture>)
+                     -> Result<Handle<Texture>, ManagerError> {
         unimplemented!();
         // Err(ManagerError::ItemRedefinition)
     }
 
     fn create_canvas_with_depth(&mut self,
-                                ctx: &mut Context,
-                                tex_id: IdType,
-                                depth_id: IdType)
-                                -> Result<IdType, ManagerError> {
+                                ctx: &Context,
+                                tex: Handle<Texture>,
+                                depth: Handle<Texture>)
+                                -> Result<Handle<Texture>, ManagerError> {
         unimplemented!();
         // Err(ManagerError::ItemRedefinition)
     }
 
-    /// retrieves a texture we can read from
-    fn get_texture_src(&self, id: IdType) -> Option<&Texture2D> {
-        self.textures.get(&id)
+    /// retrieves a texture we can read from, none if the handle is stale
+    fn get_texture_src(&self, handle: Handle<Texture>) -> Option<&Texture2D> {
+        match self.registry.index(handle) {
+            Ok(i) => self.textures[i].as_ref(),
+            Err(_) => None,
+        }
+    }
+
+    /// the texture to give to the uniforms, none if the handle is stale
+    pub fn get_texture(&self, handle: Handle<Texture>) -> Option<&texture::Texture2d> {
+        self.get_texture_src(handle).map(|t| &t.tex)
+    }
+
+    /// the name, or the name with a number if it is taken
+    pub fn unique_name(&self, name: &str) -> String {
+        self.registry.unique_name(name)
     }
 
     /// retrieves a texture we can write to.
     /// (it has a draw method)
-    fn get_texture_sink(&self, id: IdType) -> Option<&Canvas> {
-        self.sinks.get(&id)
+    fn get_texture_sink(&self, handle: Handle<Texture>) -> Option<&Canvas> {
+        if !self.registry.is_valid(handle) {
+            return None;
+        }
+        self.sinks.get(&handle)
         // Err(ManagerError::ItemRedefinition)
     }
+
+    pub fn lookup(&self, name: &str) -> Option<Handle<Texture>> {
+        self.registry.lookup(name)
+    }
+
+    /// the texture and its canvas are dropped, the name can be used again
+    pub fn release(&mut self, handle: Handle<Texture>) -> Result<(), ManagerError> {
+        let i = self.registry.index(handle)?;
+        self.registry.release(handle)?;
+        self.textures[i] = None;
+        self.sinks.remove(&handle);
+        Ok(())
+    }
 }
 
 // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
@@ -105,14 +175,34 @@ mod tests {
 
     #[test]
     fn create() {
-        let mut ctx = Context::new_headless(100, 100).unwrap();
+        let ctx = Context::new_headless(100, 100).unwrap();
         let mut mgr = TextureManager::new();
-        let id = mgr.create_texture_2D(&mut ctx, "texture", 100, 100);
+        let id = mgr.create_texture_2D(&ctx, "texture", 100, 100);
         assert!(id.is_ok());
         let tex = mgr.get_texture_src(id.unwrap());
         assert!(tex.is_some());
         let tex = mgr.get_texture_sink(id.unwrap());
         assert!(tex.is_none());
 
+        assert!(mgr.create_texture_2D(&ctx, "texture", 100, 100).is_err());
+        assert_eq!(mgr.lookup("texture"), Some(id.unwrap()));
+        mgr.release(id.unwrap()).unwrap();
+        assert!(mgr.get_texture_src(id.unwrap()).is_none());
+
+    }
+
+    #[test]
+    fn from_image() {
+        let ctx = Context::new_headless(100, 100).unwrap();
+        let mut mgr = TextureManager::new();
+        let img = image::RgbaImage::from_pixel(4, 2, image::Rgba([255, 0, 0, 255]));
+        let id = mgr.create_texture_from_image(&ctx, "red", img).unwrap();
+        {
+            let tex = mgr.get_texture(id).unwrap();
+            assert_eq!((tex.get_width(), tex.get_height()), (4, Some(2)));
+        }
+        assert_eq!(mgr.unique_name("red"), "red#1");
+        mgr.release(id).unwrap();
+        assert!(mgr.get_texture(id).is_none());
     }
 }
@@ -0,0 +1,122 @@
+use glium;
+use glium::uniforms::Uniforms;
+
+use std::collections::BTreeSet;
+use std::sync::Mutex;
+
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+//  Uniform validation,
+//  in debug builds we compare the uniforms given to a draw call with the ones
+//  the program declares. glium silently ignores most of the mistakes.
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+lazy_static! {
+    // problems already printed, we do not want the same message every frame
+    static ref REPORTED: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+}
+
+/// arrays are reflected as name[0]
+fn base_name(name: &str) -> &str {
+    if name.ends_with("[0]") {
+        &name[..name.len() - 3]
+    } else {
+        name
+    }
+}
+
+/// prints the problems not yet reported for this shader, returns them
+fn report(source: &str, problems: Vec<String>) -> Vec<String> {
+    let mut reported = match REPORTED.lock() {
+        Ok(reported) => reported,
+        Err(poisoned) => poisoned.into_inner(),
+    };
+
+    let mut res = Vec::new();
+    for problem in problems {
+        let msg = format!("{}: {}", source, problem);
+        if reported.insert(msg.clone()) {
+            println!("{}", msg);
+            res.push(msg);
+        }
+    }
+    res
+}
+
+/// checks the uniforms supplied to a draw call against the program reflection,
+/// only in debug builds.
+pub fn check_uniforms<U>(program: &glium::Program, source: &str, uniforms: &U)
+    where U: Uniforms
+{
+    if !cfg!(debug_assertions) {
+        return;
+    }
+
+    let blocks = program.get_uniform_blocks();
+    let storage = program.get_shader_storage_blocks();
+
+    let mut problems = Vec::new();
+    let mut supplied = BTreeSet::new();
+    uniforms.visit_values(|name, value| {
+        supplied.insert(name.to_string());
+
+        let declared = program.get_uniform(name)
+            .or_else(|| program.get_uniform(&format!("{}[0]", name)));
+        match declared {
+            Some(uniform) => {
+                if !value.is_usable_with(&uniform.ty) {
+                    problems.push(format!("uniform {} supplied with the wrong type, \
+                                           the program expects {:?}",
+                                          name,
+                                          uniform.ty));
+                }
+            }
+            None => {
+                if !blocks.contains_key(name) && !storage.contains_key(name) {
+                    problems.push(format!("uniform {} supplied but not used", name));
+                }
+            }
+        }
+    });
+
+    for (name, _) in program.uniforms() {
+        // the ones glium sets by itself
+        if name.starts_with("gl_") {
+            continue;
+        }
+        if !supplied.contains(name) && !supplied.contains(base_name(name)) {
+            problems.push(format!("uniform {} declared but not supplied", base_name(name)));
+        }
+    }
+    for name in blocks.keys().chain(storage.keys()) {
+        if !supplied.contains(name) {
+            problems.push(format!("uniform block {} declared but not supplied", name));
+        }
+    }
+
+    report(source, problems);
+}
+
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+//  Tests
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+#[cfg(test)]
+mod tests {
+
+    use super::{base_name, report};
+
+    #[test]
+    fn array_names() {
+        assert_eq!(base_name("sample_sphere[0]"), "sample_sphere");
+        assert_eq!(base_name("pvm"), "pvm");
+    }
+
+    #[test]
+    fn reported_once() {
+        let problems = vec!["uniform pvm declared but not supplied".to_string()];
+        assert_eq!(report("shaders/reported_once.glsl", problems.clone()).len(), 1);
+        assert_eq!(report("shaders/reported_once.glsl", problems.clone()).len(), 0);
+        // but again for a different program
+        assert_eq!(report("shaders/reported_once_too.glsl", problems).len(), 1);
+    }
+}
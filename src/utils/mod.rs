@@ -1,7 +1,9 @@
 use glium;
 use time;
 
+use glium::index::PrimitiveType;
 use renderer::context::Context;
+use renderer::handle::{Geometry, Handle};
 
 use std::vec::*;
 use std::collections::BTreeMap;
@@ -60,13 +62,18 @@ impl PerformaceCounters {
 
         let end_t = time::precise_time_s();
 
+        self.add_sample(name, end_t - start_t);
+    }
+
+    /// a time measured somewhere else, in seconds
+    pub fn add_sample(&mut self, name: &str, seconds: f64) {
         if let Some(x) = self.times.get_mut(name.into()) {
-            x.0 += end_t - start_t;
+            x.0 += seconds;
             x.1 += 1;
             return;
         }
 
-        self.times.insert(name.into(), (end_t - start_t, 1));
+        self.times.insert(name.into(), (seconds, 1));
     }
 
     pub fn digest_measures(&mut self) {
@@ -135,13 +142,34 @@ impl PerformaceCounters {
     // TODO: iterator to retrieve the digested
 }
 
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Timestep {
+    /// wall clock time of the last iteration
+    Measured,
+    /// always the same, so the simulation does not depend on the machine speed
+    Fixed(f64),
+}
+
 /// infinite loop with iterations/second reporting every x seconds
 /// it will pass delta time to function body
-pub fn loop_with_report<'a, F: FnMut(f64, &mut PerformaceCounters)>(mut body: F, x: u32) {
+#[allow(dead_code)]
+pub fn loop_with_report<'a, F: FnMut(f64, &mut PerformaceCounters)>(body: F, x: u32) {
+    loop_with_timestep(body, x, Timestep::Measured)
+}
+
+/// same loop, the delta passed to the body follows the timestep.
+/// the report is always about the real time
+pub fn loop_with_timestep<F>(mut body: F, x: u32, step: Timestep)
+    where F: FnMut(f64, &mut PerformaceCounters)
+{
     let mut pc = PerformaceCounters::new();
+    let simulated = |measured: f64| match step {
+        Timestep::Measured => measured,
+        Timestep::Fixed(delta) => delta,
+    };
     if x == 0 {
         loop {
-            body(0.0, &mut pc);
+            body(simulated(0.0), &mut pc);
         }
     } else {
         loop {
@@ -151,7 +179,7 @@ pub fn loop_with_report<'a, F: FnMut(f64, &mut PerformaceCounters)>(mut body: F,
             while start.to(time::PreciseTime::now()) < time::Duration::seconds(x as i64) {
                 let start_t = time::precise_time_s();
 
-                body(delta, &mut pc);
+                body(simulated(delta), &mut pc);
 
                 let end_t = time::precise_time_s();
                 delta = end_t - start_t;
@@ -185,7 +213,7 @@ struct AxisVert {
 }
 
 pub struct Axis {
-    axis_buffer: glium::vertex::VertexBufferAny,
+    geometry: Handle<Geometry>,
     axis_program: glium::Program,
 }
 
@@ -196,33 +224,36 @@ impl Axis {
     pub fn new(ctx: &Context) -> Axis {
 
         // plot axis lines
-        let axis_buffer = glium::VertexBuffer::new(ctx.display(),
-                                                   &[AxisVert {
-                                                         position: (0.0, 0.0, 0.0),
-                                                         color: (1.0, 0.0, 0.0),
-                                                     },
-                                                     AxisVert {
-                                                         position: (100.0, 0.0, 0.0),
-                                                         color: (1.0, 0.0, 0.0),
-                                                     },
-                                                     AxisVert {
-                                                         position: (0.0, 0.0, 0.0),
-                                                         color: (0.0, 1.0, 0.0),
-                                                     },
-                                                     AxisVert {
-                                                         position: (0.0, 100.0, 0.0),
-                                                         color: (0.0, 1.0, 0.0),
-                                                     },
-                                                     AxisVert {
-                                                         position: (0.0, 0.0, 0.0),
-                                                         color: (0.0, 0.0, 1.0),
-                                                     },
-                                                     AxisVert {
-                                                         position: (0.0, 0.0, 100.0),
-                                                         color: (0.0, 0.0, 1.0),
-                                                     }])
-            .unwrap()
-            .into();
+        let vertices = [AxisVert {
+                            position: (0.0, 0.0, 0.0),
+                            color: (1.0, 0.0, 0.0),
+                        },
+                        AxisVert {
+                            position: (100.0, 0.0, 0.0),
+                            color: (1.0, 0.0, 0.0),
+                        },
+                        AxisVert {
+                            position: (0.0, 0.0, 0.0),
+                            color: (0.0, 1.0, 0.0),
+                        },
+                        AxisVert {
+                            position: (0.0, 100.0, 0.0),
+                            color: (0.0, 1.0, 0.0),
+                        },
+                        AxisVert {
+                            position: (0.0, 0.0, 0.0),
+                            color: (0.0, 0.0, 1.0),
+                        },
+                        AxisVert {
+                            position: (0.0, 0.0, 100.0),
+                            color: (0.0, 0.0, 1.0),
+                        }];
+        let geometry = {
+            let mut manager = ctx.geometry_mut();
+            let name = manager.unique_name("axis");
+            manager.create_geom_from_data(ctx, &name, &vertices, PrimitiveType::LinesList)
+                .unwrap()
+        };
 
         let axis_program = glium::Program::from_source(ctx.display(),
                                                        // vertex shader
@@ -254,22 +285,17 @@ impl Axis {
 
         Axis {
             axis_program: axis_program,
-            axis_buffer: axis_buffer,
+            geometry: geometry,
         }
     } // new
-}
-
-use renderer::context::{DrawItem, Program};
 
-impl DrawItem for Axis {
-    fn get_vertices(&self) -> &glium::vertex::VertexBufferAny {
-        &self.axis_buffer
-    }
-    fn get_primitive(&self) -> glium::index::PrimitiveType {
-        glium::index::PrimitiveType::LinesList
+    pub fn get_geometry(&self) -> Handle<Geometry> {
+        self.geometry
     }
 }
 
+use renderer::context::Program;
+
 impl Program for Axis {
     fn get_program(&self) -> &glium::Program {
         &self.axis_program
@@ -277,4 +303,7 @@ impl Program for Axis {
     fn with_tess(&self) -> bool {
         self.axis_program.has_tessellation_shaders()
     }
+    fn get_source(&self) -> &str {
+        "utils::Axis"
+    }
 }
@@ -0,0 +1,584 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use cgmath::{InnerSpace, Vector3};
+use image::{self, Pixel};
+
+use renderer::index_vertex_list;
+use world::model::{Mesh, ModelVertex};
+
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+//    Terrain export,
+//    a region of the height map as a closed mesh, to print it or to look at
+//    it somewhere else. two shapes:
+//    - surface: the height every step pixels, and always the last row and
+//      column, so the edge is there even if the step does not divide the side.
+//    - blocks: a column per step cell, as the geometry shader draws the quarfs.
+//    the sides go down to a floor under the lowest terrain, and the bottom
+//    closes the volume. coordinates start at the corner of the region.
+//    written as indexed obj, binary ply or binary stl.
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+/// below the lowest terrain, no column ends up flat
+const FLOOR: f32 = -1.0;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Format {
+    Obj,
+    Ply,
+    Stl,
+}
+
+impl Format {
+    /// by the extension
+    pub fn from_path(path: &Path) -> Option<Format> {
+        let extension = path.extension().map(|e| e.to_string_lossy().to_lowercase());
+        match extension.as_ref().map(|e| e.as_str()) {
+            Some("obj") => Some(Format::Obj),
+            Some("ply") => Some(Format::Ply),
+            Some("stl") => Some(Format::Stl),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ExportError {
+    Io(io::Error),
+    UnknownFormat(String),
+}
+
+impl From<io::Error> for ExportError {
+    fn from(err: io::Error) -> ExportError {
+        ExportError::Io(err)
+    }
+}
+
+/// in pixels of the height map
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Region {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Region {
+    pub fn new(x: u32, y: u32, width: u32, height: u32) -> Region {
+        Region {
+            x: x,
+            y: y,
+            width: width,
+            height: height,
+        }
+    }
+
+    pub fn whole(map: &image::RgbImage) -> Region {
+        let (width, height) = map.dimensions();
+        Region::new(0, 0, width, height)
+    }
+
+    /// the part inside the map
+    fn clamp(&self, map: &image::RgbImage) -> Region {
+        let (width, height) = map.dimensions();
+        let x = self.x.min(width);
+        let y = self.y.min(height);
+        Region::new(x, y, self.width.min(width - x), self.height.min(height - y))
+    }
+}
+
+/// as lib/terrain_height.glsl
+pub fn terrain_height(map: &image::RgbImage, x: u32, y: u32) -> f32 {
+    let red = map.get_pixel(x, y).channels()[0];
+    (red as f32 / 255.0 * 256.0).trunc()
+}
+
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+/// every step from the start, and the last one
+fn samples(start: u32, length: u32, step: u32) -> Vec<u32> {
+    let end = start + length - 1;
+    let mut samples: Vec<u32> = (0..)
+        .map(|k| start + k * step)
+        .take_while(|&s| s < end)
+        .collect();
+    samples.push(end);
+    samples
+}
+
+/// the first pixel of each cell of step pixels
+fn cells(start: u32, length: u32, step: u32) -> Vec<u32> {
+    (0..).map(|k| start + k * step).take_while(|&c| c < start + length).collect()
+}
+
+fn point(x: f32, y: f32, z: f32) -> Vector3<f32> {
+    Vector3::new(x, y, z)
+}
+
+struct Builder {
+    vertices: Vec<ModelVertex>,
+    indices: Vec<u32>,
+}
+
+impl Builder {
+    fn new() -> Builder {
+        Builder {
+            vertices: Vec::new(),
+            indices: Vec::new(),
+        }
+    }
+
+    fn vertex(&mut self, p: Vector3<f32>, n: Vector3<f32>) -> u32 {
+        self.vertices.push(ModelVertex {
+            position: (p.x, p.y, p.z),
+            normal: (n.x, n.y, n.z),
+            tex_coord: (0.0, 0.0),
+        });
+        self.vertices.len() as u32 - 1
+    }
+
+    /// flat, counter clockwise seen from outside
+    fn triangle(&mut self,
+                a: Vector3<f32>,
+                b: Vector3<f32>,
+                c: Vector3<f32>,
+                outward: Vector3<f32>) {
+        let (b, c) = if (b - a).cross(c - a).dot(outward) < 0.0 {
+            (c, b)
+        } else {
+            (b, c)
+        };
+        for &p in &[a, b, c] {
+            let i = self.vertex(p, outward);
+            self.indices.push(i);
+        }
+    }
+
+    /// the corners in order around the quad
+    fn quad(&mut self, corners: [Vector3<f32>; 4], outward: Vector3<f32>) {
+        self.triangle(corners[0], corners[1], corners[2], outward);
+        self.triangle(corners[0], corners[2], corners[3], outward);
+    }
+
+    /// a vertical side between two columns of points, bottom to top.
+    /// the points of both columns are kept, so the sides of the walls around
+    /// meet at the same vertices
+    fn wall(&mut self, left: &[Vector3<f32>], right: &[Vector3<f32>], outward: Vector3<f32>) {
+        let (mut i, mut j) = (0, 0);
+        while i + 1 < left.len() || j + 1 < right.len() {
+            if j + 1 == right.len() || (i + 1 < left.len() && left[i + 1].y <= right[j + 1].y) {
+                self.triangle(left[i], right[j], left[i + 1], outward);
+                i += 1;
+            } else {
+                self.triangle(left[i], right[j], right[j + 1], outward);
+                j += 1;
+            }
+        }
+    }
+
+    /// the vertices with the same position and normal are merged
+    fn mesh(self, name: &str) -> Mesh {
+        let flat: Vec<ModelVertex> =
+            self.indices.iter().map(|&i| self.vertices[i as usize]).collect();
+        let (vertices, indices) = index_vertex_list(&flat);
+        Mesh {
+            material: name.to_string(),
+            vertices: vertices,
+            indices: indices,
+        }
+    }
+}
+
+/// the height field, with smooth normals on top
+pub fn surface(map: &image::RgbImage, region: Region, step: u32) -> Mesh {
+    let r = region.clamp(map);
+    let mut builder = Builder::new();
+    if r.width < 2 || r.height < 2 {
+        return builder.mesh("terrain");
+    }
+    let step = step.max(1);
+    let xs = samples(r.x, r.width, step);
+    let zs = samples(r.y, r.height, step);
+    let (nx, nz) = (xs.len(), zs.len());
+
+    let top: Vec<Vector3<f32>> = xs.iter()
+        .flat_map(|&x| {
+            zs.iter().map(move |&z| {
+                point((x - r.x) as f32, terrain_height(map, x, z), (z - r.y) as f32)
+            })
+        })
+        .collect();
+    let at = |i: usize, j: usize| i * nz + j;
+
+    // ~~~~~~~~~ top, normals averaged over the triangles around ~~~~~~~~~
+
+    let mut triangles = Vec::new();
+    for i in 0..nx - 1 {
+        for j in 0..nz - 1 {
+            triangles.push([at(i, j), at(i, j + 1), at(i + 1, j + 1)]);
+            triangles.push([at(i, j), at(i + 1, j + 1), at(i + 1, j)]);
+        }
+    }
+    let mut normals = vec![point(0.0, 0.0, 0.0); top.len()];
+    for t in &triangles {
+        let n = (top[t[1]] - top[t[0]]).cross(top[t[2]] - top[t[0]]);
+        for &v in t {
+            normals[v] = normals[v] + n;
+        }
+    }
+    let first = builder.vertices.len() as u32;
+    for (p, n) in top.iter().zip(normals.iter()) {
+        builder.vertex(*p, n.normalize());
+    }
+    for t in &triangles {
+        builder.indices.extend(t.iter().map(|&v| first + v as u32));
+    }
+
+    // ~~~~~~~~~ sides down to the floor, and the bottom ~~~~~~~~~
+
+    let mut edges = Vec::new();
+    for i in 0..nx - 1 {
+        edges.push((at(i, 0), at(i + 1, 0), point(0.0, 0.0, -1.0)));
+        edges.push((at(i, nz - 1), at(i + 1, nz - 1), point(0.0, 0.0, 1.0)));
+    }
+    for j in 0..nz - 1 {
+        edges.push((at(0, j), at(0, j + 1), point(-1.0, 0.0, 0.0)));
+        edges.push((at(nx - 1, j), at(nx - 1, j + 1), point(1.0, 0.0, 0.0)));
+    }
+    let floor = |p: Vector3<f32>| point(p.x, FLOOR, p.z);
+    let center = point(top[at(nx - 1, 0)].x / 2.0, FLOOR, top[at(0, nz - 1)].z / 2.0);
+    for &(a, b, outward) in &edges {
+        let (a, b) = (top[a], top[b]);
+        builder.quad([a, b, floor(b), floor(a)], outward);
+        builder.triangle(center, floor(a), floor(b), point(0.0, -1.0, 0.0));
+    }
+
+    builder.mesh("terrain")
+}
+
+/// a column per cell of step pixels, the height of its first pixel
+pub fn blocks(map: &image::RgbImage, region: Region, step: u32) -> Mesh {
+    let r = region.clamp(map);
+    let mut builder = Builder::new();
+    if r.width == 0 || r.height == 0 {
+        return builder.mesh("blocks");
+    }
+    let step = step.max(1);
+
+    // the cells, and their sides. the last cell may be narrower
+    let cells_x = cells(r.x, r.width, step);
+    let cells_z = cells(r.y, r.height, step);
+    let side = |cells: &[u32], start: u32, end: u32| -> Vec<f32> {
+        cells.iter().map(|&c| (c - start) as f32).chain(Some((end - start) as f32)).collect()
+    };
+    let bx = side(&cells_x, r.x, r.x + r.width);
+    let bz = side(&cells_z, r.y, r.y + r.height);
+    let (nx, nz) = (cells_x.len() as isize, cells_z.len() as isize);
+
+    let heights: Vec<f32> = cells_x.iter()
+        .flat_map(|&x| cells_z.iter().map(move |&z| terrain_height(map, x, z)))
+        .collect();
+    // out of the region, the floor
+    let h = |i: isize, j: isize| -> f32 {
+        if i < 0 || j < 0 || i >= nx || j >= nz {
+            FLOOR
+        } else {
+            heights[(i * nz + j) as usize]
+        }
+    };
+
+    // the points of a corner from lo to hi, at every height of the cells around
+    let column = |ci: isize, cj: isize, lo: f32, hi: f32| -> Vec<Vector3<f32>> {
+        let mut levels = vec![lo, hi];
+        for &(di, dj) in &[(-1, -1), (0, -1), (-1, 0), (0, 0)] {
+            let level = h(ci + di, cj + dj);
+            if level > lo && level < hi {
+                levels.push(level);
+            }
+        }
+        levels.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        levels.dedup();
+        levels.iter().map(|&y| point(bx[ci as usize], y, bz[cj as usize])).collect()
+    };
+
+    let up = point(0.0, 1.0, 0.0);
+    let down = point(0.0, -1.0, 0.0);
+    for i in 0..nx {
+        for j in 0..nz {
+            let (x0, x1) = (bx[i as usize], bx[i as usize + 1]);
+            let (z0, z1) = (bz[j as usize], bz[j as usize + 1]);
+            let top = h(i, j);
+            builder.quad([point(x0, top, z0),
+                          point(x0, top, z1),
+                          point(x1, top, z1),
+                          point(x1, top, z0)],
+                         up);
+            builder.quad([point(x0, FLOOR, z0),
+                          point(x0, FLOOR, z1),
+                          point(x1, FLOOR, z1),
+                          point(x1, FLOOR, z0)],
+                         down);
+
+            // the higher column of the two has the wall
+            let sides = [((1, 0), (i + 1, j), (i + 1, j + 1), point(1.0, 0.0, 0.0)),
+                         ((-1, 0), (i, j), (i, j + 1), point(-1.0, 0.0, 0.0)),
+                         ((0, 1), (i, j + 1), (i + 1, j + 1), point(0.0, 0.0, 1.0)),
+                         ((0, -1), (i, j), (i + 1, j), point(0.0, 0.0, -1.0))];
+            for &((di, dj), a, b, outward) in &sides {
+                let lo = h(i + di, j + dj);
+                if lo < top {
+                    let left = column(a.0, a.1, lo, top);
+                    let right = column(b.0, b.1, lo, top);
+                    builder.wall(&left, &right, outward);
+                }
+            }
+        }
+    }
+
+    builder.mesh("blocks")
+}
+
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+type Key = (u32, u32, u32);
+
+fn key(v: (f32, f32, f32)) -> Key {
+    (v.0.to_bits(), v.1.to_bits(), v.2.to_bits())
+}
+
+/// the different values, and the position of each one in them
+fn dedupe<I>(values: I) -> (Vec<(f32, f32, f32)>, Vec<usize>)
+    where I: Iterator<Item = (f32, f32, f32)>
+{
+    let mut seen: HashMap<Key, usize> = HashMap::new();
+    let mut unique = Vec::new();
+    let mut positions = Vec::new();
+    for v in values {
+        let i = *seen.entry(key(v)).or_insert_with(|| {
+            unique.push(v);
+            unique.len() - 1
+        });
+        positions.push(i);
+    }
+    (unique, positions)
+}
+
+/// positions and normals are indexed apart, the vertices split by the
+/// normals still share the position
+pub fn write_obj<W: Write>(mesh: &Mesh, out: &mut W) -> io::Result<()> {
+    let (positions, position_of) = dedupe(mesh.vertices.iter().map(|v| v.position));
+    let (normals, normal_of) = dedupe(mesh.vertices.iter().map(|v| v.normal));
+
+    writeln!(out, "# rquarfs {}", mesh.material)?;
+    for p in &positions {
+        writeln!(out, "v {} {} {}", p.0, p.1, p.2)?;
+    }
+    for n in &normals {
+        writeln!(out, "vn {} {} {}", n.0, n.1, n.2)?;
+    }
+    for t in mesh.indices.chunks(3) {
+        let corners: Vec<String> = t.iter()
+            .map(|&i| format!("{}//{}", position_of[i as usize] + 1, normal_of[i as usize] + 1))
+            .collect();
+        writeln!(out, "f {}", corners.join(" "))?;
+    }
+    Ok(())
+}
+
+fn write_u32<W: Write>(out: &mut W, value: u32) -> io::Result<()> {
+    out.write_all(&[value as u8, (value >> 8) as u8, (value >> 16) as u8, (value >> 24) as u8])
+}
+
+fn write_vec<W: Write>(out: &mut W, v: (f32, f32, f32)) -> io::Result<()> {
+    write_u32(out, v.0.to_bits())?;
+    write_u32(out, v.1.to_bits())?;
+    write_u32(out, v.2.to_bits())
+}
+
+/// little endian, a vertex per position and normal
+pub fn write_ply<W: Write>(mesh: &Mesh, out: &mut W) -> io::Result<()> {
+    write!(out,
+           "ply\nformat binary_little_endian 1.0\ncomment rquarfs {}\n\
+            element vertex {}\n\
+            property float x\nproperty float y\nproperty float z\n\
+            property float nx\nproperty float ny\nproperty float nz\n\
+            element face {}\nproperty list uchar uint vertex_indices\nend_header\n",
+           mesh.material,
+           mesh.vertices.len(),
+           mesh.indices.len() / 3)?;
+    for v in &mesh.vertices {
+        write_vec(out, v.position)?;
+        write_vec(out, v.normal)?;
+    }
+    for t in mesh.indices.chunks(3) {
+        out.write_all(&[3])?;
+        for &i in t {
+            write_u32(out, i)?;
+        }
+    }
+    Ok(())
+}
+
+/// binary, stl has no indices. the normal of each facet comes from its corners
+pub fn write_stl<W: Write>(mesh: &Mesh, out: &mut W) -> io::Result<()> {
+    // must not start with "solid", that is the text format
+    let mut header = [b' '; 80];
+    let title = format!("rquarfs {}", mesh.material);
+    for (h, &c) in header.iter_mut().zip(title.as_bytes()) {
+        *h = c;
+    }
+    out.write_all(&header)?;
+    write_u32(out, (mesh.indices.len() / 3) as u32)?;
+
+    for t in mesh.indices.chunks(3) {
+        let p: Vec<Vector3<f32>> = t.iter()
+            .map(|&i| {
+                let p = mesh.vertices[i as usize].position;
+                point(p.0, p.1, p.2)
+            })
+            .collect();
+        let n = (p[1] - p[0]).cross(p[2] - p[0]);
+        let n = if n.magnitude2() > 0.0 { n.normalize() } else { n };
+        write_vec(out, (n.x, n.y, n.z))?;
+        for c in &p {
+            write_vec(out, (c.x, c.y, c.z))?;
+        }
+        // attribute byte count
+        out.write_all(&[0, 0])?;
+    }
+    Ok(())
+}
+
+/// the format from the extension of the file
+pub fn save(mesh: &Mesh, path: &Path) -> Result<(), ExportError> {
+    let format = Format::from_path(path)
+        .ok_or_else(|| ExportError::UnknownFormat(format!("{}", path.display())))?;
+    if let Some(dir) = path.parent() {
+        let _ = fs::create_dir_all(dir);
+    }
+    let mut out = BufWriter::new(File::create(path)?);
+    match format {
+        Format::Obj => write_obj(mesh, &mut out)?,
+        Format::Ply => write_ply(mesh, &mut out)?,
+        Format::Stl => write_stl(mesh, &mut out)?,
+    }
+    out.flush()?;
+    Ok(())
+}
+
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+//  Tests
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::collections::HashMap;
+
+    /// heights 0, 8, 16.. in a few steps, not a flat map
+    fn map(width: u32, height: u32) -> image::RgbImage {
+        image::RgbImage::from_fn(width, height, |x, y| {
+            let red = ((x * 7 + y * 13) % 5) as u8 * 8;
+            image::Rgb([red, 0, 0])
+        })
+    }
+
+    /// every edge is used as many times in each direction, and the volume is not
+    /// inside out
+    fn check_closed(mesh: &Mesh) {
+        assert!(!mesh.indices.is_empty());
+        let position = |i: u32| key(mesh.vertices[i as usize].position);
+        let mut edges: HashMap<(Key, Key), u32> = HashMap::new();
+        let mut volume = 0.0;
+        for t in mesh.indices.chunks(3) {
+            for &(a, b) in &[(t[0], t[1]), (t[1], t[2]), (t[2], t[0])] {
+                *edges.entry((position(a), position(b))).or_insert(0) += 1;
+            }
+            let p: Vec<Vector3<f32>> = t.iter()
+                .map(|&i| {
+                    let p = mesh.vertices[i as usize].position;
+                    point(p.0, p.1, p.2)
+                })
+                .collect();
+            volume += p[0].dot(p[1].cross(p[2])) / 6.0;
+        }
+        // columns touching at a corner share the vertical edge four times
+        for (&(a, b), &count) in &edges {
+            assert_eq!(edges.get(&(b, a)), Some(&count), "open edge");
+        }
+        assert!(volume > 0.0, "inside out");
+    }
+
+    #[test]
+    fn edge_samples() {
+        assert_eq!(samples(0, 10, 4), vec![0, 4, 8, 9]);
+        assert_eq!(samples(0, 9, 4), vec![0, 4, 8]);
+        assert_eq!(samples(3, 2, 5), vec![3, 4]);
+    }
+
+    #[test]
+    fn surface_closed() {
+        let map = map(11, 7);
+        // the step does not divide the sides
+        let mesh = surface(&map, Region::new(1, 1, 10, 6), 3);
+        check_closed(&mesh);
+        // the last column and row are there
+        let max_x = mesh.vertices.iter().map(|v| v.position.0).fold(0.0, f32::max);
+        let max_z = mesh.vertices.iter().map(|v| v.position.2).fold(0.0, f32::max);
+        assert_eq!((max_x, max_z), (9.0, 5.0));
+        let corner = (9.0, terrain_height(&map, 10, 6), 5.0);
+        assert!(mesh.vertices.iter().any(|v| v.position == corner));
+
+        // out of the map, clamped
+        check_closed(&surface(&map, Region::new(5, 2, 100, 100), 1));
+        assert!(surface(&map, Region::new(10, 0, 5, 5), 1).indices.is_empty());
+    }
+
+    #[test]
+    fn blocks_closed() {
+        let map = map(9, 6);
+        check_closed(&blocks(&map, Region::whole(&map), 1));
+        check_closed(&blocks(&map, Region::whole(&map), 2));
+        let mesh = blocks(&map, Region::new(2, 1, 5, 5), 4);
+        check_closed(&mesh);
+        // a cell of 4, and the narrow one of 1
+        let max_x = mesh.vertices.iter().map(|v| v.position.0).fold(0.0, f32::max);
+        assert_eq!(max_x, 5.0);
+        assert!(mesh.vertices.iter().all(|v| {
+            let n = v.normal;
+            n.0.abs() + n.1.abs() + n.2.abs() == 1.0
+        }));
+    }
+
+    #[test]
+    fn formats() {
+        let map = map(4, 4);
+        let mesh = blocks(&map, Region::whole(&map), 2);
+        let triangles = mesh.indices.len() / 3;
+
+        let mut obj = Vec::new();
+        write_obj(&mesh, &mut obj).unwrap();
+        let obj = String::from_utf8(obj).unwrap();
+        let count = |prefix: &str| obj.lines().filter(|l| l.starts_with(prefix)).count();
+        assert_eq!(count("f "), triangles);
+        assert_eq!(count("vn "), 6);
+        assert!(count("v ") < mesh.vertices.len());
+
+        let mut ply = Vec::new();
+        write_ply(&mesh, &mut ply).unwrap();
+        let end = b"end_header\n";
+        let header = ply.windows(end.len()).position(|w| w == end).unwrap() + end.len();
+        assert_eq!(ply.len(), header + mesh.vertices.len() * 24 + triangles * 13);
+
+        let mut stl = Vec::new();
+        write_stl(&mesh, &mut stl).unwrap();
+        assert_eq!(stl.len(), 84 + triangles * 50);
+        assert!(!stl.starts_with(b"solid"));
+
+        assert_eq!(Format::from_path(Path::new("tile.STL")), Some(Format::Stl));
+        assert_eq!(Format::from_path(Path::new("tile.png")), None);
+    }
+}
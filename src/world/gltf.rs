@@ -0,0 +1,993 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use base64;
+use cgmath::{InnerSpace, Matrix4, Quaternion, SquareMatrix, Vector3};
+use glium::index::PrimitiveType;
+use image;
+use serde_json::{self, Value};
+
+use renderer::context::{Context, ManagerError};
+use renderer::handle::{Geometry, Handle, Texture};
+use renderer::index_vertex_list;
+use world::model::ModelVertex;
+
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+//    glTF 2.0 scenes,
+//    .gltf (json with its buffers in files or data uris) and .glb (json
+//    and buffer in one binary file).
+//    primitives go to the geometry manager, images to the texture manager.
+//    the node tree is flattened: each node with a mesh is an instance with
+//    its model matrix, and the matrices of a mesh are a per instance
+//    geometry, so all the copies of a mesh are one instanced draw.
+//    not supported: skins, morph targets, animations, cameras, sparse
+//    accessors and required extensions.
+//    scenes are user files, they are read from disk and never from the
+//    shipped assets.
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+#[derive(Debug)]
+pub enum GltfError {
+    Io(io::Error),
+    Json(String),
+    /// the document or its buffers do not follow the spec
+    Invalid(String),
+    Unsupported(String),
+    Image(String),
+    Manager(ManagerError),
+}
+
+impl From<io::Error> for GltfError {
+    fn from(err: io::Error) -> GltfError {
+        GltfError::Io(err)
+    }
+}
+
+impl From<ManagerError> for GltfError {
+    fn from(err: ManagerError) -> GltfError {
+        GltfError::Manager(err)
+    }
+}
+
+fn invalid(message: String) -> GltfError {
+    GltfError::Invalid(message)
+}
+
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum AlphaMode {
+    Opaque,
+    /// fragments below the cutoff are discarded
+    Mask(f32),
+    Blend,
+}
+
+/// metallic roughness parameters. textures are indices in Scene::texture
+#[allow(dead_code)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct PbrMaterial {
+    pub name: String,
+    pub base_color: [f32; 4],
+    pub base_color_texture: Option<usize>,
+    pub metallic: f32,
+    pub roughness: f32,
+    /// roughness in green, metallic in blue
+    pub metallic_roughness_texture: Option<usize>,
+    pub normal_texture: Option<usize>,
+    pub occlusion_texture: Option<usize>,
+    pub emissive: [f32; 3],
+    pub emissive_texture: Option<usize>,
+    pub alpha_mode: AlphaMode,
+    pub double_sided: bool,
+}
+
+impl PbrMaterial {
+    /// the defaults of the spec, also for primitives without material
+    pub fn new(name: &str) -> PbrMaterial {
+        PbrMaterial {
+            name: name.to_string(),
+            base_color: [1.0, 1.0, 1.0, 1.0],
+            base_color_texture: None,
+            metallic: 1.0,
+            roughness: 1.0,
+            metallic_roughness_texture: None,
+            normal_texture: None,
+            occlusion_texture: None,
+            emissive: [0.0, 0.0, 0.0],
+            emissive_texture: None,
+            alpha_mode: AlphaMode::Opaque,
+            double_sided: false,
+        }
+    }
+}
+
+/// a node with a mesh
+#[allow(dead_code)]
+#[derive(Clone, Debug)]
+pub struct Instance {
+    pub name: String,
+    pub mesh: usize,
+    /// from the scene root, parents first
+    pub matrix: Matrix4<f32>,
+}
+
+/// the per instance attribute of the instanced draws
+#[derive(Copy, Clone)]
+pub struct InstanceData {
+    pub instance_model: [[f32; 4]; 4],
+}
+implement_vertex!(InstanceData, instance_model);
+
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+//    reading the document, nothing here touches the context
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+/// the reads the document needs on top of serde_json. missing keys index to
+/// null, so optional fields read as a chain of them
+trait Field {
+    /// only integers, not negative
+    fn as_usize(&self) -> Option<usize>;
+    fn as_f32(&self) -> Option<f32>;
+    /// an empty list for anything else, missing arrays are usually empty ones
+    fn members(&self) -> &[Value];
+    /// the numbers of an array of numbers
+    fn as_floats(&self) -> Option<Vec<f32>>;
+}
+
+impl Field for Value {
+    fn as_usize(&self) -> Option<usize> {
+        self.as_u64().map(|n| n as usize)
+    }
+
+    fn as_f32(&self) -> Option<f32> {
+        self.as_f64().map(|n| n as f32)
+    }
+
+    fn members(&self) -> &[Value] {
+        self.as_array().map(|items| &items[..]).unwrap_or(&[])
+    }
+
+    fn as_floats(&self) -> Option<Vec<f32>> {
+        self.as_array().and_then(|items| items.iter().map(|i| i.as_f32()).collect())
+    }
+}
+
+struct PrimitiveData {
+    vertices: Vec<ModelVertex>,
+    indices: Option<Vec<u32>>,
+    mode: PrimitiveType,
+    material: Option<usize>,
+}
+
+struct MeshData {
+    name: String,
+    primitives: Vec<PrimitiveData>,
+}
+
+struct SceneData {
+    meshes: Vec<MeshData>,
+    materials: Vec<PbrMaterial>,
+    /// the image of each texture
+    textures: Vec<Option<usize>>,
+    instances: Vec<Instance>,
+}
+
+fn le_u16(bytes: &[u8]) -> u16 {
+    bytes[0] as u16 | (bytes[1] as u16) << 8
+}
+
+fn le_u32(bytes: &[u8]) -> u32 {
+    bytes[0] as u32 | (bytes[1] as u32) << 8 | (bytes[2] as u32) << 16 | (bytes[3] as u32) << 24
+}
+
+/// "glTF"
+const GLB_MAGIC: u32 = 0x4654_6c67;
+const CHUNK_JSON: u32 = 0x4e4f_534a;
+const CHUNK_BIN: u32 = 0x004e_4942;
+
+/// the json chunk, and the binary one if there is
+fn parse_glb(data: &[u8]) -> Result<(String, Option<Vec<u8>>), GltfError> {
+    if data.len() < 12 || le_u32(&data[0..4]) != GLB_MAGIC {
+        return Err(invalid("not a glb file".to_string()));
+    }
+    let version = le_u32(&data[4..8]);
+    if version != 2 {
+        return Err(GltfError::Unsupported(format!("glb version {}", version)));
+    }
+    let length = (le_u32(&data[8..12]) as usize).min(data.len());
+
+    let mut json = None;
+    let mut bin = None;
+    let mut at = 12;
+    while at + 8 <= length {
+        let size = le_u32(&data[at..at + 4]) as usize;
+        let kind = le_u32(&data[at + 4..at + 8]);
+        let start = at + 8;
+        let end = start + size;
+        if end > length {
+            return Err(invalid("glb chunk out of the file".to_string()));
+        }
+        match kind {
+            CHUNK_JSON => {
+                json = Some(String::from_utf8(data[start..end].to_vec())
+                    .map_err(|_| invalid("glb json is not utf8".to_string()))?)
+            }
+            CHUNK_BIN if bin.is_none() => bin = Some(data[start..end].to_vec()),
+            // extensions may add chunks
+            _ => {}
+        }
+        at = end;
+    }
+    match json {
+        Some(json) => Ok((json, bin)),
+        None => Err(invalid("glb without json chunk".to_string())),
+    }
+}
+
+/// data uris, or files next to the document
+fn read_uri(dir: &Path, uri: &str) -> Result<Vec<u8>, GltfError> {
+    if uri.starts_with("data:") {
+        match uri.find(";base64,") {
+            Some(at) => {
+                base64::decode(&uri[at + 8..])
+                    .map_err(|err| invalid(format!("data uri: {}", err)))
+            }
+            None => Err(GltfError::Unsupported("data uri not in base64".to_string())),
+        }
+    } else {
+        Ok(fs::read(dir.join(uri))?)
+    }
+}
+
+/// the first buffer of a glb has no uri, it is the binary chunk
+fn load_buffers(doc: &Value, bin: Option<Vec<u8>>, dir: &Path) -> Result<Vec<Vec<u8>>, GltfError> {
+    let mut bin = bin;
+    let mut buffers = Vec::new();
+    for (i, buffer) in doc["buffers"].members().iter().enumerate() {
+        let data = match buffer["uri"].as_str() {
+            Some(uri) => read_uri(dir, uri)?,
+            None if i == 0 && bin.is_some() => bin.take().unwrap(),
+            None => return Err(invalid(format!("buffer {} without data", i))),
+        };
+        if data.len() < buffer["byteLength"].as_usize().unwrap_or(0) {
+            return Err(invalid(format!("buffer {} is short", i)));
+        }
+        buffers.push(data);
+    }
+    Ok(buffers)
+}
+
+/// the bytes of a buffer view
+fn view_bytes<'a>(doc: &Value, buffers: &'a [Vec<u8>], view: usize) -> Result<&'a [u8], GltfError> {
+    let view_json = &doc["bufferViews"][view];
+    let buffer = view_json["buffer"]
+        .as_usize()
+        .and_then(|b| buffers.get(b))
+        .ok_or_else(|| invalid(format!("buffer view {} without buffer", view)))?;
+    let start = view_json["byteOffset"].as_usize().unwrap_or(0);
+    let end = start.checked_add(view_json["byteLength"].as_usize().unwrap_or(0));
+    match end {
+        Some(end) if end <= buffer.len() => Ok(&buffer[start..end]),
+        _ => Err(invalid(format!("buffer view {} out of its buffer", view))),
+    }
+}
+
+fn component(bytes: &[u8], kind: usize, normalized: bool) -> f64 {
+    match (kind, normalized) {
+        (5120, false) => bytes[0] as i8 as f64,
+        (5120, true) => (bytes[0] as i8 as f64 / 127.0).max(-1.0),
+        (5121, false) => bytes[0] as f64,
+        (5121, true) => bytes[0] as f64 / 255.0,
+        (5122, false) => le_u16(bytes) as i16 as f64,
+        (5122, true) => (le_u16(bytes) as i16 as f64 / 32767.0).max(-1.0),
+        (5123, false) => le_u16(bytes) as f64,
+        (5123, true) => le_u16(bytes) as f64 / 65535.0,
+        (5125, _) => le_u32(bytes) as f64,
+        _ => f32::from_bits(le_u32(bytes)) as f64,
+    }
+}
+
+/// values an accessor without buffer view may have, they are zeros
+const MAX_ZEROS: usize = 1 << 24;
+
+/// components per element and the values, normalized integers already as floats
+fn accessor(doc: &Value,
+            buffers: &[Vec<u8>],
+            index: usize)
+            -> Result<(usize, Vec<f64>), GltfError> {
+    let acc = &doc["accessors"][index];
+    if acc.is_null() {
+        return Err(invalid(format!("no accessor {}", index)));
+    }
+    if !acc["sparse"].is_null() {
+        return Err(GltfError::Unsupported("sparse accessors".to_string()));
+    }
+    let components = match acc["type"].as_str() {
+        Some("SCALAR") => 1,
+        Some("VEC2") => 2,
+        Some("VEC3") => 3,
+        Some("VEC4") | Some("MAT2") => 4,
+        Some("MAT3") => 9,
+        Some("MAT4") => 16,
+        other => return Err(invalid(format!("accessor {} of type {:?}", index, other))),
+    };
+    let kind = acc["componentType"].as_usize().unwrap_or(0);
+    let size = match kind {
+        5120 | 5121 => 1,
+        5122 | 5123 => 2,
+        5125 | 5126 => 4,
+        _ => return Err(invalid(format!("accessor {} of component type {}", index, kind))),
+    };
+    let count = acc["count"]
+        .as_usize()
+        .ok_or_else(|| invalid(format!("accessor {} without count", index)))?;
+    let normalized = acc["normalized"].as_bool().unwrap_or(false);
+    let len = match count.checked_mul(components) {
+        Some(len) => len,
+        None => return Err(invalid(format!("accessor {} is too large", index))),
+    };
+
+    let view = match acc["bufferView"].as_usize() {
+        Some(view) => view,
+        // no data, all zeros. only the count tells the size, so it gets a limit
+        None if len <= MAX_ZEROS => return Ok((components, vec![0.0; len])),
+        None => return Err(invalid(format!("accessor {} is too large", index))),
+    };
+    let bytes = view_bytes(doc, buffers, view)?;
+    let start = acc["byteOffset"].as_usize().unwrap_or(0);
+    let element = size * components;
+    let stride = doc["bufferViews"][view]["byteStride"].as_usize().unwrap_or(element);
+    if stride < element {
+        return Err(invalid(format!("accessor {} elements overlap", index)));
+    }
+    let end = stride.checked_mul(count.saturating_sub(1))
+        .and_then(|offset| offset.checked_add(start))
+        .and_then(|offset| offset.checked_add(element));
+    if count > 0 && end.map_or(true, |end| end > bytes.len()) {
+        return Err(invalid(format!("accessor {} out of its buffer view", index)));
+    }
+
+    let mut values = Vec::with_capacity(len);
+    for e in 0..count {
+        for c in 0..components {
+            let at = start + e * stride + c * size;
+            values.push(component(&bytes[at..at + size], kind, normalized));
+        }
+    }
+    Ok((components, values))
+}
+
+/// the accessor of an attribute, with the components expected
+fn attribute(doc: &Value,
+             buffers: &[Vec<u8>],
+             attributes: &Value,
+             name: &str,
+             components: usize)
+             -> Result<Option<Vec<f64>>, GltfError> {
+    let index = match attributes[name].as_usize() {
+        Some(index) => index,
+        None => return Ok(None),
+    };
+    let (n, values) = accessor(doc, buffers, index)?;
+    if n != components {
+        return Err(invalid(format!("{} with {} components", name, n)));
+    }
+    Ok(Some(values))
+}
+
+/// without normals the faces are flat, the vertices are split per triangle
+fn flat_normals(vertices: &[ModelVertex], indices: Option<&[u32]>) -> (Vec<ModelVertex>, Vec<u32>) {
+    let order: Vec<usize> = match indices {
+        Some(indices) => indices.iter().map(|&i| i as usize).collect(),
+        None => (0..vertices.len()).collect(),
+    };
+    let point = |i: usize| {
+        let p = vertices[i].position;
+        Vector3::new(p.0, p.1, p.2)
+    };
+
+    let mut flat = Vec::with_capacity(order.len());
+    for t in order.chunks(3).filter(|t| t.len() == 3) {
+        let n = (point(t[1]) - point(t[0])).cross(point(t[2]) - point(t[0]));
+        let n = if n.magnitude2() > 0.0 { n.normalize() } else { n };
+        for &i in t {
+            let mut v = vertices[i];
+            v.normal = (n.x, n.y, n.z);
+            flat.push(v);
+        }
+    }
+    index_vertex_list(&flat)
+}
+
+fn primitive(doc: &Value, buffers: &[Vec<u8>], p: &Value) -> Result<PrimitiveData, GltfError> {
+    let attributes = &p["attributes"];
+    let positions = attribute(doc, buffers, attributes, "POSITION", 3)?
+        .ok_or_else(|| invalid("primitive without positions".to_string()))?;
+    let normals = attribute(doc, buffers, attributes, "NORMAL", 3)?;
+    let tex_coords = attribute(doc, buffers, attributes, "TEXCOORD_0", 2)?;
+
+    let count = positions.len() / 3;
+    if normals.as_ref().map(|n| n.len() != count * 3).unwrap_or(false) ||
+       tex_coords.as_ref().map(|t| t.len() != count * 2).unwrap_or(false) {
+        return Err(invalid("attributes of different lengths".to_string()));
+    }
+
+    let vertices: Vec<ModelVertex> = (0..count)
+        .map(|i| {
+            let v3 = |values: &[f64]| {
+                (values[i * 3] as f32, values[i * 3 + 1] as f32, values[i * 3 + 2] as f32)
+            };
+            ModelVertex {
+                position: v3(&positions),
+                normal: normals.as_ref().map(|n| v3(n)).unwrap_or((0.0, 0.0, 0.0)),
+                tex_coord: tex_coords.as_ref()
+                    .map(|t| (t[i * 2] as f32, t[i * 2 + 1] as f32))
+                    .unwrap_or((0.0, 0.0)),
+            }
+        })
+        .collect();
+
+    let indices = match p["indices"].as_usize() {
+        Some(index) => {
+            let (n, values) = accessor(doc, buffers, index)?;
+            if n != 1 || values.iter().any(|&i| i as usize >= count) {
+                return Err(invalid(format!("indices {} out of the vertices", index)));
+            }
+            Some(values.iter().map(|&i| i as u32).collect::<Vec<u32>>())
+        }
+        None => None,
+    };
+
+    let mode = match p["mode"].as_usize().unwrap_or(4) {
+        0 => PrimitiveType::Points,
+        1 => PrimitiveType::LinesList,
+        2 => PrimitiveType::LineLoop,
+        3 => PrimitiveType::LineStrip,
+        4 => PrimitiveType::TrianglesList,
+        5 => PrimitiveType::TriangleStrip,
+        6 => PrimitiveType::TriangleFan,
+        mode => return Err(invalid(format!("primitive mode {}", mode))),
+    };
+
+    // strips and fans keep the zero normals
+    let (vertices, indices) = if normals.is_none() && mode == PrimitiveType::TrianglesList {
+        let (vertices, indices) = flat_normals(&vertices, indices.as_ref().map(|i| &i[..]));
+        (vertices, Some(indices))
+    } else {
+        (vertices, indices)
+    };
+
+    Ok(PrimitiveData {
+        vertices: vertices,
+        indices: indices,
+        mode: mode,
+        material: p["material"].as_usize(),
+    })
+}
+
+fn factor3(value: &Value, default: [f32; 3]) -> [f32; 3] {
+    match value.as_floats() {
+        Some(ref v) if v.len() == 3 => [v[0], v[1], v[2]],
+        _ => default,
+    }
+}
+
+fn factor4(value: &Value, default: [f32; 4]) -> [f32; 4] {
+    match value.as_floats() {
+        Some(ref v) if v.len() == 4 => [v[0], v[1], v[2], v[3]],
+        _ => default,
+    }
+}
+
+fn material(m: &Value) -> PbrMaterial {
+    let pbr = &m["pbrMetallicRoughness"];
+    let texture = |info: &Value| info["index"].as_usize();
+    let mut material = PbrMaterial::new(m["name"].as_str().unwrap_or(""));
+
+    material.base_color = factor4(&pbr["baseColorFactor"], material.base_color);
+    material.base_color_texture = texture(&pbr["baseColorTexture"]);
+    material.metallic = pbr["metallicFactor"].as_f32().unwrap_or(material.metallic);
+    material.roughness = pbr["roughnessFactor"].as_f32().unwrap_or(material.roughness);
+    material.metallic_roughness_texture = texture(&pbr["metallicRoughnessTexture"]);
+    material.normal_texture = texture(&m["normalTexture"]);
+    material.occlusion_texture = texture(&m["occlusionTexture"]);
+    material.emissive = factor3(&m["emissiveFactor"], material.emissive);
+    material.emissive_texture = texture(&m["emissiveTexture"]);
+    material.alpha_mode = match m["alphaMode"].as_str() {
+        Some("MASK") => AlphaMode::Mask(m["alphaCutoff"].as_f32().unwrap_or(0.5)),
+        Some("BLEND") => AlphaMode::Blend,
+        _ => AlphaMode::Opaque,
+    };
+    material.double_sided = m["doubleSided"].as_bool().unwrap_or(false);
+    material
+}
+
+/// a matrix, or translation * rotation * scale
+fn node_matrix(node: &Value) -> Result<Matrix4<f32>, GltfError> {
+    if let Some(m) = node["matrix"].as_floats() {
+        if m.len() != 16 {
+            return Err(invalid("node matrix without 16 numbers".to_string()));
+        }
+        // column major, as cgmath
+        return Ok(Matrix4::from([[m[0], m[1], m[2], m[3]],
+                                 [m[4], m[5], m[6], m[7]],
+                                 [m[8], m[9], m[10], m[11]],
+                                 [m[12], m[13], m[14], m[15]]]));
+    }
+    let t = factor3(&node["translation"], [0.0, 0.0, 0.0]);
+    let r = factor4(&node["rotation"], [0.0, 0.0, 0.0, 1.0]);
+    let s = factor3(&node["scale"], [1.0, 1.0, 1.0]);
+    Ok(Matrix4::from_translation(Vector3::new(t[0], t[1], t[2])) *
+       Matrix4::from(Quaternion::new(r[3], r[0], r[1], r[2])) *
+       Matrix4::from_nonuniform_scale(s[0], s[1], s[2]))
+}
+
+fn visit(nodes: &[Value],
+         index: usize,
+         parent: Matrix4<f32>,
+         path: &mut Vec<usize>,
+         instances: &mut Vec<Instance>)
+         -> Result<(), GltfError> {
+    if path.contains(&index) {
+        return Err(invalid(format!("node {} is its own ancestor", index)));
+    }
+    let node = nodes.get(index).ok_or_else(|| invalid(format!("no node {}", index)))?;
+    let matrix = parent * node_matrix(node)?;
+    if let Some(mesh) = node["mesh"].as_usize() {
+        instances.push(Instance {
+            name: node["name"].as_str().unwrap_or("").to_string(),
+            mesh: mesh,
+            matrix: matrix,
+        });
+    }
+
+    path.push(index);
+    for child in node["children"].members() {
+        let child = child.as_usize().ok_or_else(|| invalid(format!("bad child of {}", index)))?;
+        visit(nodes, child, matrix, path, instances)?;
+    }
+    path.pop();
+    Ok(())
+}
+
+/// the nodes of the default scene, or of the first one
+fn scene_instances(doc: &Value) -> Result<Vec<Instance>, GltfError> {
+    let nodes = doc["nodes"].members();
+    let scene = match doc["scene"].as_usize() {
+        Some(scene) => Some(scene),
+        None if !doc["scenes"].members().is_empty() => Some(0),
+        None => None,
+    };
+    let roots: Vec<usize> = match scene {
+        Some(scene) => {
+            let nodes = doc["scenes"][scene]["nodes"].members();
+            nodes.iter().filter_map(|n| n.as_usize()).collect()
+        }
+        // no scenes, the nodes that are nobody's children
+        None => {
+            let children: HashSet<usize> = nodes.iter()
+                .flat_map(|n| n["children"].members().iter().filter_map(|c| c.as_usize()))
+                .collect();
+            (0..nodes.len()).filter(|i| !children.contains(i)).collect()
+        }
+    };
+
+    let mut instances = Vec::new();
+    let mut path = Vec::new();
+    for root in roots {
+        visit(nodes, root, Matrix4::identity(), &mut path, &mut instances)?;
+    }
+    Ok(instances)
+}
+
+fn parse_scene(doc: &Value, buffers: &[Vec<u8>]) -> Result<SceneData, GltfError> {
+    match doc["asset"]["version"].as_str() {
+        Some(version) if version.starts_with("2.") => {}
+        version => return Err(GltfError::Unsupported(format!("glTF version {:?}", version))),
+    }
+    if let Some(extension) = doc["extensionsRequired"].members().first() {
+        return Err(GltfError::Unsupported(format!("extension {:?}", extension)));
+    }
+
+    let mut meshes = Vec::new();
+    for (i, mesh) in doc["meshes"].members().iter().enumerate() {
+        let primitives = mesh["primitives"]
+            .members()
+            .iter()
+            .map(|p| primitive(doc, buffers, p))
+            .collect::<Result<Vec<PrimitiveData>, GltfError>>()?;
+        meshes.push(MeshData {
+            name: mesh["name"]
+                .as_str()
+                .map(|n| n.to_string())
+                .unwrap_or_else(|| format!("mesh{}", i)),
+            primitives: primitives,
+        });
+    }
+
+    let materials: Vec<PbrMaterial> = doc["materials"].members().iter().map(material).collect();
+    let bad_material = meshes.iter()
+        .flat_map(|m| m.primitives.iter())
+        .any(|p| p.material.map(|m| m >= materials.len()).unwrap_or(false));
+    if bad_material {
+        return Err(invalid("primitive with a missing material".to_string()));
+    }
+
+    let instances = scene_instances(doc)?;
+    if instances.iter().any(|i| i.mesh >= meshes.len()) {
+        return Err(invalid("node with a missing mesh".to_string()));
+    }
+
+    Ok(SceneData {
+        meshes: meshes,
+        materials: materials,
+        textures: doc["textures"]
+            .members()
+            .iter()
+            .map(|t| t["source"].as_usize())
+            .collect(),
+        instances: instances,
+    })
+}
+
+/// an image file, or a buffer view with the file in it
+fn image_bytes(doc: &Value,
+               buffers: &[Vec<u8>],
+               dir: &Path,
+               image: &Value)
+               -> Result<Vec<u8>, GltfError> {
+    match image["uri"].as_str() {
+        Some(uri) => read_uri(dir, uri),
+        None => {
+            let view = image["bufferView"]
+                .as_usize()
+                .ok_or_else(|| invalid("image without data".to_string()))?;
+            Ok(view_bytes(doc, buffers, view)?.to_vec())
+        }
+    }
+}
+
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+pub struct Primitive {
+    pub geometry: Handle<Geometry>,
+    pub material: Option<usize>,
+}
+
+#[allow(dead_code)]
+pub struct Mesh {
+    pub name: String,
+    pub primitives: Vec<Primitive>,
+    /// InstanceData, the matrices of the nodes using the mesh. none if no node does
+    pub instances: Option<Handle<Geometry>>,
+}
+
+/// a glTF file in the geometry and texture managers.
+/// each primitive is drawn once for all the instances of its mesh, as in
+///     surface.draw_geometry_instanced(primitive.geometry, instances, prg, uniforms)
+/// with the program reading the model matrix as a per instance attribute
+pub struct Scene {
+    meshes: Vec<Mesh>,
+    materials: Vec<PbrMaterial>,
+    textures: Vec<Option<Handle<Texture>>>,
+    instances: Vec<Instance>,
+}
+
+impl Scene {
+    /// .gltf or .glb, told apart by the content. buffers and images are next to the file
+    pub fn load(ctx: &Context, filename: &str) -> Result<Scene, GltfError> {
+        println!("load scene: {}", filename);
+        let data = fs::read(filename)?;
+        let (text, bin) = if data.starts_with(b"glTF") {
+            parse_glb(&data)?
+        } else {
+            let text = String::from_utf8(data)
+                .map_err(|_| invalid(format!("{} is not utf8", filename)))?;
+            (text, None)
+        };
+        let doc: Value = serde_json::from_str(&text)
+            .map_err(|err| GltfError::Json(err.to_string()))?;
+
+        let dir = Path::new(filename).parent().unwrap_or(Path::new(""));
+        let buffers = load_buffers(&doc, bin, dir)?;
+        let scene = parse_scene(&doc, &buffers)?;
+
+        let stem = Path::new(filename).file_stem().map(|s| s.to_string_lossy().into_owned());
+        let stem = stem.unwrap_or_else(|| filename.to_string());
+
+        // ~~~~~~~~~ images, shared by the textures using them ~~~~~~~~~
+
+        let mut images = Vec::new();
+        for (i, img) in doc["images"].members().iter().enumerate() {
+            let bytes = image_bytes(&doc, &buffers, dir, img)?;
+            let decoded = image::load_from_memory(&bytes)
+                .map_err(|err| GltfError::Image(format!("image {}: {}", i, err)))?;
+            let mut manager = ctx.textures_mut();
+            let name = manager.unique_name(&format!("{}/image{}", stem, i));
+            images.push(manager.create_texture_from_image(ctx, &name, decoded.to_rgba())?);
+        }
+        let textures = scene.textures
+            .iter()
+            .map(|source| source.and_then(|s| images.get(s).cloned()))
+            .collect();
+
+        // ~~~~~~~~~ meshes and the matrices of their instances ~~~~~~~~~
+
+        let mut manager = ctx.geometry_mut();
+        let mut meshes = Vec::new();
+        for (m, mesh) in scene.meshes.iter().enumerate() {
+            let mut primitives = Vec::new();
+            for (p, data) in mesh.primitives.iter().enumerate() {
+                let name = manager.unique_name(&format!("{}/{}/{}", stem, mesh.name, p));
+                let geometry = match data.indices {
+                    Some(ref indices) => {
+                        manager.create_geom_from_data_with_indices(ctx,
+                                                                   &name,
+                                                                   &data.vertices,
+                                                                   indices,
+                                                                   data.mode)?
+                    }
+                    None => manager.create_geom_from_data(ctx, &name, &data.vertices, data.mode)?,
+                };
+                primitives.push(Primitive {
+                    geometry: geometry,
+                    material: data.material,
+                });
+            }
+
+            let matrices: Vec<InstanceData> = scene.instances
+                .iter()
+                .filter(|i| i.mesh == m)
+                .map(|i| InstanceData { instance_model: i.matrix.into() })
+                .collect();
+            let instances = if matrices.is_empty() {
+                None
+            } else {
+                let name = manager.unique_name(&format!("{}/{} instances", stem, mesh.name));
+                // dynamic, the nodes may move
+                Some(manager.create_dynamic_geom(ctx,
+                                                 &name,
+                                                 &matrices,
+                                                 None,
+                                                 PrimitiveType::Points)?)
+            };
+
+            meshes.push(Mesh {
+                name: mesh.name.clone(),
+                primitives: primitives,
+                instances: instances,
+            });
+        }
+
+        Ok(Scene {
+            meshes: meshes,
+            materials: scene.materials,
+            textures: textures,
+            instances: scene.instances,
+        })
+    }
+
+    pub fn meshes(&self) -> &[Mesh] {
+        &self.meshes
+    }
+
+    /// the nodes with a mesh, in the order of the node tree
+    #[allow(dead_code)]
+    pub fn instances(&self) -> &[Instance] {
+        &self.instances
+    }
+
+    /// the default one for primitives without material
+    pub fn material(&self, primitive: &Primitive) -> PbrMaterial {
+        primitive.material
+            .and_then(|m| self.materials.get(m).cloned())
+            .unwrap_or_else(|| PbrMaterial::new(""))
+    }
+
+    /// the texture of a material index, none if its image could not be found
+    pub fn texture(&self, index: usize) -> Option<Handle<Texture>> {
+        self.textures.get(index).cloned().and_then(|t| t)
+    }
+}
+
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+//  Tests
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use cgmath::Point3;
+    use cgmath::Transform;
+
+    /// a triangle without normals, u16 indices padded to 4, then the positions
+    fn triangle_buffer() -> Vec<u8> {
+        let mut data = Vec::new();
+        for i in &[0u16, 1, 2, 0] {
+            data.push(*i as u8);
+            data.push((*i >> 8) as u8);
+        }
+        for f in &[0.0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0] {
+            let bits = f.to_bits();
+            for b in 0..4 {
+                data.push((bits >> (b * 8)) as u8);
+            }
+        }
+        data
+    }
+
+    fn parse(text: &str) -> Value {
+        serde_json::from_str(text).unwrap()
+    }
+
+    /// a parent moved up with two children, one of them scaled
+    fn document(buffer: &str) -> String {
+        format!(r#"{{
+            "asset": {{"version": "2.0"}},
+            "scene": 0,
+            "scenes": [{{"nodes": [0]}}],
+            "nodes": [
+                {{"name": "root", "translation": [0, 10, 0], "children": [1, 2]}},
+                {{"name": "small", "mesh": 0, "scale": [0.5, 0.5, 0.5]}},
+                {{"name": "turned", "mesh": 0, "rotation": [0, 0.7071068, 0, 0.7071068]}}
+            ],
+            "meshes": [{{"primitives": [{{"attributes": {{"POSITION": 1}},
+                                          "indices": 0, "material": 0}}]}}],
+            "materials": [{{"name": "gold",
+                            "pbrMetallicRoughness": {{"baseColorFactor": [1, 0.8, 0.2, 1],
+                                                     "metallicFactor": 0.9,
+                                                     "baseColorTexture": {{"index": 0}}}},
+                            "alphaMode": "MASK"}}],
+            "textures": [{{"source": 0}}],
+            "accessors": [
+                {{"bufferView": 0, "componentType": 5123, "count": 3, "type": "SCALAR"}},
+                {{"bufferView": 1, "componentType": 5126, "count": 3, "type": "VEC3"}}
+            ],
+            "bufferViews": [
+                {{"buffer": 0, "byteOffset": 0, "byteLength": 6}},
+                {{"buffer": 0, "byteOffset": 8, "byteLength": 36}}
+            ],
+            "buffers": [{{"byteLength": 44 {}}}]
+        }}"#,
+                buffer)
+    }
+
+    fn check(doc: &Value, buffers: &[Vec<u8>]) {
+        let scene = parse_scene(doc, buffers).unwrap();
+        assert_eq!(scene.meshes.len(), 1);
+        assert_eq!(scene.meshes[0].name, "mesh0");
+        let p = &scene.meshes[0].primitives[0];
+        assert_eq!(p.mode, PrimitiveType::TrianglesList);
+        assert_eq!(p.indices, Some(vec![0, 1, 2]));
+        // flat normal, the triangle faces z
+        assert!(p.vertices.iter().all(|v| v.normal == (0.0, 0.0, 1.0)));
+
+        let material = &scene.materials[0];
+        assert_eq!(material.base_color, [1.0, 0.8, 0.2, 1.0]);
+        assert_eq!(material.metallic, 0.9);
+        assert_eq!(material.roughness, 1.0);
+        assert_eq!(material.base_color_texture, Some(0));
+        assert_eq!(material.alpha_mode, AlphaMode::Mask(0.5));
+        assert_eq!(scene.textures, vec![Some(0)]);
+
+        assert_eq!(scene.instances.len(), 2);
+        let small = &scene.instances[0];
+        assert_eq!(small.name, "small");
+        let corner = small.matrix.transform_point(Point3::new(1.0, 0.0, 0.0));
+        assert_eq!(corner, Point3::new(0.5, 10.0, 0.0));
+        let turned = &scene.instances[1];
+        let corner = turned.matrix.transform_point(Point3::new(1.0, 0.0, 0.0));
+        assert!((corner - Point3::new(0.0, 10.0, -1.0)).magnitude() < 1e-5);
+    }
+
+    #[test]
+    fn gltf_data_uri() {
+        let uri = format!(r#", "uri": "data:application/octet-stream;base64,{}""#,
+                          base64::encode(&triangle_buffer()));
+        let doc = parse(&document(&uri));
+        let buffers = load_buffers(&doc, None, Path::new("")).unwrap();
+        assert_eq!(buffers, vec![triangle_buffer()]);
+        check(&doc, &buffers);
+    }
+
+    #[test]
+    fn glb() {
+        let mut json = document("").into_bytes();
+        while json.len() % 4 != 0 {
+            json.push(b' ');
+        }
+        let bin = triangle_buffer();
+        let mut glb = Vec::new();
+        let length = 12 + 8 + json.len() + 8 + bin.len();
+        for word in &[GLB_MAGIC, 2, length as u32, json.len() as u32, CHUNK_JSON] {
+            for b in 0..4 {
+                glb.push((word >> (b * 8)) as u8);
+            }
+        }
+        glb.extend_from_slice(&json);
+        for word in &[bin.len() as u32, CHUNK_BIN] {
+            for b in 0..4 {
+                glb.push((word >> (b * 8)) as u8);
+            }
+        }
+        glb.extend_from_slice(&bin);
+
+        let (text, bin) = parse_glb(&glb).unwrap();
+        let doc = parse(&text);
+        let buffers = load_buffers(&doc, bin, Path::new("")).unwrap();
+        check(&doc, &buffers);
+
+        assert!(parse_glb(&glb[..8]).is_err());
+    }
+
+    #[test]
+    fn errors() {
+        let doc = parse(r#"{"asset": {"version": "1.0"}}"#);
+        match parse_scene(&doc, &[]) {
+            Err(GltfError::Unsupported(_)) => {}
+            _ => panic!("version 1 accepted"),
+        }
+        // the buffer is missing
+        let doc = parse(&document(""));
+        assert!(load_buffers(&doc, None, Path::new("")).is_err());
+        match parse_scene(&doc, &[]) {
+            Err(GltfError::Invalid(_)) => {}
+            _ => panic!("accessor without buffer accepted"),
+        }
+        let uri = "data:application/octet-stream;base64,aGVsbG8=";
+        assert_eq!(read_uri(Path::new(""), uri).unwrap(), b"hello".to_vec());
+        assert!(read_uri(Path::new(""), "data:application/octet-stream;base64,a*").is_err());
+    }
+
+    /// counts and offsets near the limits of usize must not overflow or allocate
+    #[test]
+    fn huge_accessors() {
+        let max = ::std::usize::MAX;
+        let buffers = vec![vec![0u8; 64]];
+        let invalid = |text: String| {
+            let doc = parse(&text);
+            match accessor(&doc, &buffers, 0) {
+                Err(GltfError::Invalid(_)) => {}
+                other => panic!("{} accepted: {:?}", text, other.map(|(c, v)| (c, v.len()))),
+            }
+        };
+        let view = r#"{"buffer": 0, "byteLength": 64}"#;
+        let accessor_json = |acc: String, view: &str| {
+            format!(r#"{{"accessors": [{}], "bufferViews": [{}]}}"#, acc, view)
+        };
+
+        // no buffer view, the size comes from the count alone
+        invalid(accessor_json(format!(r#"{{"type": "MAT4", "componentType": 5126,
+                                           "count": {}}}"#,
+                                      max / 2),
+                              view));
+        invalid(accessor_json(r#"{"type": "VEC3", "componentType": 5126,
+                                  "count": 100000000}"#
+                                  .to_string(),
+                              view));
+        // stride times count, and the offsets
+        invalid(accessor_json(format!(r#"{{"type": "VEC3", "componentType": 5126,
+                                           "bufferView": 0, "count": {}}}"#,
+                                      max / 4),
+                              view));
+        invalid(accessor_json(format!(r#"{{"type": "SCALAR", "componentType": 5126,
+                                           "bufferView": 0, "count": 1, "byteOffset": {}}}"#,
+                                      max - 2),
+                              view));
+        invalid(accessor_json(r#"{"type": "SCALAR", "componentType": 5126,
+                                  "bufferView": 0, "count": 2}"#
+                                  .to_string(),
+                              &format!(r#"{{"buffer": 0, "byteOffset": 8,
+                                            "byteLength": {}}}"#,
+                                       max - 4)));
+        // a stride of zero would read the same element forever
+        invalid(accessor_json(format!(r#"{{"type": "SCALAR", "componentType": 5121,
+                                           "bufferView": 0, "count": {}}}"#,
+                                      max),
+                              r#"{"buffer": 0, "byteLength": 64, "byteStride": 0}"#));
+    }
+}
@@ -11,6 +11,8 @@ use std::io::{Error, ErrorKind};
 use self::regex::Regex;
 use self::glob::glob;
 
+use assets;
+use world::export;
 use rand;
 use rand::distributions::Range;
 use rand::distributions::IndependentSample;
@@ -24,24 +26,27 @@ use rand::distributions::IndependentSample;
 // return ONE texture to be used by the program and the paramenters needed
 // to use a shader on it
 pub fn load_rgb(filename: &str) -> image::RgbImage {
-    let path = fs::canonicalize(&filename).unwrap();
+    let path = assets::locate(filename);
     println!("load image: {:?}", path);
 
-    // iterate over images:
-    use std::fs;
-    let image = image::open(path).unwrap();
+    let data = assets::read(&path).unwrap();
+    let image = image::load_from_memory(&data).unwrap();
     image.to_rgb()
 }
 
 pub fn generate_noise(size: (u32, u32)) -> image::RgbImage {
+    generate_noise_with(size, &mut rand::thread_rng())
+}
+
+/// noise out of the given generator, seed it to get the same image every time
+pub fn generate_noise_with<R: rand::Rng>(size: (u32, u32), rng: &mut R) -> image::RgbImage {
 
     let (w, h) = size;
     let mut image = image::RgbImage::new(w, h);
 
-    let mut rng = rand::thread_rng();
     let between = Range::new(0u8, 255);
     for pix in image.iter_mut() {
-        let r = between.ind_sample(&mut rng);
+        let r = between.ind_sample(rng);
         *pix = r;
     }
 
@@ -75,6 +80,7 @@ pub fn load_images_rgba(path: &PathBuf) -> Vec<image::RgbaImage> {
 
 // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
 // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+#[allow(dead_code)]
 pub fn get_coords_height(height_map: &image::RgbImage, i: u32, j: u32) -> f32 {
     use image::Pixel;
     let pixel = height_map.get_pixel(i, j);
@@ -305,157 +311,15 @@ impl PartialEq for MeshPoint {
     }
 }
 
-// TODO: - mesh is not complete, what if step does not divide the side?
-//       - use indices, this can turn to be a pretty damm big mesh
+/// the triangles of the whole map, every step pixels and the edge.
+/// see world::export for the indexed mesh
 #[allow(dead_code)]
 pub fn to_mesh(step: u32, height_map: &image::RgbImage) -> Vec<MeshPoint> {
-    let mut list = Vec::new();
-
-    let (max_x, max_y) = height_map.dimensions();
-    let (max_i, max_j) = (max_x / step, max_y / step);
-
-    for i in 0..max_i - 1 {
-        for j in 0..max_j - 1 {
-
-            let a = (i * step, j * step);
-            let b = ((i + 1) * step, (j) * step);
-            let c = ((i) * step, (j + 1) * step);
-            let d = ((i + 1) * step, (j + 1) * step);
-
-            list.push(MeshPoint {
-                position: (a.0 as f32, get_coords_height(height_map, a.0, a.1) as f32, a.1 as f32),
-            });
-            list.push(MeshPoint {
-                position: (c.0 as f32, get_coords_height(height_map, c.0, c.1) as f32, c.1 as f32),
-            });
-            list.push(MeshPoint {
-                position: (b.0 as f32, get_coords_height(height_map, b.0, b.1) as f32, b.1 as f32),
-            });
-
-            list.push(MeshPoint {
-                position: (c.0 as f32, get_coords_height(height_map, c.0, c.1) as f32, c.1 as f32),
-            });
-            list.push(MeshPoint {
-                position: (d.0 as f32, get_coords_height(height_map, d.0, d.1) as f32, d.1 as f32),
-            });
-            list.push(MeshPoint {
-                position: (b.0 as f32, get_coords_height(height_map, b.0, b.1) as f32, b.1 as f32),
-            });
-
-            assert!(i * step < max_x);
-            assert!(j * step < max_y);
-
-            if i == 0 {
-                list.push(MeshPoint {
-                    position: (c.0 as f32,
-                               get_coords_height(height_map, c.0, c.1) as f32,
-                               c.1 as f32),
-                });
-                list.push(MeshPoint {
-                    position: (a.0 as f32,
-                               get_coords_height(height_map, a.0, a.1) as f32,
-                               a.1 as f32),
-                });
-                list.push(MeshPoint { position: (c.0 as f32, 0.0, c.1 as f32) });
-
-                list.push(MeshPoint {
-                    position: (a.0 as f32,
-                               get_coords_height(height_map, a.0, a.1) as f32,
-                               a.1 as f32),
-                });
-                list.push(MeshPoint { position: (a.0 as f32, 0.0, a.1 as f32) });
-                list.push(MeshPoint { position: (c.0 as f32, 0.0, c.1 as f32) });
-            }
-            if i == max_i - 2 {
-                list.push(MeshPoint { position: (b.0 as f32, 0.0, b.1 as f32) });
-                list.push(MeshPoint {
-                    position: (b.0 as f32,
-                               get_coords_height(height_map, b.0, b.1) as f32,
-                               b.1 as f32),
-                });
-                list.push(MeshPoint {
-                    position: (d.0 as f32,
-                               get_coords_height(height_map, d.0, d.1) as f32,
-                               d.1 as f32),
-                });
-
-                list.push(MeshPoint {
-                    position: (d.0 as f32,
-                               get_coords_height(height_map, d.0, d.1) as f32,
-                               d.1 as f32),
-                });
-                list.push(MeshPoint { position: (d.0 as f32, 0.0, d.1 as f32) });
-                list.push(MeshPoint { position: (b.0 as f32, 0.0, b.1 as f32) });
-            }
-            if j == 0 {
-                list.push(MeshPoint {
-                    position: (a.0 as f32,
-                               get_coords_height(height_map, a.0, a.1) as f32,
-                               a.1 as f32),
-                });
-                list.push(MeshPoint {
-                    position: (b.0 as f32,
-                               get_coords_height(height_map, b.0, b.1) as f32,
-                               b.1 as f32),
-                });
-                list.push(MeshPoint { position: (a.0 as f32, 0.0, a.1 as f32) });
-
-                list.push(MeshPoint {
-                    position: (b.0 as f32,
-                               get_coords_height(height_map, b.0, b.1) as f32,
-                               b.1 as f32),
-                });
-                list.push(MeshPoint { position: (b.0 as f32, 0.0, b.1 as f32) });
-                list.push(MeshPoint { position: (a.0 as f32, 0.0, a.1 as f32) });
-            }
-            if j == max_j - 2 {
-                list.push(MeshPoint {
-                    position: (c.0 as f32,
-                               get_coords_height(height_map, c.0, c.1) as f32,
-                               c.1 as f32),
-                });
-                list.push(MeshPoint { position: (c.0 as f32, 0.0, c.1 as f32) });
-                list.push(MeshPoint {
-                    position: (d.0 as f32,
-                               get_coords_height(height_map, d.0, d.1) as f32,
-                               d.1 as f32),
-                });
-
-                list.push(MeshPoint {
-                    position: (d.0 as f32,
-                               get_coords_height(height_map, d.0, d.1) as f32,
-                               d.1 as f32),
-                });
-                list.push(MeshPoint { position: (c.0 as f32, 0.0, c.1 as f32) });
-                list.push(MeshPoint { position: (d.0 as f32, 0.0, d.1 as f32) });
-            }
-        }
-    }
-
-
-    let a = (max_i as f32 - 1.0) * step as f32;
-    let b = (max_j as f32 - 1.0) * step as f32;
-    let d = -100.0;
-
-
-    // inverted piramid, only 4 triangles close the volume
-    list.push(MeshPoint { position: (0.0, 0.0, 0.0) });
-    list.push(MeshPoint { position: (a, 0.0, 0.0) });
-    list.push(MeshPoint { position: (a / 2.0, d, b / 2.0) }); // check
-
-    list.push(MeshPoint { position: (0.0, 0.0, 0.0) });
-    list.push(MeshPoint { position: (a / 2.0, d, b / 2.0) });
-    list.push(MeshPoint { position: (0.0, 0.0, b) });
-
-    list.push(MeshPoint { position: (0.0, 0.0, b) });
-    list.push(MeshPoint { position: (a / 2.0, d, b / 2.0) });
-    list.push(MeshPoint { position: (a, 0.0, b) });
-
-    list.push(MeshPoint { position: (a, 0.0, b) });
-    list.push(MeshPoint { position: (a / 2.0, d, b / 2.0) });
-    list.push(MeshPoint { position: (a, 0.0, 0.0) });
-
-    list
+    let mesh = export::surface(height_map, export::Region::whole(height_map), step);
+    mesh.indices
+        .iter()
+        .map(|&i| MeshPoint { position: mesh.vertices[i as usize].position })
+        .collect()
 }
 
 // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
@@ -471,6 +335,11 @@ mod tests {
     #[test]
     fn get_mesh() {
         let map = load_rgb("assets/pico.png");
-        let _ = to_mesh(10, &map);
+        let mesh = to_mesh(10, &map);
+        assert_eq!(mesh.len() % 3, 0);
+        // the edge is there, even when 10 does not divide the side
+        let (w, h) = map.dimensions();
+        let far = ((w - 1) as f32, (h - 1) as f32);
+        assert!(mesh.iter().any(|p| (p.position.0, p.position.2) == far));
     }
 }
@@ -1,6 +1,9 @@
 
 
 
+pub mod gltf;
+pub mod export;
 pub mod image_atlas;
+pub mod model;
 // pub mod cube;
 pub mod terrain;
@@ -0,0 +1,484 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use cgmath::{InnerSpace, Vector3};
+use glium::index::PrimitiveType;
+
+use assets::{self, AssetError};
+use renderer::context::{Context, ManagerError};
+use renderer::handle::{Geometry, Handle};
+use renderer::{index_vertex_list, VertexKey};
+
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+//    Wavefront models,
+//    obj files with their mtl materials. faces with more than three
+//    corners are split in a fan, so they should be convex.
+//    faces without normals get them from the smoothing group: averaged
+//    over the faces of the group sharing the position, flat with "s off".
+//    there is a mesh per material, indexed, registered in the geometry
+//    manager of the context.
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ModelVertex {
+    pub position: (f32, f32, f32),
+    pub normal: (f32, f32, f32),
+    pub tex_coord: (f32, f32),
+}
+implement_vertex!(ModelVertex, position, normal, tex_coord);
+
+impl VertexKey for ModelVertex {
+    type Key = [u32; 8];
+    fn key(&self) -> [u32; 8] {
+        [self.position.0.to_bits(),
+         self.position.1.to_bits(),
+         self.position.2.to_bits(),
+         self.normal.0.to_bits(),
+         self.normal.1.to_bits(),
+         self.normal.2.to_bits(),
+         self.tex_coord.0.to_bits(),
+         self.tex_coord.1.to_bits()]
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Material {
+    pub name: String,
+    pub ambient: [f32; 3],
+    pub diffuse: [f32; 3],
+    pub specular: [f32; 3],
+    pub shininess: f32,
+    pub alpha: f32,
+    /// path relative to the mtl file
+    pub diffuse_map: Option<String>,
+}
+
+impl Material {
+    /// what faces without usemtl get
+    pub fn new(name: &str) -> Material {
+        Material {
+            name: name.to_string(),
+            ambient: [0.0, 0.0, 0.0],
+            diffuse: [0.8, 0.8, 0.8],
+            specular: [0.0, 0.0, 0.0],
+            shininess: 0.0,
+            alpha: 1.0,
+            diffuse_map: None,
+        }
+    }
+}
+
+/// the faces of a material
+#[derive(Debug)]
+pub struct Mesh {
+    pub material: String,
+    pub vertices: Vec<ModelVertex>,
+    pub indices: Vec<u32>,
+}
+
+#[derive(Debug)]
+pub struct ObjData {
+    pub meshes: Vec<Mesh>,
+    /// mtllib files, as written in the obj
+    pub material_libs: Vec<String>,
+}
+
+#[derive(Debug)]
+pub enum ObjError {
+    Asset(AssetError),
+    Parse { line: usize, message: String },
+    Manager(ManagerError),
+}
+
+impl From<AssetError> for ObjError {
+    fn from(err: AssetError) -> ObjError {
+        ObjError::Asset(err)
+    }
+}
+
+impl From<ManagerError> for ObjError {
+    fn from(err: ManagerError) -> ObjError {
+        ObjError::Manager(err)
+    }
+}
+
+fn parse_error(line: usize, message: String) -> ObjError {
+    ObjError::Parse {
+        line: line,
+        message: message,
+    }
+}
+
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+fn parse_floats(line: usize, words: &[&str], count: usize) -> Result<Vec<f32>, ObjError> {
+    if words.len() < count {
+        return Err(parse_error(line, format!("expected {} numbers", count)));
+    }
+    words[..count]
+        .iter()
+        .map(|w| w.parse().map_err(|_| parse_error(line, format!("{} is not a number", w))))
+        .collect()
+}
+
+/// obj indices start at 1, negative ones count back from the last element
+fn parse_index(line: usize, word: &str, count: usize) -> Result<usize, ObjError> {
+    let index: i64 = word.parse()
+        .map_err(|_| parse_error(line, format!("{} is not an index", word)))?;
+    let resolved = if index < 0 {
+        count as i64 + index
+    } else {
+        index - 1
+    };
+    if resolved < 0 || resolved >= count as i64 {
+        return Err(parse_error(line, format!("index {} out of range", word)));
+    }
+    Ok(resolved as usize)
+}
+
+#[derive(Copy, Clone)]
+struct Corner {
+    position: usize,
+    tex_coord: Option<usize>,
+    normal: Option<usize>,
+}
+
+struct Triangle {
+    corners: [Corner; 3],
+    /// 0 is off
+    smoothing: u32,
+}
+
+/// v, v/vt, v//vn or v/vt/vn
+fn parse_corner(line: usize,
+                word: &str,
+                counts: (usize, usize, usize))
+                -> Result<Corner, ObjError> {
+    let mut parts = word.split('/');
+    let position = parse_index(line, parts.next().unwrap_or(""), counts.0)?;
+    let tex_coord = match parts.next() {
+        Some("") | None => None,
+        Some(w) => Some(parse_index(line, w, counts.1)?),
+    };
+    let normal = match parts.next() {
+        Some("") | None => None,
+        Some(w) => Some(parse_index(line, w, counts.2)?),
+    };
+    Ok(Corner {
+        position: position,
+        tex_coord: tex_coord,
+        normal: normal,
+    })
+}
+
+fn face_normal(positions: &[Vector3<f32>], t: &Triangle) -> Vector3<f32> {
+    let a = positions[t.corners[0].position];
+    let b = positions[t.corners[1].position];
+    let c = positions[t.corners[2].position];
+    // not normalized, bigger faces weight more in the smoothing
+    (b - a).cross(c - a)
+}
+
+fn normalized(v: Vector3<f32>) -> Vector3<f32> {
+    if v.magnitude2() > 0.0 {
+        v.normalize()
+    } else {
+        v
+    }
+}
+
+/// the triangles of a material into an indexed mesh
+fn build_mesh(material: String,
+              triangles: &[Triangle],
+              positions: &[Vector3<f32>],
+              tex_coords: &[(f32, f32)],
+              normals: &[Vector3<f32>])
+              -> Mesh {
+
+    // sum of the face normals around each position, per smoothing group
+    let mut smooth: HashMap<(usize, u32), Vector3<f32>> = HashMap::new();
+    for t in triangles.iter().filter(|t| t.smoothing != 0) {
+        let n = face_normal(positions, t);
+        for c in &t.corners {
+            *smooth.entry((c.position, t.smoothing)).or_insert(Vector3::new(0.0, 0.0, 0.0)) += n;
+        }
+    }
+
+    let mut flat = Vec::with_capacity(triangles.len() * 3);
+    for t in triangles {
+        for c in &t.corners {
+            let normal = match c.normal {
+                Some(n) => normals[n],
+                None if t.smoothing != 0 => normalized(smooth[&(c.position, t.smoothing)]),
+                None => normalized(face_normal(positions, t)),
+            };
+            let p = positions[c.position];
+            flat.push(ModelVertex {
+                position: (p.x, p.y, p.z),
+                normal: (normal.x, normal.y, normal.z),
+                tex_coord: c.tex_coord.map(|i| tex_coords[i]).unwrap_or((0.0, 0.0)),
+            });
+        }
+    }
+
+    let (vertices, indices) = index_vertex_list(&flat);
+    Mesh {
+        material: material,
+        vertices: vertices,
+        indices: indices,
+    }
+}
+
+/// meshes in the order the materials are first used
+pub fn parse_obj(source: &str) -> Result<ObjData, ObjError> {
+    let mut positions = Vec::new();
+    let mut tex_coords = Vec::new();
+    let mut normals = Vec::new();
+    let mut material_libs = Vec::new();
+
+    let mut groups: Vec<(String, Vec<Triangle>)> = Vec::new();
+    let mut material = String::new();
+    let mut smoothing = 0;
+
+    for (number, line) in source.lines().enumerate() {
+        let number = number + 1;
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if words.is_empty() {
+            continue;
+        }
+        let args = &words[1..];
+        match words[0] {
+            "v" => {
+                let v = parse_floats(number, args, 3)?;
+                positions.push(Vector3::new(v[0], v[1], v[2]));
+            }
+            "vt" => {
+                let v = parse_floats(number, args, 2)?;
+                tex_coords.push((v[0], v[1]));
+            }
+            "vn" => {
+                let v = parse_floats(number, args, 3)?;
+                normals.push(normalized(Vector3::new(v[0], v[1], v[2])));
+            }
+            "f" => {
+                if args.len() < 3 {
+                    return Err(parse_error(number, "a face needs three corners".to_string()));
+                }
+                let counts = (positions.len(), tex_coords.len(), normals.len());
+                let corners = args.iter()
+                    .map(|w| parse_corner(number, w, counts))
+                    .collect::<Result<Vec<Corner>, ObjError>>()?;
+
+                let group = match groups.iter().position(|g| g.0 == material) {
+                    Some(i) => i,
+                    None => {
+                        groups.push((material.clone(), Vec::new()));
+                        groups.len() - 1
+                    }
+                };
+                let triangles = &mut groups[group].1;
+                for i in 1..corners.len() - 1 {
+                    triangles.push(Triangle {
+                        corners: [corners[0], corners[i], corners[i + 1]],
+                        smoothing: smoothing,
+                    });
+                }
+            }
+            "s" => {
+                smoothing = match args.first() {
+                    Some(&"off") | None => 0,
+                    Some(w) => {
+                        w.parse()
+                            .map_err(|_| parse_error(number, format!("bad smoothing group {}", w)))?
+                    }
+                };
+            }
+            "usemtl" => material = args.join(" "),
+            "mtllib" => material_libs.extend(args.iter().map(|w| w.to_string())),
+            // objects and groups are not kept apart, lines, points and the rest are ignored
+            _ => {}
+        }
+    }
+
+    let meshes = groups.into_iter()
+        .map(|(material, triangles)| {
+            build_mesh(material, &triangles, &positions, &tex_coords, &normals)
+        })
+        .collect();
+    Ok(ObjData {
+        meshes: meshes,
+        material_libs: material_libs,
+    })
+}
+
+/// the materials by name
+pub fn parse_mtl(source: &str) -> Result<HashMap<String, Material>, ObjError> {
+    let mut materials = HashMap::new();
+    let mut current: Option<Material> = None;
+
+    for (number, line) in source.lines().enumerate() {
+        let number = number + 1;
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if words.is_empty() || words[0].starts_with('#') {
+            continue;
+        }
+        let args = &words[1..];
+        if words[0] == "newmtl" {
+            if let Some(done) = current.take() {
+                materials.insert(done.name.clone(), done);
+            }
+            current = Some(Material::new(&args.join(" ")));
+            continue;
+        }
+
+        let material = match current.as_mut() {
+            Some(material) => material,
+            None => return Err(parse_error(number, format!("{} before newmtl", words[0]))),
+        };
+        let color = |args: &[&str]| -> Result<[f32; 3], ObjError> {
+            let v = parse_floats(number, args, 3)?;
+            Ok([v[0], v[1], v[2]])
+        };
+        match words[0] {
+            "Ka" => material.ambient = color(args)?,
+            "Kd" => material.diffuse = color(args)?,
+            "Ks" => material.specular = color(args)?,
+            "Ns" => material.shininess = parse_floats(number, args, 1)?[0],
+            "d" => material.alpha = parse_floats(number, args, 1)?[0],
+            "Tr" => material.alpha = 1.0 - parse_floats(number, args, 1)?[0],
+            "map_Kd" => material.diffuse_map = args.last().map(|w| w.to_string()),
+            _ => {}
+        }
+    }
+    if let Some(done) = current {
+        materials.insert(done.name.clone(), done);
+    }
+    Ok(materials)
+}
+
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+pub struct Part {
+    pub geometry: Handle<Geometry>,
+    pub material: Material,
+}
+
+/// an obj file in the geometry manager
+pub struct Model {
+    parts: Vec<Part>,
+}
+
+impl Model {
+    /// the mtllib files are next to the obj
+    pub fn load(ctx: &Context, filename: &str) -> Result<Model, ObjError> {
+        let path = assets::locate(filename);
+        println!("load model: {:?}", path);
+        let data = parse_obj(&assets::read_string(&path)?)?;
+
+        let dir = Path::new(filename).parent().unwrap_or(Path::new(""));
+        let mut materials = HashMap::new();
+        for lib in &data.material_libs {
+            let source = assets::read_string(&assets::locate(&dir.join(lib).to_string_lossy()))?;
+            materials.extend(parse_mtl(&source)?);
+        }
+
+        let stem = Path::new(filename).file_stem().map(|s| s.to_string_lossy().into_owned());
+        let stem = stem.unwrap_or_else(|| filename.to_string());
+        let mut manager = ctx.geometry_mut();
+        let mut parts = Vec::new();
+        for mesh in &data.meshes {
+            let name = manager.unique_name(&format!("{}/{}", stem, mesh.material));
+            let geometry = manager.create_geom_from_data_with_indices(ctx,
+                                                 &name,
+                                                 &mesh.vertices,
+                                                 &mesh.indices,
+                                                 PrimitiveType::TrianglesList)?;
+            let material = materials.get(&mesh.material)
+                .cloned()
+                .unwrap_or_else(|| Material::new(&mesh.material));
+            parts.push(Part {
+                geometry: geometry,
+                material: material,
+            });
+        }
+        Ok(Model { parts: parts })
+    }
+
+    /// one per material
+    pub fn parts(&self) -> &[Part] {
+        &self.parts
+    }
+}
+
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+//  Tests
+// ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use assets;
+
+    #[test]
+    fn quad_fan() {
+        let data = parse_obj("v 0 0 0\nv 1 0 0\nv 1 0 1\nv 0 0 1\nv -0.5 0 0.5\n\
+                              f 1 2 3 4 -1\n")
+            .unwrap();
+        assert_eq!(data.meshes.len(), 1);
+        let mesh = &data.meshes[0];
+        assert_eq!(mesh.material, "");
+        assert_eq!(mesh.indices, vec![0, 1, 2, 0, 2, 3, 0, 3, 4]);
+        assert_eq!(mesh.vertices.len(), 5);
+        // flat, facing down for this winding
+        for v in &mesh.vertices {
+            assert_eq!(v.normal, (0.0, -1.0, 0.0));
+        }
+    }
+
+    #[test]
+    fn smoothing_groups() {
+        // two faces folded along the z axis
+        let obj = "v 0 0 0\nv 0 0 1\nv 1 1 0\nv -1 1 0\n{}\nf 1 2 3\nf 2 1 4\n";
+        let flat = parse_obj(&obj.replace("{}", "s off")).unwrap();
+        assert_eq!(flat.meshes[0].vertices.len(), 6);
+
+        let smooth = parse_obj(&obj.replace("{}", "s 1")).unwrap();
+        let mesh = &smooth.meshes[0];
+        assert_eq!(mesh.vertices.len(), 4);
+        assert_eq!(mesh.indices, vec![0, 1, 2, 1, 0, 3]);
+        // the shared edge points up, between the faces
+        let n = mesh.vertices[0].normal;
+        assert!(n.0.abs() < 1e-6 && (n.1 - 1.0).abs() < 1e-6 && n.2.abs() < 1e-6);
+    }
+
+    #[test]
+    fn errors() {
+        match parse_obj("v 0 0 0\nv 1 0 0\nf 1 2 3\n") {
+            Err(ObjError::Parse { line: 3, .. }) => {}
+            other => panic!("bad index accepted: {:?}", other),
+        }
+        assert!(parse_obj("v 0 zero 0\n").is_err());
+        assert!(parse_mtl("Kd 1 1 1\n").is_err());
+    }
+
+    #[test]
+    fn quarf() {
+        let obj = assets::read_string(&assets::locate("assets/quarf.obj")).unwrap();
+        let data = parse_obj(&obj).unwrap();
+        assert_eq!(data.material_libs, vec!["quarf.mtl".to_string()]);
+        assert_eq!(data.meshes.len(), 1);
+        let mesh = &data.meshes[0];
+        assert_eq!(mesh.material, "Material");
+        // four boxes with flat faces, four corners each
+        assert_eq!(mesh.vertices.len(), 4 * 6 * 4);
+        assert_eq!(mesh.indices.len(), 4 * 6 * 2 * 3);
+
+        let mtl = assets::read_string(&assets::locate("assets/quarf.mtl")).unwrap();
+        let materials = parse_mtl(&mtl).unwrap();
+        let material = &materials["Material"];
+        assert_eq!(material.diffuse, [0.64, 0.64, 0.64]);
+        assert_eq!(material.alpha, 1.0);
+    }
+}
@@ -1,15 +1,17 @@
-use glium;
+use glium::index::PrimitiveType;
 use rand;
 use renderer::context::*;
+use renderer::handle::{Geometry, Handle};
 
 // ~~~~~~~~~~
 
 
 /// The idea here is to create a tessellation terrain,
+/// the patch and the tiles live in the geometry manager of the context
 pub struct Terrain {
-    vertices: VerticesT,
-    tiles: VerticesT,
-    indices: IndicesT,
+    geometry: Handle<Geometry>,
+    tiles: Handle<Geometry>,
+    extent: (u32, u32),
 }
 
 
@@ -18,8 +20,11 @@ impl Terrain {
     /// it will be tiled in 64x64 sized tiles (which is the maximun tessellation we can get with
     /// resolution 1 to 1)
     pub fn new(ctx: &Context, width: u32, height: u32) -> Terrain {
-        use rand::Rng;
+        Terrain::with_rng(ctx, width, height, &mut rand::thread_rng())
+    }
 
+    /// same as new, tile detail comes out of the given generator
+    pub fn with_rng<R: rand::Rng>(ctx: &Context, width: u32, height: u32, rng: &mut R) -> Terrain {
         // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
 
         #[derive(Copy, Clone)]
@@ -28,11 +33,10 @@ impl Terrain {
         }
         implement_vertex!(Vertex, position);
 
-        let vertices_buff = glium::VertexBuffer::new(ctx.display(),
-                                                     &[Vertex { position: (0, 0) },
-                                                       Vertex { position: (64, 0) },
-                                                       Vertex { position: (0, 64) },
-                                                       Vertex { position: (64, 64) }]);
+        let vertices = [Vertex { position: (0, 0) },
+                        Vertex { position: (64, 0) },
+                        Vertex { position: (0, 64) },
+                        Vertex { position: (64, 64) }];
 
         // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
 
@@ -42,43 +46,56 @@ impl Terrain {
         }
         implement_vertex!(Tile, tile_offset);
 
-        let mut rng = rand::thread_rng();
+        // a map of a single tile still gets drawn
+        let tiles_x = ((width / 64).saturating_sub(1)).max(1);
+        let tiles_z = ((height / 64).saturating_sub(1)).max(1);
 
         let mut data: Vec<Tile> = Vec::new();
-        for i in 0..(width / 64) - 1 {
-            for j in 0..(height / 64) - 1 {
+        for i in 0..tiles_x {
+            for j in 0..tiles_z {
                 let detail = rng.gen_range(0, 7);
                 data.push(Tile { tile_offset: (i, j, detail) });
             }
         }
 
-        let tiles = glium::vertex::VertexBuffer::dynamic(ctx.display(), &data);
-
         // ~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~~
 
-        let indices =
-            glium::IndexBuffer::new(ctx.display(),
-                                    glium::index::PrimitiveType::Patches { vertices_per_patch: 4 },
-                                    &[0u16, 2, 3, 1]);
+        let mut manager = ctx.geometry_mut();
+        let name = manager.unique_name("terrain");
+        let patch = PrimitiveType::Patches { vertices_per_patch: 4 };
+        let geometry = manager.create_geom_from_data_with_indices(ctx,
+                                                                  &name,
+                                                                  &vertices,
+                                                                  &[0, 2, 3, 1],
+                                                                  patch)
+            .unwrap();
+        let tiles = manager.create_dynamic_geom(ctx,
+                                                &format!("{} tiles", name),
+                                                &data,
+                                                None,
+                                                PrimitiveType::Points)
+            .unwrap();
 
         Terrain {
-            vertices: vertices_buff.unwrap().into(),
-            tiles: tiles.unwrap().into(),
-            indices: indices.unwrap().into(),
+            geometry: geometry,
+            tiles: tiles,
+            extent: (tiles_x * 64, tiles_z * 64),
         }
     }
 
-    pub fn get_tiles(&self) -> &VerticesT {
-        &self.tiles
+    /// the patch, drawn once per tile
+    pub fn get_geometry(&self) -> Handle<Geometry> {
+        self.geometry
     }
-}
 
-
-impl DrawIndexed for Terrain {
-    fn get_vertices(&self) -> &VerticesT {
-        &self.vertices
+    pub fn get_tiles(&self) -> Handle<Geometry> {
+        self.tiles
     }
-    fn get_indices(&self) -> &IndicesT {
-        &self.indices
+
+    /// area covered by the tiles, in height map texels. tiles are whole,
+    /// the border of the map which does not fill one is not drawn
+    #[allow(dead_code)]
+    pub fn get_extent(&self) -> (u32, u32) {
+        self.extent
     }
 }